@@ -27,6 +27,17 @@ const vec3 light_pos   = vec3(3.0);
 const vec3 light_color = vec3(100.0);
 
 
+// Values for DEBUG_VIEW, e.g. `DEBUG_VIEW=DEBUG_VIEW_NORMALS`.
+#define DEBUG_VIEW_SHADED       0
+#define DEBUG_VIEW_NORMALS      1
+#define DEBUG_VIEW_VIEW_NORMALS 2
+#define DEBUG_VIEW_ALBEDO       3
+
+#ifndef DEBUG_VIEW
+#define DEBUG_VIEW DEBUG_VIEW_VIEW_NORMALS
+#endif
+
+
 
 // code adapted from "Normal Mapping Without Precomputed Tangents",
 // http://www.thetenthplanet.de/archives/1180
@@ -61,26 +72,35 @@ mat3 cotangent_frame(vec3 N, vec3 p, vec2 uv)
 void main()
 {
     vec4 tex_color = texture(sampler2D(u_Texture, u_Sampler), v_Texcoord);
+
+#ifdef NORMAL_MAP
     vec3 normal    = texture(sampler2D(u_Normals, u_NormSampler), v_Texcoord).xyz;
     normal.xy = normal.xy * 2.0 - 1.0;
     normal.y *= -1;
 
     mat3 perturb = cotangent_frame(normalize(v_Normal), camera.pos - v_Position, v_Texcoord);
-    mat3 view_perturb = cotangent_frame(normalize(v_Normal), v_Position, v_Texcoord);
 
     normal = perturb * normal;
+#else
+    vec3 normal = normalize(v_Normal);
+#endif
 
     vec3 normal_color = normal*0.5+0.5;
 
-    vec3 view_normal = view_perturb * normal;
-
-    view_normal = vec4(inverse(transpose(camera.view)) * vec4(normal, 0.0)).xyz;
+    vec3 view_normal = vec4(inverse(transpose(camera.view)) * vec4(normal, 0.0)).xyz;
     view_normal.z *= -1;
 
     vec3 view_normal_color = view_normal*0.5+0.5;
 
     float angle_factor = pow(max(0.0, dot(normal, vec3(0.0, 0.0, 1.0))), 5.0);
 
+#if DEBUG_VIEW == DEBUG_VIEW_NORMALS
+    f_Color = vec4(normal_color, 1.0);
+#elif DEBUG_VIEW == DEBUG_VIEW_VIEW_NORMALS
     f_Color = vec4(view_normal_color, 1.0);
-    // f_Color = vec4(view_normal_color * mix(vec3(0.1), vec3(5.0), angle_factor), 1.0);
+#elif DEBUG_VIEW == DEBUG_VIEW_ALBEDO
+    f_Color = tex_color;
+#else
+    f_Color = vec4(view_normal_color * mix(vec3(0.1), vec3(5.0), angle_factor), 1.0);
+#endif
 }
\ No newline at end of file
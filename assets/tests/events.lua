@@ -0,0 +1,59 @@
+
+test("subscriptions get distinct ids", function()
+    local a = events.subscribe("turn_start", function() end)
+    local b = events.subscribe("turn_start", function() end, { priority = 5 })
+    assert(a ~= b, "ids should differ")
+end)
+
+test("unsubscribing twice is harmless", function()
+    local id = events.subscribe("turn_start", function() end)
+    assert_eq(events.unsubscribe(id), true)
+    assert_eq(events.unsubscribe(id), false, "second unsubscribe")
+end)
+
+test("filters must be tables or functions", function()
+    local ok = pcall(events.subscribe, "turn_start", function() end, { filter = 3 })
+    assert_eq(ok, false)
+end)
+
+local function fixture()
+    return world.setup {
+        cards = { Goblin = {} },
+        players = { { hand = { "Goblin" } }, { board = { "Goblin" } } },
+    }
+end
+
+test("each handler gets its own copy of the event", function()
+    local seen = {}
+    events.subscribe("card_played", function(e)
+        e.card = "Dragon"
+        e.extra = true
+    end, { priority = 1 })
+    events.subscribe("card_played", function(e) seen = e end)
+
+    fixture():play(1, "Goblin")
+    assert_eq(seen.card, "Goblin")
+    assert_eq(seen.player, 1)
+    assert_eq(seen.extra, nil)
+end)
+
+test("units dying are announced", function()
+    local died
+    events.subscribe("unit_died", function(e) died = e end)
+
+    local w = fixture()
+    w:move(w:find(2, "board", "Goblin"), 2, "graveyard")
+    assert_eq(died.player, 2)
+    assert_eq(died.card, "Goblin")
+
+    died = nil
+    w:move(w:find(1, "hand", "Goblin"), 1, "graveyard")
+    assert_eq(died, nil, "discarding isn't dying")
+end)
+
+test("failing handlers fail the action", function()
+    local id = events.subscribe("card_played", function() error("nope") end)
+    local w = fixture()
+    assert_eq(pcall(w.play, w, 1, "Goblin"), false)
+    events.unsubscribe(id)
+end)
@@ -0,0 +1,40 @@
+
+test("no access to the host system", function()
+    assert_eq(io, nil)
+    assert_eq(os, nil)
+    assert_eq(dofile, nil)
+    assert_eq(loadfile, nil)
+end)
+
+test("load refuses bytecode", function()
+    local f, err = load(string.dump(function() end))
+    assert_eq(f, nil)
+    assert(err, "expected an error message")
+end)
+
+test("runaway loops are stopped", function()
+    local ok = pcall(function() while true do end end)
+    assert_eq(ok, false)
+end)
+
+test("load sees the globals", function()
+    local f = assert(load("return assert_eq"))
+    assert_eq(f(), assert_eq)
+end)
+
+test("load passes its environment along", function()
+    local f = assert(load("return x", "chunk", "t", { x = 3 }))
+    assert_eq(f(), 3)
+
+    -- An explicit nil environment has no globals at all, so even reading one fails.
+    local g = assert(load("return assert_eq", "chunk", "t", nil))
+    assert_eq(pcall(g), false, "an explicit nil environment")
+end)
+
+test("finalizers are refused", function()
+    local ok = pcall(setmetatable, {}, { __gc = function() while true do end end })
+    assert_eq(ok, false)
+
+    local t = setmetatable({}, { __index = { x = 1 } })
+    assert_eq(t.x, 1)
+end)
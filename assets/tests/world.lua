@@ -0,0 +1,69 @@
+local function raider()
+    return {
+        on_play = function(w, player, card)
+            w:pay(player, "mana", 2)
+            local picked = effects.choose {
+                prompt = "Discard a card",
+                options = w:player(2).hand,
+            }
+            w:move(picked[1], 2, "graveyard")
+        end,
+    }
+end
+
+local function fixture()
+    return world.setup {
+        cards = { Raider = raider(), Scout = { tap = function(w, player) w:draw(player) end } },
+        players = {
+            { deck = { "Forest" }, hand = { "Raider" }, board = { "Scout" }, resources = { mana = 3 } },
+            { hand = { "Forest", "Wall" } },
+        },
+    }
+end
+
+test("setup lays out every zone", function()
+    local w = fixture()
+    assert_eq(w:count(1, "deck"), 1)
+    assert_eq(w:count(1, "hand", "Raider"), 1)
+    assert_eq(w:count(1, "board", "Scout"), 1)
+    assert_eq(w:count(2, "hand"), 2)
+    assert_eq(w:player(1).resources.mana, 3)
+    assert_eq(w:find(2, "hand", "Wall").owner, 2)
+end)
+
+test("abilities wait on choices", function()
+    local w = fixture()
+    w:play(1, "Raider")
+
+    assert_eq(w:count(1, "board", "Raider"), 1)
+    assert_eq(w:player(1).resources.mana, 1)
+    assert_eq(w:choice().prompt, "Discard a card")
+
+    w:answer { 2 }
+    assert_eq(w:choice(), nil)
+    assert_eq(w:count(2, "graveyard", "Wall"), 1)
+    assert_eq(w:count(2, "hand", "Forest"), 1)
+end)
+
+test("bad answers are refused", function()
+    local w = fixture()
+    w:play(1, "Raider")
+    assert_eq(pcall(w.answer, w, {}), false, "too few")
+    assert_eq(pcall(w.answer, w, { 3 }), false, "out of range")
+    assert_eq(pcall(w.answer, w, { 1, 2 }), false, "too many")
+    assert(w:choice(), "still waiting after bad answers")
+end)
+
+test("activated abilities run from the board", function()
+    local w = fixture()
+    w:activate(1, "Scout", "tap")
+    assert_eq(w:count(1, "hand", "Forest"), 1)
+    assert_eq(pcall(w.activate, w, 1, "Scout", "tap"), false, "the deck is empty")
+end)
+
+test("abilities which fail leave nothing waiting", function()
+    local w = fixture()
+    w:player(1).resources.mana = 1
+    assert_eq(pcall(w.play, w, 1, "Raider"), false)
+    assert_eq(w:choice(), nil)
+end)
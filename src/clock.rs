@@ -0,0 +1,374 @@
+
+use std::time::Duration;
+
+
+/// The clock only moves in whole ticks, so that a match replayed tick for
+/// tick times out exactly where it did the first time, however the frames
+/// that drove it happened to fall.
+pub const TICK: Duration = Duration::from_millis(100);
+
+
+/// What happens to a player whose clock runs out.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimeoutPolicy {
+    AutoPass,
+    AutoConcede,
+}
+
+/// The result of a player running out of time, as reported by `ChessClock::tick`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timeout {
+    Pass(usize),
+    Concede(usize),
+    /// The player let a reaction window close without responding.
+    Reaction(usize),
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct TimeControl {
+    /// Free time each turn, spent before touching the reserve bank.
+    pub per_turn: Duration,
+    /// Time each player starts the match with.
+    pub reserve: Duration,
+    /// Added to a player's bank at the end of each of their turns.
+    pub increment: Duration,
+    /// How long a player gets to respond in a reaction window. Comes out
+    /// of nobody's bank, and the turn's clock stops meanwhile.
+    pub reaction: Duration,
+    pub on_timeout: TimeoutPolicy,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        Self {
+            per_turn: Duration::from_secs(30),
+            reserve: Duration::from_secs(10 * 60),
+            increment: Duration::from_secs(5),
+            reaction: Duration::from_secs(10),
+            on_timeout: TimeoutPolicy::AutoPass,
+        }
+    }
+}
+
+
+/// Per-player time banks, only one of which runs at a time.
+pub struct ChessClock {
+    control: TimeControl,
+    banks: Vec<Duration>,
+    turn_left: Duration,
+    active: usize,
+    /// Who is responding in an open reaction window, and their time left.
+    reaction: Option<(usize, Duration)>,
+    paused: bool,
+    flagged: bool,
+    /// Players out of the match, who have no use for an increment.
+    retired: Vec<bool>,
+}
+
+
+impl ChessClock {
+
+    pub fn new(control: TimeControl, players: usize) -> Self {
+        Self {
+            control,
+            banks: vec![control.reserve; players],
+            turn_left: control.per_turn,
+            active: 0,
+            reaction: None,
+            paused: false,
+            flagged: false,
+            retired: vec![false; players],
+        }
+    }
+
+    #[inline]
+    pub fn control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    #[inline]
+    pub fn bank(&self, player: usize) -> Duration {
+        self.banks[player]
+    }
+
+    #[inline]
+    pub fn turn_left(&self) -> Duration {
+        self.turn_left
+    }
+
+    #[inline]
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Who a reaction window is open for, and how long they have left.
+    #[inline]
+    pub fn reaction(&self) -> Option<(usize, Duration)> {
+        self.reaction
+    }
+
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    #[inline]
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    #[inline]
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    /// Gives `player` the control's reaction time to respond, stopping the
+    /// turn's clock until `close_reaction`, or until the window times out.
+    /// Does nothing for a player the clock has no bank for.
+    pub fn open_reaction(&mut self, player: usize) {
+        if player < self.banks.len() {
+            self.reaction = Some((player, self.control.reaction));
+        }
+    }
+
+    #[inline]
+    pub fn close_reaction(&mut self) {
+        self.reaction = None;
+    }
+
+    /// Runs the clock forward: an open reaction window's timer if there
+    /// is one, otherwise the active player's per-turn allowance and then
+    /// their bank. Reports a timeout exactly once, the first tick the
+    /// reaction timer or the bank is empty.
+    pub fn tick(&mut self, dt: Duration) -> Option<Timeout> {
+        if self.paused {
+            return None;
+        }
+
+        if let Some((player, left)) = &mut self.reaction {
+            if dt < *left {
+                *left -= dt;
+                return None;
+            }
+            let player = *player;
+            self.reaction = None;
+            return Some(Timeout::Reaction(player));
+        }
+
+        if self.flagged {
+            return None;
+        }
+
+        if dt <= self.turn_left {
+            self.turn_left -= dt;
+            return None;
+        }
+
+        let overflow = dt - self.turn_left;
+        self.turn_left = Duration::new(0, 0);
+
+        let bank = &mut self.banks[self.active];
+        if overflow < *bank {
+            *bank -= overflow;
+            return None;
+        }
+
+        *bank = Duration::new(0, 0);
+        self.flagged = true;
+
+        Some(match self.control.on_timeout {
+            TimeoutPolicy::AutoPass => Timeout::Pass(self.active),
+            TimeoutPolicy::AutoConcede => Timeout::Concede(self.active),
+        })
+    }
+
+    /// Marks `player` as out of the match, e.g. having conceded.
+    pub fn retire(&mut self, player: usize) {
+        if let Some(retired) = self.retired.get_mut(player) {
+            *retired = true;
+        }
+    }
+
+    /// Hands the clock to `next`, crediting the outgoing player's increment,
+    /// unless they ran out of time this turn or are out of the match.
+    pub fn switch_to(&mut self, next: usize) {
+        if !self.flagged && !self.retired[self.active] {
+            self.banks[self.active] += self.control.increment;
+        }
+        self.active = next;
+        self.turn_left = self.control.per_turn;
+        self.reaction = None;
+        self.flagged = false;
+    }
+}
+
+
+/// Turns wall-clock time into whole `TICK`s, carrying the remainder over.
+#[derive(Default)]
+pub struct Ticker {
+    carry: Duration,
+}
+
+impl Ticker {
+    /// How many ticks `dt`, on top of what was left over last time, makes.
+    pub fn ticks(&mut self, dt: Duration) -> u32 {
+        self.carry += dt;
+        let mut ticks = 0;
+        while self.carry >= TICK {
+            self.carry -= TICK;
+            ticks += 1;
+        }
+        ticks
+    }
+}
+
+
+/// Formats a duration as `m:ss.s` for display.
+pub fn display(time: Duration) -> String {
+    let secs = time.as_secs_f32();
+    format!("{}:{:04.1}", (secs / 60.0) as u32, secs % 60.0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn control(on_timeout: TimeoutPolicy) -> TimeControl {
+        TimeControl {
+            per_turn: secs(10),
+            reserve: secs(60),
+            increment: secs(5),
+            reaction: secs(3),
+            on_timeout,
+        }
+    }
+
+    #[test]
+    fn spends_the_turn_allowance_before_the_bank() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+
+        assert_eq!(clock.tick(secs(4)), None);
+        assert_eq!(clock.turn_left(), secs(6));
+        assert_eq!(clock.bank(0), secs(60));
+
+        assert_eq!(clock.tick(secs(10)), None);
+        assert_eq!(clock.turn_left(), secs(0));
+        assert_eq!(clock.bank(0), secs(56));
+        assert_eq!(clock.bank(1), secs(60));
+    }
+
+    #[test]
+    fn reports_a_timeout_once() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoConcede), 2);
+
+        assert_eq!(clock.tick(secs(70)), Some(Timeout::Concede(0)));
+        assert_eq!(clock.bank(0), secs(0));
+        assert_eq!(clock.tick(secs(1)), None);
+
+        clock.switch_to(1);
+        assert_eq!(clock.tick(secs(80)), Some(Timeout::Concede(1)));
+    }
+
+    #[test]
+    fn pauses() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+
+        clock.pause();
+        assert_eq!(clock.tick(secs(100)), None);
+        assert_eq!(clock.turn_left(), secs(10));
+
+        clock.resume();
+        assert_eq!(clock.tick(secs(100)), Some(Timeout::Pass(0)));
+    }
+
+    #[test]
+    fn switching_credits_the_increment_and_resets_the_turn() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 3);
+
+        clock.tick(secs(15));
+        clock.switch_to(2);
+
+        assert_eq!(clock.active(), 2);
+        assert_eq!(clock.bank(0), secs(60));
+        assert_eq!(clock.turn_left(), secs(10));
+        assert_eq!(clock.bank(2), secs(60));
+    }
+
+    #[test]
+    fn no_increment_after_running_out_or_retiring() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+
+        assert_eq!(clock.tick(secs(70)), Some(Timeout::Pass(0)));
+        clock.switch_to(1);
+        assert_eq!(clock.bank(0), secs(0));
+
+        clock.retire(1);
+        clock.switch_to(0);
+        assert_eq!(clock.bank(1), secs(60));
+    }
+
+    #[test]
+    fn ignores_reactions_for_unknown_players() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+
+        clock.open_reaction(2);
+        assert_eq!(clock.reaction(), None);
+        assert_eq!(clock.tick(secs(4)), None);
+        assert_eq!(clock.turn_left(), secs(6));
+    }
+
+    #[test]
+    fn times_out_on_the_same_tick_however_frames_fall() {
+        fn run(frames: &[Duration]) -> Vec<(u32, Timeout)> {
+            let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+            let mut ticker = Ticker::default();
+            let mut tick = 0;
+            let mut timeouts = Vec::new();
+            for &frame in frames {
+                for _ in 0 .. ticker.ticks(frame) {
+                    tick += 1;
+                    if let Some(timeout) = clock.tick(TICK) {
+                        timeouts.push((tick, timeout));
+                        clock.switch_to(1 - clock.active());
+                    }
+                }
+            }
+            timeouts
+        }
+
+        let smooth = vec![Duration::from_millis(16); 10_000];
+        // The same 160 seconds, in uneven frames.
+        let choppy = (0 .. 10_000)
+            .map(|i| Duration::from_millis([3, 41, 7, 9, 20][i % 5]))
+            .collect::<Vec<_>>();
+
+        let expected = run(&smooth);
+        assert_eq!(expected, run(&choppy));
+        assert_eq!(expected[.. 2], [(700, Timeout::Pass(0)), (1400, Timeout::Pass(1))]);
+    }
+
+    #[test]
+    fn reaction_windows_stop_the_turn_and_time_out_separately() {
+        let mut clock = ChessClock::new(control(TimeoutPolicy::AutoPass), 2);
+
+        clock.open_reaction(1);
+        assert_eq!(clock.tick(secs(2)), None);
+        assert_eq!(clock.reaction(), Some((1, secs(1))));
+        assert_eq!(clock.turn_left(), secs(10));
+
+        assert_eq!(clock.tick(secs(2)), Some(Timeout::Reaction(1)));
+        assert_eq!(clock.reaction(), None);
+        assert_eq!(clock.turn_left(), secs(10));
+
+        clock.open_reaction(1);
+        clock.close_reaction();
+        assert_eq!(clock.tick(secs(4)), None);
+        assert_eq!(clock.turn_left(), secs(6));
+    }
+}
@@ -0,0 +1,457 @@
+//! The cards a player owns, and the decks they've built from them, kept on disk.
+//!
+//! The store is a small text file, so it can be read and fixed by hand:
+//!
+//! ```text
+//! collection 1
+//! card 3 Goblin Raider
+//! card 1 Forest
+//! deck Goblins
+//!   2 Goblin Raider
+//! ```
+//!
+//! Its first line says which version of the format the rest is in. Older
+//! files are brought up to date with `MIGRATIONS` as they're loaded, and
+//! files from newer versions of the game are refused rather than mangled.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+
+/// Where the collection is stored, relative to the game's directory.
+pub const PATH: &str = "saves/collection.txt";
+
+/// Rewrites a file of one version into the next one. The migration from
+/// version `n` to `n + 1` goes at index `n - 1`.
+pub type Migration = fn(&str) -> Result<String, String>;
+
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// The version of the format `Collection::to_text` writes: the one the
+/// last of `MIGRATIONS` migrates to.
+pub const VERSION: u32 = 1 + MIGRATIONS.len() as u32;
+
+
+#[derive(Debug)]
+pub enum CollectionError {
+    Io(std::io::Error),
+    /// The file was written by a newer version of the game.
+    TooNew(u32),
+    Malformed { line: usize, reason: String },
+    Migration { from: u32, reason: String },
+    /// Names can't be empty, span lines, or start or end with whitespace.
+    InvalidName(String),
+    /// More copies of a card than can be counted.
+    TooMany(String),
+}
+
+impl std::fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CollectionError::Io(e) =>
+                write!(f, "{}", e),
+            CollectionError::TooNew(version) =>
+                write!(f, "collection is version {}, newer than this game's version {}", version, VERSION),
+            CollectionError::Malformed { line, reason } =>
+                write!(f, "line {}: {}", line, reason),
+            CollectionError::Migration { from, reason } =>
+                write!(f, "couldn't migrate collection from version {}: {}", from, reason),
+            CollectionError::InvalidName(name) =>
+                write!(f, "{:?} isn't a valid name", name),
+            CollectionError::TooMany(name) =>
+                write!(f, "too many copies of {} to count", name),
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
+
+impl From<std::io::Error> for CollectionError {
+    fn from(e: std::io::Error) -> Self {
+        CollectionError::Io(e)
+    }
+}
+
+
+/// How many of each card, by name.
+pub type Cards = BTreeMap<String, u32>;
+
+/// What a legal deck looks like.
+#[derive(Copy, Clone, Debug)]
+pub struct DeckRules {
+    pub min_size: u32,
+    pub max_copies: u32,
+}
+
+impl Default for DeckRules {
+    fn default() -> Self {
+        Self { min_size: 40, max_copies: 4 }
+    }
+}
+
+/// Something wrong with a deck, as found by `Collection::check_deck`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeckProblem {
+    TooSmall { size: u32, min: u32 },
+    TooManyCopies { card: String, count: u32, max: u32 },
+    NotOwned { card: String, count: u32, owned: u32 },
+}
+
+impl std::fmt::Display for DeckProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeckProblem::TooSmall { size, min } =>
+                write!(f, "{} cards, but decks need at least {}", size, min),
+            DeckProblem::TooManyCopies { card, count, max } =>
+                write!(f, "{} copies of {}, but decks can have at most {}", count, card, max),
+            DeckProblem::NotOwned { card, count, owned } =>
+                write!(f, "{} copies of {}, but only {} owned", count, card, owned),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Collection {
+    owned: Cards,
+    decks: BTreeMap<String, Cards>,
+}
+
+impl Collection {
+    /// Reads the collection at `path`, or starts an empty one if there isn't one yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CollectionError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text, MIGRATIONS),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the collection to `path`, by way of a temporary file, so a
+    /// crash partway through leaves the old collection intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), CollectionError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, self.to_text())?;
+        std::fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    /// Reads a collection from `text`, migrating it with `migrations` if it's old.
+    pub fn parse(text: &str, migrations: &[Migration]) -> Result<Self, CollectionError> {
+        let current = 1 + migrations.len() as u32;
+
+        let mut version = parse_header(text)?;
+        if version > current {
+            return Err(CollectionError::TooNew(version));
+        }
+
+        let mut text = text.to_owned();
+        while version < current {
+            text = migrations[version as usize - 1](&text)
+                .map_err(|reason| CollectionError::Migration { from: version, reason })?;
+            if parse_header(&text)? != version + 1 {
+                let reason = format!("didn't produce version {}", version + 1);
+                return Err(CollectionError::Migration { from: version, reason });
+            }
+            version += 1;
+        }
+
+        Self::parse_current(&text)
+    }
+
+    fn parse_current(text: &str) -> Result<Self, CollectionError> {
+        let mut collection = Self::default();
+        let mut deck: Option<String> = None;
+
+        for (i, line) in text.lines().enumerate().skip(1) {
+            let malformed = |reason: &str| CollectionError::Malformed { line: i + 1, reason: reason.to_owned() };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("card ") {
+                let (count, name) = parse_count(rest).ok_or_else(|| malformed("expected `card <count> <name>`"))?;
+                add_to(&mut collection.owned, name, count).map_err(|_| malformed("too many copies to count"))?;
+                deck = None;
+            } else if let Some(name) = line.strip_prefix("deck ") {
+                check_name(name).map_err(|_| malformed("invalid deck name"))?;
+                collection.decks.insert(name.to_owned(), Cards::new());
+                deck = Some(name.to_owned());
+            } else if let Some(rest) = line.strip_prefix("  ") {
+                let deck = deck.as_ref().ok_or_else(|| malformed("deck entry outside of a deck"))?;
+                let (count, name) = parse_count(rest).ok_or_else(|| malformed("expected `  <count> <name>`"))?;
+                add_to(collection.decks.get_mut(deck).unwrap(), name, count)
+                    .map_err(|_| malformed("too many copies to count"))?;
+            } else {
+                return Err(malformed("expected `card`, `deck`, or an indented deck entry"));
+            }
+        }
+
+        Ok(collection)
+    }
+
+    /// The collection in the current version of the format.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("collection {}\n", VERSION);
+        for (name, count) in &self.owned {
+            text += &format!("card {} {}\n", count, name);
+        }
+        for (deck, cards) in &self.decks {
+            text += &format!("deck {}\n", deck);
+            for (name, count) in cards {
+                text += &format!("  {} {}\n", count, name);
+            }
+        }
+        text
+    }
+
+    /// How many of `card` are owned.
+    pub fn owned(&self, card: &str) -> u32 {
+        self.owned.get(card).copied().unwrap_or(0)
+    }
+
+    pub fn cards(&self) -> &Cards {
+        &self.owned
+    }
+
+    pub fn add(&mut self, card: &str, count: u32) -> Result<(), CollectionError> {
+        check_name(card)?;
+        add_to(&mut self.owned, card, count)
+    }
+
+    /// Removes up to `count` of `card`, and returns how many were removed.
+    /// Decks using them are left alone, and fail `check_deck` until fixed.
+    pub fn remove(&mut self, card: &str, count: u32) -> u32 {
+        let owned = self.owned(card);
+        let removed = count.min(owned);
+        if removed == owned {
+            self.owned.remove(card);
+        } else {
+            self.owned.insert(card.to_owned(), owned - removed);
+        }
+        removed
+    }
+
+    /// Saved decks' names, in order.
+    pub fn decks(&self) -> impl Iterator<Item = &str> {
+        self.decks.keys().map(String::as_str)
+    }
+
+    pub fn deck(&self, name: &str) -> Option<&Cards> {
+        self.decks.get(name)
+    }
+
+    /// Saves `cards` as the deck `name`, replacing any deck already saved as it.
+    /// Decks are saved whether or not they're legal, so they can be worked on.
+    pub fn save_deck(&mut self, name: &str, cards: Cards) -> Result<(), CollectionError> {
+        check_name(name)?;
+        for card in cards.keys() {
+            check_name(card)?;
+        }
+        self.decks.insert(name.to_owned(), cards.into_iter().filter(|&(_, count)| count > 0).collect());
+        Ok(())
+    }
+
+    /// Whether there was a deck called `name` to delete.
+    pub fn delete_deck(&mut self, name: &str) -> bool {
+        self.decks.remove(name).is_some()
+    }
+
+    /// Everything stopping `cards` from being played as a deck.
+    pub fn check_deck(&self, cards: &Cards, rules: DeckRules) -> Vec<DeckProblem> {
+        let mut problems = Vec::new();
+
+        // A deck too big to count is, at least, not too small.
+        let size = cards.values().fold(0u32, |size, &count| size.saturating_add(count));
+        if size < rules.min_size {
+            problems.push(DeckProblem::TooSmall { size, min: rules.min_size });
+        }
+
+        for (card, &count) in cards {
+            if count > rules.max_copies {
+                problems.push(DeckProblem::TooManyCopies { card: card.clone(), count, max: rules.max_copies });
+            }
+            let owned = self.owned(card);
+            if count > owned {
+                problems.push(DeckProblem::NotOwned { card: card.clone(), count, owned });
+            }
+        }
+
+        problems
+    }
+}
+
+fn add_to(cards: &mut Cards, card: &str, count: u32) -> Result<(), CollectionError> {
+    let total = cards.entry(card.to_owned()).or_insert(0);
+    *total = total.checked_add(count).ok_or_else(|| CollectionError::TooMany(card.to_owned()))?;
+    Ok(())
+}
+
+fn parse_header(text: &str) -> Result<u32, CollectionError> {
+    text.lines().next()
+        .and_then(|line| line.strip_prefix("collection "))
+        .and_then(|version| version.trim().parse().ok())
+        .filter(|&version| version > 0)
+        .ok_or_else(|| CollectionError::Malformed { line: 1, reason: "expected `collection <version>`".to_owned() })
+}
+
+fn parse_count(text: &str) -> Option<(u32, &str)> {
+    let mut parts = text.splitn(2, ' ');
+    let count = parts.next()?.parse().ok()?;
+    let name = parts.next()?;
+    check_name(name).ok()?;
+    Some((count, name))
+}
+
+fn check_name(name: &str) -> Result<(), CollectionError> {
+    if name.is_empty() || name.contains(|c| c == '\n' || c == '\r') || name.trim() != name {
+        return Err(CollectionError::InvalidName(name.to_owned()));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cards(list: &[(&str, u32)]) -> Cards {
+        list.iter().map(|&(name, count)| (name.to_owned(), count)).collect()
+    }
+
+    fn sample() -> Collection {
+        let mut collection = Collection::default();
+        collection.add("Goblin Raider", 3).unwrap();
+        collection.add("Forest", 40).unwrap();
+        collection.save_deck("Goblins", cards(&[("Goblin Raider", 3), ("Forest", 37)])).unwrap();
+        collection.save_deck("Empty", Cards::new()).unwrap();
+        collection
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let collection = sample();
+        assert_eq!(Collection::parse(&collection.to_text(), MIGRATIONS).unwrap(), collection);
+    }
+
+    #[test]
+    fn reads_hand_written_files() {
+        let text = "collection 1\ncard 2 Forest\n\ndeck Mono green\n  2 Forest\ncard 1 Forest\n";
+        let collection = Collection::parse(text, MIGRATIONS).unwrap();
+        assert_eq!(collection.owned("Forest"), 3);
+        assert_eq!(collection.deck("Mono green"), Some(&cards(&[("Forest", 2)])));
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        for (text, line) in &[
+            ("", 1),
+            ("collection x\n", 1),
+            ("collection 1\ncard two Forest\n", 2),
+            ("collection 1\n  2 Forest\n", 2),
+            ("collection 1\ndeck A\n  2 Forest\nwhat\n", 4),
+            ("collection 1\ncard 4294967295 Forest\ncard 1 Forest\n", 3),
+            ("collection 1\ndeck A\n  4294967295 Forest\n  1 Forest\n", 4),
+        ] {
+            match Collection::parse(text, MIGRATIONS) {
+                Err(CollectionError::Malformed { line: l, .. }) => assert_eq!(l, *line, "{:?}", text),
+                other => panic!("{:?} parsed as {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let text = format!("collection {}\n", VERSION + 1);
+        assert!(matches!(Collection::parse(&text, MIGRATIONS), Err(CollectionError::TooNew(_))));
+    }
+
+    #[test]
+    fn migrates_older_versions() {
+        // Pretend today's format is version 2, and version 1 indented deck entries with tabs.
+        fn from_tabs(text: &str) -> Result<String, String> {
+            Ok(text.replacen("collection 1", "collection 2", 1).replace("\n\t", "\n  "))
+        }
+        fn broken(_: &str) -> Result<String, String> {
+            Err("nope".to_owned())
+        }
+
+        let text = "collection 1\ncard 2 Forest\ndeck A\n\t2 Forest\n";
+        let collection = Collection::parse(text, &[from_tabs]).unwrap();
+        assert_eq!(collection.deck("A"), Some(&cards(&[("Forest", 2)])));
+
+        // Failing migrations are reported, rather than skipped, and only old files run them.
+        assert!(matches!(Collection::parse(text, &[broken]), Err(CollectionError::Migration { from: 1, .. })));
+        assert!(Collection::parse("collection 2\n", &[broken]).is_ok());
+    }
+
+    #[test]
+    fn adds_and_removes_cards() {
+        let mut collection = sample();
+        assert_eq!(collection.remove("Goblin Raider", 2), 2);
+        assert_eq!(collection.owned("Goblin Raider"), 1);
+        assert_eq!(collection.remove("Goblin Raider", 5), 1);
+        assert_eq!(collection.owned("Goblin Raider"), 0);
+        assert!(!collection.cards().contains_key("Goblin Raider"));
+
+        assert!(collection.add("", 1).is_err());
+        assert!(collection.add(" Forest", 1).is_err());
+        assert!(collection.add("Forest\ncard 99 Dragon", 1).is_err());
+
+        assert!(matches!(collection.add("Forest", u32::MAX), Err(CollectionError::TooMany(_))));
+        assert_eq!(collection.owned("Forest"), 40);
+    }
+
+    #[test]
+    fn saves_and_deletes_decks() {
+        let mut collection = sample();
+        assert_eq!(collection.decks().collect::<Vec<_>>(), vec!["Empty", "Goblins"]);
+
+        collection.save_deck("Empty", cards(&[("Forest", 1), ("Goblin Raider", 0)])).unwrap();
+        assert_eq!(collection.deck("Empty"), Some(&cards(&[("Forest", 1)])));
+
+        assert!(collection.delete_deck("Empty"));
+        assert!(!collection.delete_deck("Empty"));
+        assert!(collection.save_deck("", Cards::new()).is_err());
+    }
+
+    #[test]
+    fn checks_decks() {
+        let collection = sample();
+        let rules = DeckRules::default();
+
+        let goblins = collection.deck("Goblins").unwrap();
+        assert_eq!(collection.check_deck(goblins, rules), vec![]);
+
+        let problems = collection.check_deck(&cards(&[("Goblin Raider", 5), ("Forest", 10)]), rules);
+        assert_eq!(problems, vec![
+            DeckProblem::TooSmall { size: 15, min: 40 },
+            DeckProblem::TooManyCopies { card: "Goblin Raider".to_owned(), count: 5, max: 4 },
+            DeckProblem::NotOwned { card: "Goblin Raider".to_owned(), count: 5, owned: 3 },
+        ]);
+
+        let huge = cards(&[("Forest", u32::MAX), ("Goblin Raider", u32::MAX)]);
+        assert!(!collection.check_deck(&huge, rules).iter().any(|problem| matches!(problem, DeckProblem::TooSmall { .. })));
+    }
+
+    #[test]
+    fn saves_and_loads_files() {
+        let dir = std::env::temp_dir().join(format!("collection-test-{}", std::process::id()));
+        let path = dir.join("saves").join("collection.txt");
+
+        assert_eq!(Collection::load(&path).unwrap(), Collection::default());
+
+        let collection = sample();
+        collection.save(&path).unwrap();
+        assert_eq!(Collection::load(&path).unwrap(), collection);
+        assert!(!path.with_extension("tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+
+use crate::clock::{self, ChessClock, TimeControl, Timeout};
+use crate::render::camera::GimbalCamera;
+use crate::render::gui::Widget;
+
+
+/// One human player sharing the machine.
+pub struct Seat {
+    pub name: imgui::ImString,
+    pub conceded: bool,
+    /// Played from another machine, so there's no device to pass over.
+    pub remote: bool,
+    camera: GimbalCamera,
+}
+
+
+/// Where the game is at, as far as whoever's turn it is can act.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting on the device to reach the next player.
+    Handoff,
+    Play,
+    /// Waiting on a player's response in a reaction window.
+    Reaction,
+    Over,
+}
+
+impl Phase {
+    /// The name scripts know the phase by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Handoff => "handoff",
+            Phase::Play => "play",
+            Phase::Reaction => "reaction",
+            Phase::Over => "over",
+        }
+    }
+}
+
+
+/// Local multiplayer state for several players passing one device around.
+///
+/// Each seat remembers its own camera, so that handing the device over
+/// swings the view around to the incoming player's side of the board.
+pub struct HotSeat {
+    seats: Vec<Seat>,
+    active: usize,
+    turn: u64,
+    handing_off: bool,
+    /// The last seat standing, once everyone else has conceded.
+    winner: Option<usize>,
+    /// The last timeout, to show in the HUD.
+    notice: Option<String>,
+    /// Set while play waits on something outside the game, like a dropped connection.
+    held: bool,
+    pub clock: ChessClock,
+}
+
+
+impl HotSeat {
+
+    /// Seats `players` around the board, spaced evenly around `base`'s
+    /// gimbal axis. The first seat gets `base` itself.
+    pub fn new(players: usize, base: GimbalCamera, time_control: TimeControl) -> Self {
+        assert!(players > 0, "Hot-seat play needs at least one player.");
+
+        let seats = (0..players).map(|i| {
+            let mut camera = base;
+            camera.gimbal_lr(std::f32::consts::PI * 2.0 * i as f32 / players as f32);
+            Seat {
+                name: imgui::ImString::new(format!("Player {}", i + 1)),
+                conceded: false,
+                remote: false,
+                camera,
+            }
+        }).collect();
+
+        Self {
+            seats,
+            active: 0,
+            turn: 1,
+            handing_off: false,
+            winner: None,
+            notice: None,
+            held: false,
+            clock: ChessClock::new(time_control, players),
+        }
+    }
+
+    #[inline]
+    pub fn active(&self) -> &Seat {
+        &self.seats[self.active]
+    }
+
+    #[inline]
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    #[inline]
+    pub fn seats(&self) -> &[Seat] {
+        &self.seats
+    }
+
+    /// Marks `seat` as played from another machine.
+    pub fn set_remote(&mut self, seat: usize) {
+        if let Some(seat) = self.seats.get_mut(seat) {
+            seat.remote = true;
+        }
+    }
+
+    /// Stops the clock while `held`, on top of any other pause.
+    #[inline]
+    pub fn set_held(&mut self, held: bool) {
+        self.held = held;
+    }
+
+    /// How many turns have started, counting the first.
+    #[inline]
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    /// While this is true, nothing private to either player should be drawn.
+    #[inline]
+    pub fn is_handing_off(&self) -> bool {
+        self.handing_off
+    }
+
+    /// The last seat still in the game, once the game is over.
+    #[inline]
+    pub fn winner(&self) -> Option<usize> {
+        self.winner
+    }
+
+    #[inline]
+    pub fn is_over(&self) -> bool {
+        self.winner.is_some()
+    }
+
+    pub fn phase(&self) -> Phase {
+        if self.is_over() {
+            Phase::Over
+        } else if self.handing_off {
+            Phase::Handoff
+        } else if self.clock.reaction().is_some() {
+            Phase::Reaction
+        } else {
+            Phase::Play
+        }
+    }
+
+    /// Ends the active player's turn: stashes their view of the board,
+    /// moves `camera` to the next seat still in the game, and raises the
+    /// privacy screen. The clock stays paused until the handoff is confirmed.
+    ///
+    /// Once only one seat is left in the game, it has won, and the game
+    /// stops instead.
+    pub fn end_turn(&mut self, camera: &mut GimbalCamera) {
+        if self.handing_off || self.is_over() {
+            return;
+        }
+
+        // `camera` is this machine's, so remote seats don't get a say in it.
+        let outgoing = self.active;
+        if !self.seats[outgoing].remote {
+            self.seats[outgoing].camera = *camera;
+        }
+
+        if self.check_winner() {
+            return;
+        }
+
+        let seat_ct = self.seats.len();
+        let next = (1..=seat_ct)
+            .map(|offset| (outgoing + offset) % seat_ct)
+            .find(|&seat| !self.seats[seat].conceded)
+            .unwrap_or(outgoing);
+
+        self.active = next;
+        self.turn += 1;
+        if !self.seats[next].remote {
+            *camera = self.seats[next].camera;
+        }
+        self.clock.switch_to(next);
+
+        // With nobody else left, there is nobody to hide anything from,
+        // and a remote seat has a screen of its own.
+        self.handing_off = next != outgoing && !self.seats[outgoing].remote && !self.seats[next].remote;
+        if self.handing_off {
+            self.clock.pause();
+        }
+    }
+
+    /// Ends the game if only one seat is left in it, and returns whether it did.
+    fn check_winner(&mut self) -> bool {
+        let mut remaining = self.seats.iter()
+            .enumerate()
+            .filter(|(_, seat)| !seat.conceded)
+            .map(|(i, _)| i);
+
+        // A game of one never ends by concession, since nobody else could win.
+        if let (Some(last), None) = (remaining.next(), remaining.next()) {
+            if self.seats.len() > 1 {
+                self.winner = Some(last);
+                self.clock.close_reaction();
+                self.clock.pause();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Takes `seat` out of the game, moving play on if it was their turn.
+    pub fn concede(&mut self, seat: usize, camera: &mut GimbalCamera) {
+        match self.seats.get_mut(seat) {
+            Some(seat) if !seat.conceded => seat.conceded = true,
+            _ => return,
+        }
+        self.clock.retire(seat);
+
+        if seat == self.active {
+            // Nobody needs to be handed a device they've given up on.
+            self.handing_off = false;
+            self.end_turn(camera);
+        } else {
+            self.check_winner();
+        }
+    }
+
+    /// Starts `seat`'s reaction timer, stopping the turn's clock meanwhile.
+    /// Seats that aren't at the table, or are out of the game, can't react.
+    pub fn open_reaction(&mut self, seat: usize) {
+        let playing = self.seats.get(seat).map_or(false, |seat| !seat.conceded);
+        if playing && !self.is_over() {
+            self.clock.open_reaction(seat);
+        }
+    }
+
+    pub fn close_reaction(&mut self) {
+        self.clock.close_reaction();
+    }
+
+    /// Called once the incoming player has the device in hand.
+    pub fn confirm_handoff(&mut self) {
+        self.handing_off = false;
+        self.clock.resume();
+    }
+
+    /// Advances the clock by one `clock::TICK`, passing or conceding on the
+    /// active player's behalf according to the time control if they run out.
+    /// Time only passes in the game through here, so a replay that ticks
+    /// and acts in the same order runs out of time in the same places.
+    ///
+    /// Returns any timeout, for the caller to pass on, e.g. to the scripts.
+    /// A reaction window timing out is left to whoever opened it to close.
+    pub fn tick(&mut self, camera: &mut GimbalCamera) -> Option<Timeout> {
+        if self.held {
+            return None;
+        }
+        let timeout = self.clock.tick(clock::TICK)?;
+
+        let (seat, what) = match timeout {
+            Timeout::Pass(seat) => (seat, "passed"),
+            Timeout::Concede(seat) => (seat, "conceded"),
+            Timeout::Reaction(seat) => (seat, "let the reaction window close"),
+        };
+        self.notice = Some(format!("{} ran out of time and {}.", self.seats[seat].name.to_str(), what));
+
+        match timeout {
+            Timeout::Pass(_) => self.end_turn(camera),
+            Timeout::Concede(seat) => self.concede(seat, camera),
+            Timeout::Reaction(_) => (),
+        }
+
+        Some(timeout)
+    }
+
+    fn compose_privacy_screen(&mut self, ui: &imgui::Ui) {
+        use imgui::*;
+
+        let [width, height] = ui.io().display_size;
+
+        let screen = Window::new(im_str!("Pass the device"))
+            .position([0.0, 0.0], Condition::Always)
+            .size([width, height], Condition::Always)
+            .bg_alpha(1.0)
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .collapsible(false)
+            .begin(&ui);
+
+        if let Some(screen) = screen {
+
+            let incoming = &self.seats[self.active].name;
+
+            ui.set_cursor_pos([width / 2.0 - 120.0, height / 2.0 - 40.0]);
+            ui.text(format!("Pass the device to {}.", incoming.to_str()));
+
+            ui.set_cursor_pos([width / 2.0 - 120.0, height / 2.0]);
+            if ui.button(&im_str!("I am {}", incoming.to_str()), [240.0, 40.0]) {
+                self.confirm_handoff();
+            }
+
+            screen.end(&ui);
+        }
+    }
+
+    /// Each seat's time, one line apiece.
+    fn compose_banks(&self, ui: &imgui::Ui) {
+        let reaction = self.clock.reaction();
+
+        for (i, seat) in self.seats.iter().enumerate() {
+            let bank = clock::display(self.clock.bank(i));
+
+            if seat.conceded {
+                ui.text_disabled(format!("{}  {}  (conceded)", seat.name.to_str(), bank));
+            } else if Some(i) == self.winner {
+                ui.text_colored([0.4, 1.0, 0.4, 1.0], format!("{}  wins", seat.name.to_str()));
+            } else if let Some((_, left)) = reaction.filter(|&(reacting, _)| reacting == i) {
+                ui.text_colored(
+                    [1.0, 0.6, 0.2, 1.0],
+                    format!("{}  {}  reacting {}", seat.name.to_str(), bank, clock::display(left)),
+                );
+            } else if i == self.clock.active() {
+                let turn = clock::display(self.clock.turn_left());
+                ui.text_colored(
+                    [1.0, 1.0, 0.4, 1.0],
+                    format!("{}  {}  +{}", seat.name.to_str(), bank, turn),
+                );
+            } else {
+                ui.text(format!("{}  {}", seat.name.to_str(), bank));
+            }
+        }
+
+        if let Some(notice) = &self.notice {
+            ui.text_disabled(notice);
+        }
+
+        if self.held {
+            ui.text_disabled("Waiting on the other players...");
+        }
+    }
+
+    /// The clock as players see it during play, in the corner of the screen.
+    fn compose_hud(&self, ui: &imgui::Ui) {
+        use imgui::*;
+
+        let [width, _] = ui.io().display_size;
+
+        let hud = Window::new(im_str!("###clock_hud"))
+            .position([width - 250.0, 10.0], Condition::Always)
+            .always_auto_resize(true)
+            .bg_alpha(0.5)
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .collapsible(false)
+            .begin(&ui);
+
+        if let Some(hud) = hud {
+            self.compose_banks(ui);
+            hud.end(&ui);
+        }
+    }
+
+    /// The clock's debug window, which can also pause it.
+    fn compose_clock(&mut self, ui: &imgui::Ui) {
+        use imgui::*;
+
+        let clock_window = Window::new(im_str!("Clock"))
+            .size([220.0, 120.0], Condition::FirstUseEver)
+            .begin(&ui);
+
+        if let Some(clock_window) = clock_window {
+
+            self.compose_banks(ui);
+
+            ui.separator();
+
+            if self.clock.is_paused() {
+                if ui.button(im_str!("Resume"), [100.0, 20.0]) {
+                    self.clock.resume();
+                }
+            } else if ui.button(im_str!("Pause"), [100.0, 20.0]) {
+                self.clock.pause();
+            }
+
+            clock_window.end(&ui);
+        }
+    }
+}
+
+
+impl Widget for HotSeat {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+        if self.handing_off {
+            self.compose_privacy_screen(ui);
+        } else {
+            self.compose_hud(ui);
+            self.compose_clock(ui);
+        }
+    }
+}
+
+
+/// Just the clock's HUD, for when the debug view is hidden.
+pub struct ClockHud<'a>(pub &'a HotSeat);
+
+impl Widget for ClockHud<'_> {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+        if !self.0.handing_off {
+            self.0.compose_hud(ui);
+        }
+    }
+}
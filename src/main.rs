@@ -8,6 +8,11 @@ use winit::{
 
 pub mod render;
 pub mod util;
+pub mod hotseat;
+pub mod clock;
+pub mod script;
+pub mod net;
+pub mod collection;
 
 
 use render::{Pass, AnyAttachmentDescriptor::*};
@@ -19,11 +24,27 @@ enum EngineEvent {
     RefreshRenderPasses {
         clear_asset_caches: bool,
     },
+    View(script::view::ViewCommand),
 }
 
 
 
 fn main() -> ! {
+
+    let mut args = std::env::args().skip(1);
+    let host = match args.next().as_deref() {
+        Some("test-scripts") => std::process::exit(script::harness::run_cli(args)),
+        #[cfg(feature = "shaderc")]
+        Some("compile-shaders") => std::process::exit(render::precompile::run_cli(args)),
+        Some("host") => match net::server::HostConfig::from_args(args) {
+            Ok(config) => Some(config),
+            Err(usage) => {
+                eprintln!("{}", usage);
+                std::process::exit(2);
+            },
+        },
+        _ => None,
+    };
     
     let event_loop = EventLoop::<EngineEvent>::with_user_event();
 
@@ -31,8 +52,10 @@ fn main() -> ! {
     
     let mut renderer = futures::executor::block_on(render::Core::init(&mut window_state));
     
+    let mut render_scale: f64 = 1.0;
+
     let (mut main_pass, _) =
-        render::MainPass::construct(1.0, (&renderer, &renderer.sc_desc));
+        render::MainPass::construct(render_scale, (&renderer, &renderer.sc_desc));
 
     let (mut imgui_pass, _) =
         gui::imgui_wgpu::ImguiPass::construct(None, (
@@ -44,7 +67,49 @@ fn main() -> ! {
     eprintln!("initial size: {:?}", window_state.window.inner_size());
     eprintln!("initial scale: {}", window_state.platform.hidpi_factor());
 
-    let mut gui = gui::GuiComponentState::new();
+    let scripts = script::loader::ScriptLoader::install(&window_state.lua)
+        .expect("Failed to install lua module loader.");
+
+    let view_proxy = event_loop.create_proxy();
+    let view_state = script::view::install(
+        &window_state.lua,
+        &main_pass.basic.camera,
+        render_scale,
+        move |command| {
+            let _ = view_proxy.send_event(EngineEvent::View(command));
+        },
+    ).expect("Failed to install lua view bindings.");
+
+    gui::LuaPanels::install(&window_state.lua)
+        .expect("Failed to install lua gui bindings.");
+
+    let events = script::events::EventBus::install(&window_state.lua)
+        .expect("Failed to install lua event bindings.");
+
+    let seats = 2;
+
+    let effects = script::effects::Effects::install(&window_state.lua, seats)
+        .expect("Failed to install lua effect bindings.");
+
+    let mut gui = gui::GuiComponentState::new(window_state.console.clone());
+    let mut choices = gui::ChoicePrompts::new(effects.clone());
+    let mut shader_errors = gui::ShaderErrorOverlay::new(
+        renderer.shaders.errors(),
+        vec![renderer.textures.errors(), renderer.models.errors()],
+    );
+
+    let mut hotseat = hotseat::HotSeat::new(
+        seats,
+        *main_pass.basic.camera,
+        clock::TimeControl::default(),
+    );
+
+    let mut server = host.map(|config| net::server::Server::bind(&config, &mut hotseat)
+        .expect("Failed to host the match."));
+
+    let mut last_turn = 0;
+    let mut last_phase = None;
+    let mut ticker = clock::Ticker::default();
 
     // TODO: add ECS processing features
     let mut _world = hecs::World::new();
@@ -68,14 +133,50 @@ fn main() -> ! {
             &event,
         );
 
-        let imgui_wants_mouse = debug_view && window_state.imgui.io().want_capture_mouse;
-        let imgui_wants_kbord = debug_view && window_state.imgui.io().want_capture_keyboard;
+        let gui_visible = debug_view || hotseat.is_handing_off();
+
+        let imgui_wants_mouse = gui_visible && window_state.imgui.io().want_capture_mouse;
+        let imgui_wants_kbord = gui_visible && window_state.imgui.io().want_capture_keyboard;
         
         match &event {
             Event::NewEvents(_) => {
                 let (frame_time, frame_dura) = window_state.update_frame_time(last_frame_time);
                 last_frame_time = frame_time;
                 last_frame_duration = frame_dura;
+
+                // The game's time runs in whole ticks; see `clock::TICK`.
+                for _ in 0 .. ticker.ticks(frame_dura) {
+                    if let Some(timeout) = hotseat.tick(&mut main_pass.basic.camera) {
+                        if let Some(server) = &mut server {
+                            server.record(net::schema::Event::Timeout(timeout));
+                        }
+
+                        let (player, kind) = match timeout {
+                            clock::Timeout::Pass(seat) => (seat, "pass"),
+                            clock::Timeout::Concede(seat) => (seat, "concede"),
+                            clock::Timeout::Reaction(seat) => (seat, "reaction"),
+                        };
+
+                        // Reactions always allow choosing nothing, so running out passes.
+                        if kind == "reaction" {
+                            for (id, request) in effects.pending() {
+                                if request.reaction {
+                                    let _ = effects.answer(&window_state.lua, id, &[]);
+                                }
+                            }
+                        }
+
+                        let errors = events.emit(&window_state.lua, "timeout", |ctx| {
+                            let event = ctx.create_table()?;
+                            event.set("player", player + 1)?;
+                            event.set("kind", kind)?;
+                            Ok(event)
+                        });
+                        for error in errors {
+                            script::report_error(&window_state.lua, &error.to_string());
+                        }
+                    }
+                }
             },
 
             Event::DeviceEvent { event, .. } => match *event {
@@ -106,12 +207,12 @@ fn main() -> ! {
                     if clear_asset_caches {
                         eprintln!("Resetting asset caches...");
                         use render::cache::AssetCache;
-                        renderer.shaders.invalidate("basic.frag");
+                        renderer.shaders.clear();
                         renderer.textures.clear();
                         renderer.models.clear();
                     }
 
-                    let _ = main_pass.refresh(1.0, (
+                    let _ = main_pass.refresh(render_scale, (
                         &renderer,
                         &renderer.sc_desc,
                     ));
@@ -121,7 +222,17 @@ fn main() -> ! {
                         &mut window_state,
                         SwapChain(&renderer.sc_desc),
                     ));
-                }
+                },
+
+                EngineEvent::View(command) => {
+                    if let Some(scale) = command.apply(&mut main_pass.basic.camera) {
+                        render_scale = scale;
+                        let _ = main_pass.refresh(render_scale, (
+                            &renderer,
+                            &renderer.sc_desc,
+                        ));
+                    }
+                },
             }
 
             Event::WindowEvent { event, .. } => match *event {
@@ -202,6 +313,9 @@ fn main() -> ! {
                         VirtualKeyCode::Grave =>
                             debug_view = !debug_view,
 
+                        VirtualKeyCode::Return if !hotseat.is_handing_off() && !hotseat.active().remote =>
+                            hotseat.end_turn(&mut main_pass.basic.camera),
+
                         VirtualKeyCode::R if modifiers.ctrl() =>
                             event_proxy.send_event(
                                 EngineEvent::RefreshRenderPasses {
@@ -217,6 +331,87 @@ fn main() -> ! {
             },
 
             Event::MainEventsCleared => {
+                let changed = renderer.invalidate_changed_assets();
+                if !changed.is_empty() {
+                    eprintln!("Reloading changed assets: {:?}", changed);
+
+                    if render::MainPass::depends_on(&changed) {
+                        let _ = main_pass.refresh(render_scale, (
+                            &renderer,
+                            &renderer.sc_desc,
+                        ));
+                    }
+
+                    if gui::imgui_wgpu::ImguiPass::depends_on(&changed) {
+                        let _ = imgui_pass.refresh(None, (
+                            &renderer,
+                            &mut window_state,
+                            SwapChain(&renderer.sc_desc),
+                        ));
+                    }
+                }
+
+                if gui.debug_view() != main_pass.basic.debug_view {
+                    main_pass.basic.debug_view = gui.debug_view();
+                    let _ = main_pass.refresh(render_scale, (
+                        &renderer,
+                        &renderer.sc_desc,
+                    ));
+                }
+
+                scripts.reload_changed(&window_state.lua);
+                view_state.update(&main_pass.basic.camera, render_scale);
+                effects.update(&window_state.lua);
+
+                // Keep a reaction timer running while an effect waits on a reaction.
+                let reacting = effects.pending().into_iter()
+                    .find(|(_, request)| request.reaction)
+                    .map(|(_, request)| request.player.unwrap_or_else(|| hotseat.active_index()));
+                match (reacting, hotseat.clock.reaction()) {
+                    (Some(seat), None) => hotseat.open_reaction(seat),
+                    (None, Some(_)) => hotseat.close_reaction(),
+                    _ => (),
+                }
+
+                if let Some(server) = &mut server {
+                    server.poll(
+                        last_frame_duration,
+                        &window_state.lua,
+                        &mut hotseat,
+                        &effects,
+                        &mut main_pass.basic.camera,
+                    );
+                }
+
+                if hotseat.turn() != last_turn {
+                    last_turn = hotseat.turn();
+                    let player = hotseat.active_index() + 1;
+                    let errors = events.emit(&window_state.lua, "turn_start", |ctx| {
+                        let event = ctx.create_table()?;
+                        event.set("turn", last_turn)?;
+                        event.set("player", player)?;
+                        Ok(event)
+                    });
+                    for error in errors {
+                        script::report_error(&window_state.lua, &error.to_string());
+                    }
+                }
+
+                let phase = hotseat.phase();
+                if last_phase != Some(phase) {
+                    last_phase = Some(phase);
+                    let player = hotseat.active_index() + 1;
+                    let errors = events.emit(&window_state.lua, "phase_change", |ctx| {
+                        let event = ctx.create_table()?;
+                        event.set("phase", phase.name())?;
+                        event.set("player", player)?;
+                        Ok(event)
+                    });
+                    for error in errors {
+                        script::report_error(&window_state.lua, &error.to_string());
+                    }
+                }
+
                 window_state.window.request_redraw();
             },
             
@@ -235,8 +430,28 @@ fn main() -> ! {
                     &frame.output.view,
                 ));
 
-                if debug_view {
-                    let _ = imgui_pass.perform(&mut gui, (
+                // The privacy screen takes precedence over the debug view,
+                // which could otherwise show the outgoing player's hand.
+                if hotseat.is_handing_off() {
+                    let _ = imgui_pass.perform(&mut hotseat, (
+                        &renderer,
+                        &mut window_state,
+                        &frame.output.view,
+                    ));
+                }
+                else if debug_view {
+                    let active = hotseat.active_index();
+                    choices.set_viewer(Some(active).filter(|_| !hotseat.active().remote));
+
+                    let _ = imgui_pass.perform(&mut (&mut gui, (&mut hotseat, (&mut server, (&mut choices, &mut shader_errors)))), (
+                        &renderer,
+                        &mut window_state,
+                        &frame.output.view,
+                    ));
+                }
+                // The clock, and any shader errors, stay up even with the debug view hidden.
+                else {
+                    let _ = imgui_pass.perform(&mut (hotseat::ClockHud(&hotseat), &mut shader_errors), (
                         &renderer,
                         &mut window_state,
                         &frame.output.view,
@@ -0,0 +1,8 @@
+//! What two copies of the game say to each other over the network.
+//!
+//! `schema` is the message set and its encoding, `session` the bookkeeping
+//! either end needs, and `server` carries them over TCP for a hosted match.
+
+pub mod schema;
+pub mod server;
+pub mod session;
@@ -0,0 +1,898 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use crate::clock::Timeout;
+use crate::script::effects::ChoiceRequest;
+
+
+/// Bumped whenever any message's encoding changes.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Peers whose rules differ in major version (or, before 1.0, in minor
+/// version) can't play each other.
+pub const RULES_VERSION: Version = Version { major: 0, minor: 1 };
+
+/// Peers have to agree on the card data exactly, or they'd disagree on
+/// what cards do.
+pub const CARD_DATA_VERSION: Version = Version { major: 0, minor: 1 };
+
+/// Frames longer than this are rejected outright, before reading them.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+
+/// The first message each peer sends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    pub protocol: u16,
+    pub rules: Version,
+    pub cards: Version,
+    pub name: String,
+}
+
+impl Hello {
+    /// A hello describing this build of the game.
+    pub fn ours(name: impl Into<String>) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            rules: RULES_VERSION,
+            cards: CARD_DATA_VERSION,
+            name: name.into(),
+        }
+    }
+
+    /// Whether a peer which sent `theirs` can play against us.
+    pub fn check(&self, theirs: &Hello) -> Result<(), Incompatible> {
+        if self.protocol != theirs.protocol {
+            return Err(Incompatible::Protocol { ours: self.protocol, theirs: theirs.protocol });
+        }
+
+        let rules_agree = self.rules.major == theirs.rules.major
+            && (self.rules.major != 0 || self.rules.minor == theirs.rules.minor);
+        if !rules_agree {
+            return Err(Incompatible::Rules { ours: self.rules, theirs: theirs.rules });
+        }
+
+        if self.cards != theirs.cards {
+            return Err(Incompatible::Cards { ours: self.cards, theirs: theirs.cards });
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Why a handshake was turned down.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incompatible {
+    Protocol { ours: u16, theirs: u16 },
+    Rules { ours: Version, theirs: Version },
+    Cards { ours: Version, theirs: Version },
+}
+
+impl std::fmt::Display for Incompatible {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Incompatible::Protocol { ours, theirs } =>
+                write!(f, "protocol version {} can't talk to version {}", ours, theirs),
+            Incompatible::Rules { ours, theirs } =>
+                write!(f, "rules version {} can't play against rules version {}", ours, theirs),
+            Incompatible::Cards { ours, theirs } =>
+                write!(f, "card data version {} doesn't match card data version {}", ours, theirs),
+        }
+    }
+}
+
+
+/// What a player asks the server to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    EndTurn,
+    Concede,
+    /// The (zero-based) options picked for choice `choice`.
+    Answer { choice: u64, picks: Vec<u32> },
+}
+
+/// Something which happened in the match, in the order it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    TurnStarted { turn: u64, player: u8 },
+    Timeout(Timeout),
+    Conceded { player: u8 },
+    ChoiceRequested(ChoiceView),
+    ChoiceAnswered { choice: u64 },
+    GameOver { winner: Option<u8> },
+}
+
+/// A choice some effect is waiting on, as a player sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChoiceView {
+    pub id: u64,
+    pub player: Option<u8>,
+    pub prompt: String,
+    pub options: Vec<String>,
+    pub min: u32,
+    pub max: u32,
+    pub reaction: bool,
+}
+
+impl ChoiceView {
+    /// Fails if the request's numbers don't fit the schema's.
+    pub fn new(id: u64, request: &ChoiceRequest) -> Result<Self, EncodeError> {
+        let player = match request.player {
+            Some(player) => Some(narrow(player, "choice player")?),
+            None => None,
+        };
+        Ok(Self {
+            id,
+            player,
+            prompt: request.prompt.clone(),
+            options: request.options.clone(),
+            min: narrow(request.min, "choice minimum")?,
+            max: narrow(request.max, "choice maximum")?,
+            reaction: request.reaction,
+        })
+    }
+}
+
+/// Everything one seat can see of the match at some point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    pub seat: u8,
+    pub turn: u64,
+    pub active: u8,
+    pub turn_left: Duration,
+    pub banks: Vec<Duration>,
+    pub conceded: Vec<bool>,
+    /// Only the choices this seat can see.
+    pub choices: Vec<ChoiceView>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Malformed,
+    NotYourTurn,
+    NoSuchChoice,
+    Internal,
+    /// A seat couldn't be taken.
+    Refused,
+}
+
+
+/// Everything either peer can send.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Hello(Hello),
+    /// The server took the handshake, and seated the client.
+    Welcome { seat: u8, players: u8 },
+    Rejected(Incompatible),
+    Command(Command),
+    /// `seq` numbers every event in the match, from 0.
+    Event { seq: u64, event: Event },
+    View(View),
+    Chat { from: u8, text: String },
+    Error { code: ErrorCode, message: String },
+    /// A client asks for a seat: its own back, having seen every event
+    /// up to `last_seen`, or an open one, with `last_seen: None`.
+    Rejoin { seat: u8, last_seen: Option<u64> },
+    /// What a (re)joining client missed: where things stand now, and the
+    /// events it didn't see on the way.
+    Resync { view: View, events: Vec<Sequenced> },
+}
+
+/// An event and its place in the match.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequenced {
+    pub seq: u64,
+    pub event: Event,
+}
+
+
+/// Why a message couldn't be written as bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EncodeError {
+    /// A number too big for the field the schema gives it.
+    OutOfRange(&'static str),
+    FrameTooLong(usize),
+}
+
+impl std::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EncodeError::OutOfRange(what) =>
+                write!(f, "{} is too big to send", what),
+            EncodeError::FrameTooLong(len) =>
+                write!(f, "message of {} bytes is longer than the limit of {}", len, MAX_FRAME_LEN),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// `value` as the narrower type the schema sends it as.
+fn narrow<T: TryFrom<usize>>(value: usize, what: &'static str) -> Result<T, EncodeError> {
+    T::try_from(value).map_err(|_| EncodeError::OutOfRange(what))
+}
+
+
+/// Why bytes couldn't be read as a message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// Ran out of bytes partway through a message.
+    Truncated,
+    UnknownTag { what: &'static str, tag: u8 },
+    InvalidUtf8,
+    OutOfRange(&'static str),
+    FrameTooLong(usize),
+    /// A frame had bytes left over after its message.
+    TrailingBytes(usize),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecodeError::Truncated =>
+                write!(f, "message ends early"),
+            DecodeError::UnknownTag { what, tag } =>
+                write!(f, "unknown {} tag {}", what, tag),
+            DecodeError::InvalidUtf8 =>
+                write!(f, "string isn't valid UTF-8"),
+            DecodeError::OutOfRange(what) =>
+                write!(f, "{} is out of range", what),
+            DecodeError::FrameTooLong(len) =>
+                write!(f, "frame of {} bytes is longer than the limit of {}", len, MAX_FRAME_LEN),
+            DecodeError::TrailingBytes(count) =>
+                write!(f, "{} bytes left over after the message", count),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+
+/// Reads values back out of an encoded message.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DecodeError> {
+        if count > self.bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+
+/// A value with a fixed encoding. Integers are little-endian, and
+/// strings and lists are prefixed with their length as a `u32`.
+pub trait Wire: Sized {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError>;
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError>;
+}
+
+macro_rules! wire_ints {
+    ($($t:ty),*) => {
+        $(
+            impl Wire for $t {
+                fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+                    out.extend_from_slice(&self.to_le_bytes());
+                    Ok(())
+                }
+
+                fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    bytes.copy_from_slice(input.take(bytes.len())?);
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    }
+}
+
+wire_ints!(u8, u16, u32, u64);
+
+/// Encodes a struct field by field, in the order given.
+macro_rules! wire_struct {
+    ($t:ident { $($field:ident),* $(,)? }) => {
+        impl Wire for $t {
+            fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+                $( self.$field.encode(out)?; )*
+                Ok(())
+            }
+
+            fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+                Ok($t { $( $field: Wire::decode(input)?, )* })
+            }
+        }
+    }
+}
+
+impl Wire for bool {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        (*self as u8).encode(out)
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::UnknownTag { what: "bool", tag }),
+        }
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        narrow::<u32>(self.len(), "string length")?.encode(out)?;
+        out.extend_from_slice(self.as_bytes());
+        Ok(())
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        let len = u32::decode(input)? as usize;
+        let bytes = input.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Wire> Wire for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        narrow::<u32>(self.len(), "list length")?.encode(out)?;
+        for item in self {
+            item.encode(out)?;
+        }
+        Ok(())
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        let len = u32::decode(input)? as usize;
+        // Every item takes at least a byte, so a longer list can't be
+        // there, and trusting its length would allocate whatever it says.
+        if len > input.remaining() {
+            return Err(DecodeError::Truncated);
+        }
+        (0 .. len).map(|_| T::decode(input)).collect()
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        match self {
+            None => 0u8.encode(out),
+            Some(value) => {
+                1u8.encode(out)?;
+                value.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::UnknownTag { what: "option", tag }),
+        }
+    }
+}
+
+impl Wire for Duration {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        self.as_secs().encode(out)?;
+        self.subsec_nanos().encode(out)
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        let secs = u64::decode(input)?;
+        let nanos = u32::decode(input)?;
+        if nanos >= 1_000_000_000 {
+            return Err(DecodeError::OutOfRange("duration nanoseconds"));
+        }
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+wire_struct!(Version { major, minor });
+wire_struct!(Hello { protocol, rules, cards, name });
+wire_struct!(ChoiceView { id, player, prompt, options, min, max, reaction });
+wire_struct!(View { seat, turn, active, turn_left, banks, conceded, choices });
+wire_struct!(Sequenced { seq, event });
+
+impl Wire for Incompatible {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        match self {
+            Incompatible::Protocol { ours, theirs } => {
+                0u8.encode(out)?;
+                ours.encode(out)?;
+                theirs.encode(out)
+            },
+            Incompatible::Rules { ours, theirs } => {
+                1u8.encode(out)?;
+                ours.encode(out)?;
+                theirs.encode(out)
+            },
+            Incompatible::Cards { ours, theirs } => {
+                2u8.encode(out)?;
+                ours.encode(out)?;
+                theirs.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Incompatible::Protocol { ours: Wire::decode(input)?, theirs: Wire::decode(input)? },
+            1 => Incompatible::Rules { ours: Wire::decode(input)?, theirs: Wire::decode(input)? },
+            2 => Incompatible::Cards { ours: Wire::decode(input)?, theirs: Wire::decode(input)? },
+            tag => return Err(DecodeError::UnknownTag { what: "incompatibility", tag }),
+        })
+    }
+}
+
+impl Wire for Command {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        match self {
+            Command::EndTurn => 0u8.encode(out),
+            Command::Concede => 1u8.encode(out),
+            Command::Answer { choice, picks } => {
+                2u8.encode(out)?;
+                choice.encode(out)?;
+                picks.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Command::EndTurn,
+            1 => Command::Concede,
+            2 => Command::Answer { choice: Wire::decode(input)?, picks: Wire::decode(input)? },
+            tag => return Err(DecodeError::UnknownTag { what: "command", tag }),
+        })
+    }
+}
+
+impl Wire for Timeout {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, player) = match *self {
+            Timeout::Pass(player) => (0u8, player),
+            Timeout::Concede(player) => (1u8, player),
+            Timeout::Reaction(player) => (2u8, player),
+        };
+        tag.encode(out)?;
+        narrow::<u8>(player, "timeout player")?.encode(out)
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        let tag = u8::decode(input)?;
+        let player = u8::decode(input)? as usize;
+        Ok(match tag {
+            0 => Timeout::Pass(player),
+            1 => Timeout::Concede(player),
+            2 => Timeout::Reaction(player),
+            tag => return Err(DecodeError::UnknownTag { what: "timeout", tag }),
+        })
+    }
+}
+
+impl Wire for Event {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        match self {
+            Event::TurnStarted { turn, player } => {
+                0u8.encode(out)?;
+                turn.encode(out)?;
+                player.encode(out)
+            },
+            Event::Timeout(timeout) => {
+                1u8.encode(out)?;
+                timeout.encode(out)
+            },
+            Event::Conceded { player } => {
+                2u8.encode(out)?;
+                player.encode(out)
+            },
+            Event::ChoiceRequested(choice) => {
+                3u8.encode(out)?;
+                choice.encode(out)
+            },
+            Event::ChoiceAnswered { choice } => {
+                4u8.encode(out)?;
+                choice.encode(out)
+            },
+            Event::GameOver { winner } => {
+                5u8.encode(out)?;
+                winner.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Event::TurnStarted { turn: Wire::decode(input)?, player: Wire::decode(input)? },
+            1 => Event::Timeout(Wire::decode(input)?),
+            2 => Event::Conceded { player: Wire::decode(input)? },
+            3 => Event::ChoiceRequested(Wire::decode(input)?),
+            4 => Event::ChoiceAnswered { choice: Wire::decode(input)? },
+            5 => Event::GameOver { winner: Wire::decode(input)? },
+            tag => return Err(DecodeError::UnknownTag { what: "event", tag }),
+        })
+    }
+}
+
+impl Wire for ErrorCode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let tag: u8 = match self {
+            ErrorCode::Malformed => 0,
+            ErrorCode::NotYourTurn => 1,
+            ErrorCode::NoSuchChoice => 2,
+            ErrorCode::Internal => 3,
+            ErrorCode::Refused => 4,
+        };
+        tag.encode(out)
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => ErrorCode::Malformed,
+            1 => ErrorCode::NotYourTurn,
+            2 => ErrorCode::NoSuchChoice,
+            3 => ErrorCode::Internal,
+            4 => ErrorCode::Refused,
+            tag => return Err(DecodeError::UnknownTag { what: "error code", tag }),
+        })
+    }
+}
+
+impl Wire for Message {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+        match self {
+            Message::Hello(hello) => {
+                0u8.encode(out)?;
+                hello.encode(out)
+            },
+            Message::Welcome { seat, players } => {
+                1u8.encode(out)?;
+                seat.encode(out)?;
+                players.encode(out)
+            },
+            Message::Rejected(why) => {
+                2u8.encode(out)?;
+                why.encode(out)
+            },
+            Message::Command(command) => {
+                3u8.encode(out)?;
+                command.encode(out)
+            },
+            Message::Event { seq, event } => {
+                4u8.encode(out)?;
+                seq.encode(out)?;
+                event.encode(out)
+            },
+            Message::View(view) => {
+                5u8.encode(out)?;
+                view.encode(out)
+            },
+            Message::Chat { from, text } => {
+                6u8.encode(out)?;
+                from.encode(out)?;
+                text.encode(out)
+            },
+            Message::Error { code, message } => {
+                7u8.encode(out)?;
+                code.encode(out)?;
+                message.encode(out)
+            },
+            Message::Rejoin { seat, last_seen } => {
+                8u8.encode(out)?;
+                seat.encode(out)?;
+                last_seen.encode(out)
+            },
+            Message::Resync { view, events } => {
+                9u8.encode(out)?;
+                view.encode(out)?;
+                events.encode(out)
+            },
+        }
+    }
+
+    fn decode(input: &mut Reader) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Message::Hello(Wire::decode(input)?),
+            1 => Message::Welcome { seat: Wire::decode(input)?, players: Wire::decode(input)? },
+            2 => Message::Rejected(Wire::decode(input)?),
+            3 => Message::Command(Wire::decode(input)?),
+            4 => Message::Event { seq: Wire::decode(input)?, event: Wire::decode(input)? },
+            5 => Message::View(Wire::decode(input)?),
+            6 => Message::Chat { from: Wire::decode(input)?, text: Wire::decode(input)? },
+            7 => Message::Error { code: Wire::decode(input)?, message: Wire::decode(input)? },
+            8 => Message::Rejoin { seat: Wire::decode(input)?, last_seen: Wire::decode(input)? },
+            9 => Message::Resync { view: Wire::decode(input)?, events: Wire::decode(input)? },
+            tag => return Err(DecodeError::UnknownTag { what: "message", tag }),
+        })
+    }
+}
+
+
+/// Encodes `message` as one frame: its length as a `u32`, then itself.
+/// Fails rather than write a frame `decode_frame` would refuse.
+pub fn encode_frame(message: &Message) -> Result<Vec<u8>, EncodeError> {
+    let mut frame = vec![0; 4];
+    message.encode(&mut frame)?;
+
+    let len = frame.len() - 4;
+    if len > MAX_FRAME_LEN {
+        return Err(EncodeError::FrameTooLong(len));
+    }
+    frame[.. 4].copy_from_slice(&(len as u32).to_le_bytes());
+    Ok(frame)
+}
+
+/// Reads the first frame in `bytes`, along with how many bytes it took up.
+/// `Ok(None)` means the frame hasn't fully arrived yet.
+pub fn decode_frame(bytes: &[u8]) -> Result<Option<(Message, usize)>, DecodeError> {
+    let mut input = Reader::new(bytes);
+    let len = match u32::decode(&mut input) {
+        Ok(len) => len as usize,
+        Err(_) => return Ok(None),
+    };
+
+    if len > MAX_FRAME_LEN {
+        return Err(DecodeError::FrameTooLong(len));
+    }
+    if len > input.remaining() {
+        return Ok(None);
+    }
+
+    let mut body = Reader::new(input.take(len)?);
+    let message = Message::decode(&mut body)?;
+    if body.remaining() != 0 {
+        return Err(DecodeError::TrailingBytes(body.remaining()));
+    }
+
+    Ok(Some((message, 4 + len)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choice() -> ChoiceView {
+        ChoiceView {
+            id: 7,
+            player: Some(1),
+            prompt: "Discard two cards".to_owned(),
+            options: vec!["Goblin".to_owned(), "Forest ✓".to_owned(), String::new()],
+            min: 0,
+            max: 2,
+            reaction: true,
+        }
+    }
+
+    fn view() -> View {
+        View {
+            seat: 1,
+            turn: 12,
+            active: 0,
+            turn_left: Duration::new(3, 250_000_000),
+            banks: vec![Duration::from_secs(600), Duration::from_millis(1)],
+            conceded: vec![false, true],
+            choices: vec![choice()],
+        }
+    }
+
+    /// At least one of every message, command and event.
+    fn samples() -> Vec<Message> {
+        let events = vec![
+            Event::TurnStarted { turn: u64::MAX, player: 3 },
+            Event::Timeout(Timeout::Pass(0)),
+            Event::Timeout(Timeout::Concede(1)),
+            Event::Timeout(Timeout::Reaction(2)),
+            Event::Conceded { player: 1 },
+            Event::ChoiceRequested(choice()),
+            Event::ChoiceAnswered { choice: 7 },
+            Event::GameOver { winner: None },
+            Event::GameOver { winner: Some(0) },
+        ];
+
+        let mut messages = vec![
+            Message::Hello(Hello::ours("Player 1")),
+            Message::Welcome { seat: 1, players: 2 },
+            Message::Rejected(Incompatible::Protocol { ours: 1, theirs: 2 }),
+            Message::Rejected(Incompatible::Rules { ours: RULES_VERSION, theirs: Version { major: 1, minor: 0 } }),
+            Message::Rejected(Incompatible::Cards { ours: CARD_DATA_VERSION, theirs: Version { major: 0, minor: 9 } }),
+            Message::Command(Command::EndTurn),
+            Message::Command(Command::Concede),
+            Message::Command(Command::Answer { choice: 7, picks: vec![0, 2] }),
+            Message::Command(Command::Answer { choice: 8, picks: vec![] }),
+            Message::View(view()),
+            Message::Chat { from: 0, text: "gg".to_owned() },
+            Message::Error { code: ErrorCode::Malformed, message: "bad".to_owned() },
+            Message::Error { code: ErrorCode::NotYourTurn, message: String::new() },
+            Message::Error { code: ErrorCode::NoSuchChoice, message: String::new() },
+            Message::Error { code: ErrorCode::Internal, message: String::new() },
+            Message::Error { code: ErrorCode::Refused, message: "that seat has forfeited".to_owned() },
+            Message::Rejoin { seat: 0, last_seen: None },
+            Message::Rejoin { seat: 1, last_seen: Some(41) },
+            Message::Resync { view: view(), events: vec![] },
+            Message::Resync {
+                view: view(),
+                events: vec![
+                    Sequenced { seq: 42, event: Event::ChoiceAnswered { choice: 7 } },
+                    Sequenced { seq: 43, event: Event::Timeout(Timeout::Pass(1)) },
+                ],
+            },
+        ];
+
+        messages.extend(events.into_iter().enumerate().map(|(seq, event)| Message::Event { seq: seq as u64, event }));
+        messages
+    }
+
+    /// Which variant a message is. Has no catch-all, so that adding a
+    /// message without a sample fails to compile here first.
+    fn variant(message: &Message) -> usize {
+        match message {
+            Message::Hello(_) => 0,
+            Message::Welcome { .. } => 1,
+            Message::Rejected(_) => 2,
+            Message::Command(Command::EndTurn) => 3,
+            Message::Command(Command::Concede) => 4,
+            Message::Command(Command::Answer { .. }) => 5,
+            Message::Event { event: Event::TurnStarted { .. }, .. } => 6,
+            Message::Event { event: Event::Timeout(_), .. } => 7,
+            Message::Event { event: Event::Conceded { .. }, .. } => 8,
+            Message::Event { event: Event::ChoiceRequested(_), .. } => 9,
+            Message::Event { event: Event::ChoiceAnswered { .. }, .. } => 10,
+            Message::Event { event: Event::GameOver { .. }, .. } => 11,
+            Message::View(_) => 12,
+            Message::Chat { .. } => 13,
+            Message::Error { .. } => 14,
+            Message::Rejoin { .. } => 15,
+            Message::Resync { .. } => 16,
+        }
+    }
+
+    #[test]
+    fn samples_cover_every_variant() {
+        let mut covered = samples().iter().map(variant).collect::<Vec<_>>();
+        covered.sort();
+        covered.dedup();
+        assert_eq!(covered, (0 ..= 16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for message in samples() {
+            let frame = encode_frame(&message).unwrap();
+            assert_eq!(decode_frame(&frame), Ok(Some((message.clone(), frame.len()))), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn frames_decode_one_at_a_time() {
+        let messages = samples();
+        let stream = messages.iter().flat_map(|message| encode_frame(message).unwrap()).collect::<Vec<_>>();
+
+        let mut rest = &stream[..];
+        for message in &messages {
+            let (decoded, len) = decode_frame(rest).unwrap().unwrap();
+            assert_eq!(&decoded, message);
+            rest = &rest[len ..];
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more() {
+        for message in samples() {
+            let frame = encode_frame(&message).unwrap();
+            for len in 0 .. frame.len() {
+                assert_eq!(decode_frame(&frame[.. len]), Ok(None));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_overlong_frames_and_leftovers() {
+        let len = (MAX_FRAME_LEN as u32 + 1).to_le_bytes();
+        assert_eq!(decode_frame(&len), Err(DecodeError::FrameTooLong(MAX_FRAME_LEN + 1)));
+
+        let mut frame = encode_frame(&Message::Command(Command::EndTurn)).unwrap();
+        frame.push(0);
+        frame[0] += 1;
+        assert_eq!(decode_frame(&frame), Err(DecodeError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn refuses_to_encode_what_wont_fit() {
+        let timeout = Message::Event { seq: 0, event: Event::Timeout(Timeout::Pass(256)) };
+        assert_eq!(encode_frame(&timeout), Err(EncodeError::OutOfRange("timeout player")));
+
+        let chat = Message::Chat { from: 0, text: "x".repeat(MAX_FRAME_LEN) };
+        assert!(matches!(encode_frame(&chat), Err(EncodeError::FrameTooLong(_))));
+
+        let request = ChoiceRequest {
+            prompt: String::new(),
+            options: vec![],
+            min: 0,
+            max: 0,
+            player: Some(300),
+            reaction: false,
+        };
+        assert_eq!(ChoiceView::new(0, &request), Err(EncodeError::OutOfRange("choice player")));
+    }
+
+    /// Garbage and corrupted frames are errors, never panics or huge allocations.
+    #[test]
+    fn survives_garbage() {
+        // xorshift, so the test is the same every run.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let frames = samples().iter().map(|message| encode_frame(message).unwrap()).collect::<Vec<_>>();
+
+        for _ in 0 .. 10_000 {
+            let mut bytes = frames[random() as usize % frames.len()].clone();
+            for _ in 0 .. 1 + random() % 4 {
+                let at = 4 + random() as usize % (bytes.len() - 4);
+                bytes[at] = random() as u8;
+            }
+            let _ = decode_frame(&bytes);
+
+            let garbage = (0 .. random() % 64).map(|_| random() as u8).collect::<Vec<_>>();
+            let _ = decode_frame(&garbage);
+        }
+    }
+
+    #[test]
+    fn handshake_checks_versions() {
+        let ours = Hello::ours("a");
+
+        assert_eq!(ours.check(&Hello::ours("b")), Ok(()));
+
+        let theirs = Hello { protocol: PROTOCOL_VERSION + 1, ..Hello::ours("b") };
+        assert!(matches!(ours.check(&theirs), Err(Incompatible::Protocol { .. })));
+
+        let theirs = Hello { rules: Version { minor: RULES_VERSION.minor + 1, ..RULES_VERSION }, ..Hello::ours("b") };
+        assert!(matches!(ours.check(&theirs), Err(Incompatible::Rules { .. })));
+
+        let theirs = Hello { cards: Version { minor: CARD_DATA_VERSION.minor + 1, ..CARD_DATA_VERSION }, ..Hello::ours("b") };
+        assert!(matches!(ours.check(&theirs), Err(Incompatible::Cards { .. })));
+    }
+
+    #[test]
+    fn rules_past_1_0_only_need_the_same_major_version() {
+        let ours = Hello { rules: Version { major: 1, minor: 2 }, ..Hello::ours("a") };
+        let theirs = Hello { rules: Version { major: 1, minor: 5 }, ..Hello::ours("b") };
+        assert_eq!(ours.check(&theirs), Ok(()));
+    }
+}
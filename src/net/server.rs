@@ -0,0 +1,459 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::clock;
+use crate::hotseat::HotSeat;
+use crate::net::schema::{self, ChoiceView, Command, ErrorCode, Event, Hello, Message, View};
+use crate::net::session::{DisconnectPolicy, Link, Refused, Session, SessionConfig};
+use crate::render::camera::GimbalCamera;
+use crate::render::gui::Widget;
+use crate::script::effects::{ChoiceError, Effects};
+
+
+/// A client whose unsent messages pile up past this is too far behind to keep.
+const MAX_OUTBOX: usize = 4 * schema::MAX_FRAME_LEN;
+
+
+/// What to host, as given on the command line.
+pub struct HostConfig {
+    pub addr: SocketAddr,
+    pub session: SessionConfig,
+}
+
+impl HostConfig {
+    /// Reads `<address> [--keep-running] [--grace <seconds>]`.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        const USAGE: &str = "usage: host <address:port> [--keep-running] [--grace <seconds>]";
+
+        let addr = args.next()
+            .and_then(|addr| addr.parse().ok())
+            .ok_or_else(|| USAGE.to_owned())?;
+
+        let mut session = SessionConfig::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--keep-running" => session.policy = DisconnectPolicy::KeepRunning,
+                "--grace" => {
+                    session.grace = args.next()
+                        .and_then(|secs| secs.parse().ok())
+                        .map(Duration::from_secs)
+                        .ok_or_else(|| USAGE.to_owned())?;
+                },
+                _ => return Err(format!("Unknown argument: {}\n{}", arg, USAGE)),
+            }
+        }
+
+        Ok(Self { addr, session })
+    }
+}
+
+
+struct Client {
+    stream: TcpStream,
+    peer: SocketAddr,
+    inbox: Vec<u8>,
+    outbox: Vec<u8>,
+    /// Whether its hello has been checked and accepted.
+    greeted: bool,
+    seat: Option<usize>,
+    /// Dropped once whatever's in `outbox` has had a chance to go out.
+    closed: bool,
+}
+
+impl Client {
+    fn send(&mut self, message: &Message) {
+        match schema::encode_frame(message) {
+            Ok(frame) => self.outbox.extend(frame),
+            Err(e) => eprintln!("Failed to send {} a message: {}", self.peer, e),
+        }
+    }
+
+    /// Reads whatever has arrived, without waiting for more.
+    /// Returns false once the connection is gone.
+    fn receive(&mut self) -> bool {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(len) => self.inbox.extend_from_slice(&buffer[.. len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Writes as much of the outbox as the connection takes without waiting.
+    /// Returns false once the connection is gone, or too far behind.
+    fn flush(&mut self) -> bool {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return false,
+                Ok(len) => { self.outbox.drain(.. len); },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return false,
+            }
+        }
+        self.outbox.len() <= MAX_OUTBOX
+    }
+}
+
+
+/// Hosts the match for players on other machines.
+///
+/// The first seat is played here; clients take the others by sending a
+/// hello, then a `Rejoin` for the seat they want. Until every seat is
+/// taken, play holds. After that, whoever drops out gets the session's
+/// grace period to come back with another `Rejoin`, while play holds or
+/// runs on according to its policy, and concedes once it's up.
+pub struct Server {
+    listener: TcpListener,
+    name: String,
+    clients: Vec<Client>,
+    session: Session,
+    // What's been announced so far, to tell what's changed since.
+    turn: u64,
+    conceded: Vec<bool>,
+    choices: Vec<u64>,
+    over: bool,
+}
+
+impl Server {
+    /// Starts listening for clients to take every seat but the first.
+    pub fn bind(config: &HostConfig, hotseat: &mut HotSeat) -> io::Result<Self> {
+        let seats = hotseat.seats().len();
+        // The schema numbers seats, and counts them, with a byte.
+        if seats > usize::from(u8::MAX) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many seats to host"));
+        }
+
+        let listener = TcpListener::bind(config.addr)?;
+        listener.set_nonblocking(true)?;
+        eprintln!("Hosting on {}", listener.local_addr()?);
+
+        let mut session = Session::new(seats, config.session);
+        for seat in 1 .. seats {
+            session.open(seat);
+            hotseat.set_remote(seat);
+        }
+
+        Ok(Self {
+            listener,
+            name: hotseat.seats()[0].name.to_str().to_owned(),
+            clients: Vec::new(),
+            session,
+            turn: 0,
+            conceded: vec![false; seats],
+            choices: Vec::new(),
+            over: false,
+        })
+    }
+
+    /// Logs `event`, and sends it to every seat allowed to see it.
+    pub fn record(&mut self, event: Event) {
+        let seq = self.session.record(event);
+        for client in &mut self.clients {
+            if let Some(message) = client.seat.and_then(|seat| self.session.announce(seq, seat)) {
+                client.send(&message);
+            }
+        }
+    }
+
+    /// Takes in new clients and whatever they've sent, applies the
+    /// disconnect policy, and announces what's happened since the last
+    /// call. `dt` is the time since then.
+    pub fn poll(
+        &mut self,
+        dt: Duration,
+        lua: &rlua::Lua,
+        hotseat: &mut HotSeat,
+        effects: &Effects,
+        camera: &mut GimbalCamera,
+    ) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => self.accept(stream, peer),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    eprintln!("Failed to accept a client: {}", e);
+                    break;
+                },
+            }
+        }
+
+        for i in 0 .. self.clients.len() {
+            let connected = self.clients[i].receive();
+
+            while !self.clients[i].closed {
+                let (message, len) = match schema::decode_frame(&self.clients[i].inbox) {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(e) => {
+                        // There's no telling where the next frame starts.
+                        self.clients[i].send(&error(ErrorCode::Malformed, e.to_string()));
+                        self.clients[i].closed = true;
+                        break;
+                    },
+                };
+                self.clients[i].inbox.drain(.. len);
+
+                if let Err((code, why)) = self.handle(i, message, lua, hotseat, effects, camera) {
+                    self.clients[i].send(&error(code, why));
+                }
+            }
+
+            if !connected {
+                self.clients[i].closed = true;
+            }
+        }
+
+        for seat in self.session.tick(dt) {
+            eprintln!("Player {} was gone too long, and forfeits.", seat + 1);
+            hotseat.concede(seat, camera);
+        }
+        hotseat.set_held(self.session.is_paused());
+
+        self.sync(hotseat, effects);
+
+        for client in &mut self.clients {
+            if !client.flush() {
+                client.closed = true;
+            }
+        }
+
+        let session = &mut self.session;
+        self.clients.retain(|client| {
+            if client.closed {
+                if let Some(seat) = client.seat {
+                    eprintln!("Player {} ({}) dropped out.", seat + 1, client.peer);
+                    session.disconnect(seat);
+                }
+            }
+            !client.closed
+        });
+    }
+
+    fn accept(&mut self, stream: TcpStream, peer: SocketAddr) {
+        if let Err(e) = stream.set_nonblocking(true) {
+            eprintln!("Failed to accept {}: {}", peer, e);
+            return;
+        }
+        let _ = stream.set_nodelay(true);
+
+        let mut client = Client {
+            stream,
+            peer,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+            greeted: false,
+            seat: None,
+            closed: false,
+        };
+        client.send(&Message::Hello(Hello::ours(self.name.as_str())));
+        self.clients.push(client);
+    }
+
+    fn handle(
+        &mut self,
+        i: usize,
+        message: Message,
+        lua: &rlua::Lua,
+        hotseat: &mut HotSeat,
+        effects: &Effects,
+        camera: &mut GimbalCamera,
+    ) -> Result<(), (ErrorCode, String)> {
+        let malformed = |what: &str| (ErrorCode::Malformed, what.to_owned());
+
+        if !self.clients[i].greeted {
+            let theirs = match message {
+                Message::Hello(theirs) => theirs,
+                _ => {
+                    self.clients[i].closed = true;
+                    return Err(malformed("expected a hello first"));
+                },
+            };
+
+            if let Err(why) = Hello::ours(self.name.as_str()).check(&theirs) {
+                eprintln!("Turned away {} ({}): {}", theirs.name, self.clients[i].peer, why);
+                self.clients[i].send(&Message::Rejected(why));
+                self.clients[i].closed = true;
+                return Ok(());
+            }
+
+            eprintln!("{} ({}) connected.", theirs.name, self.clients[i].peer);
+            self.clients[i].greeted = true;
+            return Ok(());
+        }
+
+        let (seat, message) = match (self.clients[i].seat, message) {
+            (Some(seat), message) => (seat, message),
+            (None, Message::Rejoin { seat, last_seen }) =>
+                return self.seat(i, usize::from(seat), last_seen, hotseat, effects),
+            (None, _) => return Err(malformed("take a seat first")),
+        };
+
+        match message {
+            Message::Command(Command::EndTurn) => {
+                if seat != hotseat.active_index() || hotseat.is_handing_off() || hotseat.is_over() {
+                    return Err((ErrorCode::NotYourTurn, "it isn't your turn".to_owned()));
+                }
+                hotseat.end_turn(camera);
+            },
+
+            Message::Command(Command::Concede) =>
+                hotseat.concede(seat, camera),
+
+            Message::Command(Command::Answer { choice, picks }) => {
+                let request = effects.pending().into_iter()
+                    .find(|(id, _)| *id == choice)
+                    .map(|(_, request)| request)
+                    .ok_or_else(|| (ErrorCode::NoSuchChoice, ChoiceError::NoSuchChoice(choice).to_string()))?;
+
+                if request.player.unwrap_or_else(|| hotseat.active_index()) != seat {
+                    return Err((ErrorCode::NotYourTurn, "that choice isn't yours to make".to_owned()));
+                }
+
+                let picks = picks.iter().map(|&pick| pick as usize).collect::<Vec<_>>();
+                effects.answer(lua, choice, &picks).map_err(|e| (ErrorCode::Malformed, e.to_string()))?;
+            },
+
+            Message::Chat { text, .. } => {
+                let chat = Message::Chat { from: seat as u8, text };
+                for client in self.clients.iter_mut().filter(|client| client.seat.is_some()) {
+                    client.send(&chat);
+                }
+            },
+
+            _ => return Err(malformed("only commands and chat are expected from a seated client")),
+        }
+
+        Ok(())
+    }
+
+    /// Gives client `i` the seat it asked for, along with what it missed.
+    fn seat(
+        &mut self,
+        i: usize,
+        seat: usize,
+        last_seen: Option<u64>,
+        hotseat: &HotSeat,
+        effects: &Effects,
+    ) -> Result<(), (ErrorCode, String)> {
+        if seat >= self.session.seats() {
+            return Err((ErrorCode::Refused, Refused::NoSuchSeat.to_string()));
+        }
+
+        let resync = self.session.rejoin(seat, last_seen, view(seat, hotseat, effects))
+            .map_err(|why| (ErrorCode::Refused, why.to_string()))?;
+
+        eprintln!("Player {} ({}) took their seat.", seat + 1, self.clients[i].peer);
+
+        let players = self.session.seats() as u8;
+        let client = &mut self.clients[i];
+        client.seat = Some(seat);
+        client.send(&Message::Welcome { seat: seat as u8, players });
+        client.send(&resync);
+        Ok(())
+    }
+
+    /// Announces whatever's happened in the match since the last call.
+    fn sync(&mut self, hotseat: &HotSeat, effects: &Effects) {
+        if hotseat.turn() != self.turn {
+            self.turn = hotseat.turn();
+            self.record(Event::TurnStarted { turn: self.turn, player: hotseat.active_index() as u8 });
+
+            // The turn's clock starts over, so everyone gets a fresh look at it.
+            for client in &mut self.clients {
+                if let Some(seat) = client.seat {
+                    client.send(&Message::View(view(seat, hotseat, effects)));
+                }
+            }
+        }
+
+        for (i, seat) in hotseat.seats().iter().enumerate() {
+            if seat.conceded && !self.conceded[i] {
+                self.conceded[i] = true;
+                self.record(Event::Conceded { player: i as u8 });
+            }
+        }
+
+        let pending = effects.pending();
+
+        let answered = self.choices.iter()
+            .copied()
+            .filter(|choice| !pending.iter().any(|(id, _)| id == choice))
+            .collect::<Vec<_>>();
+        for choice in answered {
+            self.record(Event::ChoiceAnswered { choice });
+        }
+
+        for (id, request) in &pending {
+            if self.choices.contains(id) {
+                continue;
+            }
+            match ChoiceView::new(*id, request) {
+                Ok(choice) => self.record(Event::ChoiceRequested(choice)),
+                Err(e) => eprintln!("No client will see choice #{}: {}", id, e),
+            }
+        }
+        self.choices = pending.iter().map(|(id, _)| *id).collect();
+
+        if hotseat.is_over() && !self.over {
+            self.over = true;
+            self.record(Event::GameOver { winner: hotseat.winner().map(|winner| winner as u8) });
+        }
+    }
+}
+
+
+/// How the match looks from `seat`. Seat numbers fit a byte, as `Server::bind` checks.
+fn view(seat: usize, hotseat: &HotSeat, effects: &Effects) -> View {
+    View {
+        seat: seat as u8,
+        turn: hotseat.turn(),
+        active: hotseat.active_index() as u8,
+        turn_left: hotseat.clock.turn_left(),
+        banks: (0 .. hotseat.seats().len()).map(|i| hotseat.clock.bank(i)).collect(),
+        conceded: hotseat.seats().iter().map(|seat| seat.conceded).collect(),
+        // The same choices the session lets `seat` see.
+        choices: effects.pending().iter()
+            .filter(|(_, request)| request.player.map_or(true, |player| player == seat))
+            .filter_map(|(id, request)| ChoiceView::new(*id, request).ok())
+            .collect(),
+    }
+}
+
+fn error(code: ErrorCode, message: String) -> Message {
+    Message::Error { code, message }
+}
+
+
+impl Widget for Server {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+        use imgui::*;
+
+        let window = Window::new(im_str!("Players"))
+            .size([240.0, 100.0], Condition::FirstUseEver)
+            .begin(&ui);
+
+        if let Some(window) = window {
+            for seat in 0 .. self.session.seats() {
+                let link = match self.session.link(seat) {
+                    Link::Open => "waiting to join".to_owned(),
+                    Link::Connected => "connected".to_owned(),
+                    Link::Dropped { away } => format!("dropped for {}", clock::display(away)),
+                    Link::Forfeited => "forfeited".to_owned(),
+                };
+                ui.text(format!("Player {}  {}", seat + 1, link));
+            }
+
+            if self.session.is_paused() {
+                ui.text_disabled("Play holds until everyone is here.");
+            }
+
+            window.end(&ui);
+        }
+    }
+}
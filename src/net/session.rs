@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use crate::net::schema::{Event, Message, Sequenced, View};
+
+
+/// What the match does while someone's connection is down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DisconnectPolicy {
+    /// Everyone waits, clocks and all, until they're back or out of grace.
+    Pause,
+    /// Play goes on, and the dropped player's clock keeps running.
+    KeepRunning,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SessionConfig {
+    pub policy: DisconnectPolicy,
+    /// How long a player can be gone before forfeiting.
+    pub grace: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            policy: DisconnectPolicy::Pause,
+            grace: Duration::from_secs(120),
+        }
+    }
+}
+
+
+/// How a seat's connection stands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// Nobody has taken the seat yet.
+    Open,
+    Connected,
+    /// Gone for `away` so far.
+    Dropped { away: Duration },
+    /// Gone for longer than the grace period; the seat won't come back.
+    Forfeited,
+}
+
+/// Why a seat couldn't be rejoined.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Refused {
+    NoSuchSeat,
+    AlreadyConnected,
+    Forfeited,
+    /// The client claims to have seen events which haven't happened.
+    UnknownEvent(u64),
+}
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Refused::NoSuchSeat => write!(f, "there's no such seat"),
+            Refused::AlreadyConnected => write!(f, "that seat is already connected"),
+            Refused::Forfeited => write!(f, "that seat has forfeited"),
+            Refused::UnknownEvent(seq) => write!(f, "event {} hasn't happened yet", seq),
+        }
+    }
+}
+
+
+/// The server's record of who's connected, and of everything that's happened
+/// in the match, so that a dropped client can pick up where it left off.
+pub struct Session {
+    config: SessionConfig,
+    links: Vec<Link>,
+    /// Every event so far, indexed by sequence number.
+    log: Vec<Event>,
+}
+
+impl Session {
+    pub fn new(seats: usize, config: SessionConfig) -> Self {
+        Self {
+            config,
+            links: vec![Link::Connected; seats],
+            log: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn link(&self, seat: usize) -> Link {
+        self.links[seat]
+    }
+
+    #[inline]
+    pub fn seats(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Leaves `seat` for a client to take, as it would be for one which dropped.
+    /// It has no grace period, since nobody there has been waited on yet.
+    pub fn open(&mut self, seat: usize) {
+        self.links[seat] = Link::Open;
+    }
+
+    /// Whether play should hold still, because a seat hasn't been taken
+    /// yet, or someone is away under `Pause`.
+    pub fn is_paused(&self) -> bool {
+        self.links.iter().any(|link| match link {
+            Link::Open => true,
+            Link::Dropped { .. } => self.config.policy == DisconnectPolicy::Pause,
+            _ => false,
+        })
+    }
+
+    /// Logs `event`, and returns its sequence number.
+    pub fn record(&mut self, event: Event) -> u64 {
+        self.log.push(event);
+        self.log.len() as u64 - 1
+    }
+
+    /// The message announcing event `seq` to `seat`, if `seat` is connected
+    /// and allowed to see it.
+    pub fn announce(&self, seq: u64, seat: usize) -> Option<Message> {
+        let event = &self.log[seq as usize];
+        if self.links[seat] != Link::Connected || !visible(event, seat) {
+            return None;
+        }
+        Some(Message::Event { seq, event: event.clone() })
+    }
+
+    pub fn disconnect(&mut self, seat: usize) {
+        if self.links[seat] == Link::Connected {
+            self.links[seat] = Link::Dropped { away: Duration::from_secs(0) };
+        }
+    }
+
+    /// Runs down the grace period of everyone who's away, and returns the
+    /// seats which just ran out of it. The caller concedes for them.
+    pub fn tick(&mut self, dt: Duration) -> Vec<usize> {
+        let grace = self.config.grace;
+        let mut forfeited = Vec::new();
+
+        for (seat, link) in self.links.iter_mut().enumerate() {
+            if let Link::Dropped { away } = link {
+                *away += dt;
+                if *away >= grace {
+                    *link = Link::Forfeited;
+                    forfeited.push(seat);
+                }
+            }
+        }
+
+        forfeited
+    }
+
+    /// Gives `seat` to a client which has seen every event up to
+    /// `last_seen`, and tells it what it missed. `view` is how the match
+    /// looks to `seat` now. Taking an open seat works the same way.
+    pub fn rejoin(&mut self, seat: usize, last_seen: Option<u64>, view: View) -> Result<Message, Refused> {
+        match self.links.get(seat) {
+            None => return Err(Refused::NoSuchSeat),
+            Some(Link::Connected) => return Err(Refused::AlreadyConnected),
+            Some(Link::Forfeited) => return Err(Refused::Forfeited),
+            Some(Link::Open) | Some(Link::Dropped { .. }) => {},
+        }
+
+        let from = match last_seen {
+            None => 0,
+            Some(seq) if seq < self.log.len() as u64 => seq as usize + 1,
+            Some(seq) => return Err(Refused::UnknownEvent(seq)),
+        };
+
+        let events = self.log[from ..].iter()
+            .enumerate()
+            .filter(|(_, event)| visible(event, seat))
+            .map(|(i, event)| Sequenced { seq: (from + i) as u64, event: event.clone() })
+            .collect();
+
+        self.links[seat] = Link::Connected;
+
+        Ok(Message::Resync { view, events })
+    }
+}
+
+/// Whether `seat` gets to see `event`. Choices put to one player are
+/// theirs alone, since their options can give away hidden cards.
+fn visible(event: &Event, seat: usize) -> bool {
+    match event {
+        Event::ChoiceRequested(choice) => choice.player.map_or(true, |player| player as usize == seat),
+        _ => true,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::schema::ChoiceView;
+
+    fn view(seat: u8) -> View {
+        View {
+            seat,
+            turn: 0,
+            active: 0,
+            turn_left: Duration::from_secs(30),
+            banks: vec![Duration::from_secs(600); 2],
+            conceded: vec![false; 2],
+            choices: vec![],
+        }
+    }
+
+    fn choice_for(player: Option<u8>) -> Event {
+        Event::ChoiceRequested(ChoiceView {
+            id: 1,
+            player,
+            prompt: "Pick a card".to_owned(),
+            options: vec!["Goblin".to_owned()],
+            min: 1,
+            max: 1,
+            reaction: false,
+        })
+    }
+
+    fn seqs(message: Message) -> Vec<u64> {
+        match message {
+            Message::Resync { events, .. } => events.iter().map(|e| e.seq).collect(),
+            other => panic!("Expected a resync, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejoining_replays_what_was_missed() {
+        let mut session = Session::new(2, SessionConfig::default());
+        session.record(Event::TurnStarted { turn: 0, player: 0 });
+        session.record(Event::ChoiceAnswered { choice: 0 });
+        session.disconnect(1);
+        session.record(Event::TurnStarted { turn: 1, player: 1 });
+        session.record(Event::Conceded { player: 0 });
+
+        assert_eq!(session.announce(3, 1), None);
+        assert!(session.announce(3, 0).is_some());
+
+        let resync = session.rejoin(1, Some(1), view(1)).unwrap();
+        assert_eq!(seqs(resync), vec![2, 3]);
+        assert_eq!(session.link(1), Link::Connected);
+    }
+
+    #[test]
+    fn rejoining_from_scratch_replays_everything() {
+        let mut session = Session::new(2, SessionConfig::default());
+        session.record(Event::TurnStarted { turn: 0, player: 0 });
+        session.disconnect(0);
+
+        assert_eq!(seqs(session.rejoin(0, None, view(0)).unwrap()), vec![0]);
+    }
+
+    #[test]
+    fn other_players_choices_stay_hidden() {
+        let mut session = Session::new(2, SessionConfig::default());
+        session.record(choice_for(Some(0)));
+        session.record(choice_for(Some(1)));
+        session.record(choice_for(None));
+
+        assert_eq!(session.announce(0, 1), None);
+        session.disconnect(1);
+        assert_eq!(seqs(session.rejoin(1, None, view(1)).unwrap()), vec![1, 2]);
+    }
+
+    #[test]
+    fn pauses_only_under_the_pause_policy() {
+        let mut paused = Session::new(2, SessionConfig::default());
+        assert!(!paused.is_paused());
+        paused.disconnect(1);
+        assert!(paused.is_paused());
+        paused.rejoin(1, None, view(1)).unwrap();
+        assert!(!paused.is_paused());
+
+        let config = SessionConfig { policy: DisconnectPolicy::KeepRunning, ..SessionConfig::default() };
+        let mut running = Session::new(2, config);
+        running.disconnect(1);
+        assert!(!running.is_paused());
+    }
+
+    #[test]
+    fn open_seats_hold_play_until_taken() {
+        let config = SessionConfig { policy: DisconnectPolicy::KeepRunning, grace: Duration::from_secs(10) };
+        let mut session = Session::new(2, config);
+        session.record(Event::TurnStarted { turn: 0, player: 0 });
+        session.open(1);
+
+        assert!(session.is_paused());
+        assert_eq!(session.announce(0, 1), None);
+        assert_eq!(session.tick(Duration::from_secs(60)), vec![]);
+
+        assert_eq!(seqs(session.rejoin(1, None, view(1)).unwrap()), vec![0]);
+        assert!(!session.is_paused());
+    }
+
+    #[test]
+    fn running_out_of_grace_forfeits() {
+        let config = SessionConfig { grace: Duration::from_secs(10), ..SessionConfig::default() };
+        let mut session = Session::new(2, config);
+        session.disconnect(0);
+
+        assert_eq!(session.tick(Duration::from_secs(6)), vec![]);
+        assert_eq!(session.link(0), Link::Dropped { away: Duration::from_secs(6) });
+        assert_eq!(session.tick(Duration::from_secs(6)), vec![0]);
+        assert_eq!(session.tick(Duration::from_secs(6)), vec![]);
+
+        assert!(!session.is_paused());
+        assert_eq!(session.rejoin(0, None, view(0)), Err(Refused::Forfeited));
+    }
+
+    #[test]
+    fn refuses_bad_rejoins() {
+        let mut session = Session::new(2, SessionConfig::default());
+        session.record(Event::TurnStarted { turn: 0, player: 0 });
+
+        assert_eq!(session.rejoin(0, None, view(0)), Err(Refused::AlreadyConnected));
+        assert_eq!(session.rejoin(2, None, view(2)), Err(Refused::NoSuchSeat));
+
+        session.disconnect(0);
+        assert_eq!(session.rejoin(0, Some(1), view(0)), Err(Refused::UnknownEvent(1)));
+        assert_eq!(session.link(0), Link::Dropped { away: Duration::from_secs(0) });
+    }
+}
@@ -6,14 +6,20 @@ pub mod gui;
 pub mod cache;
 pub mod core;
 pub mod camera;
+pub mod reflect;
+pub mod layout;
+pub mod compute;
+#[cfg(feature = "shaderc")]
+pub mod precompile;
 
 pub use self::core::*;
 pub use self::cache::{
-    shaders::ShaderCache,
+    shaders::{ShaderCache, ShaderCacheEntry, ShaderError, ShaderName},
     textures::TextureCache,
     models::ModelCache,
 };
 
+use crate::render::reflect::ShaderInterface;
 use crate::util::{self, bytes};
 
 
@@ -107,33 +113,37 @@ pub type InputHandle<'p, P> = Handle<'p, Input<'p, P>>;
 pub type OutputHandle<'p, P> = Handle<'p, Output<'p, P>>;
 
 
-pub struct Uniform<T> {
+/// A uniform buffer, holding `T` as its shaders' block sees it.
+pub struct Uniform<T: layout::AsBlock> {
     buffer: wgpu::Buffer,
+    fields: Vec<layout::Field>,
     data: T,
 }
 
-impl<T> std::ops::Deref for Uniform<T> {
+impl<T: layout::AsBlock> std::ops::Deref for Uniform<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         &self.data
     }
 }
 
-impl<T> std::ops::DerefMut for Uniform<T> {
+impl<T: layout::AsBlock> std::ops::DerefMut for Uniform<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.data
     }
 }
 
-impl<T: Sized + bytes::IntoBytes> Uniform<T> {
+impl<T: layout::AsBlock> Uniform<T> {
     pub fn new(device: &wgpu::Device, data: T) -> Self {
         let buffer = device.create_buffer_with_data(
-            bytes::of(&data),
+            bytes::of(&data.as_block()),
             wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST
         );
 
+        let fields = <T::Block as layout::Block>::fields();
+
         Self {
-            buffer, data,
+            buffer, fields, data,
         }
     }
 
@@ -141,26 +151,21 @@ impl<T: Sized + bytes::IntoBytes> Uniform<T> {
         core.queue.write_buffer(
             &self.buffer,
             0 as wgpu::BufferAddress,
-            bytes::of(&self.data)
+            bytes::of(&self.data.as_block())
         );
     }
 
-    fn bind_type() -> wgpu::BindingType {
-        wgpu::BindingType::UniformBuffer {
-            dynamic: false,
-            min_binding_size: std::num::NonZeroU64::new(
-                std::mem::size_of::<T>() as u64
-            ),
+    fn bound(&self) -> reflect::BoundResource {
+        reflect::BoundResource::Buffer {
+            buffer: &self.buffer,
+            size: std::mem::size_of::<T::Block>() as u64,
+            fields: Some(&self.fields),
         }
     }
-
-    fn bind(&self) -> wgpu::BindingResource {
-        wgpu::BindingResource::Buffer(self.buffer.slice(..))
-    }
 }
 
 
-impl<'p, T: Sized + bytes::IntoBytes> Resource<'p> for Uniform<T> {
+impl<'p, T: layout::AsBlock> Resource<'p> for Uniform<T> {
     type Descriptor = wgpu::BindGroupLayout;
     type Handle = wgpu::BindGroup;
 }
@@ -188,6 +193,7 @@ impl<'p> Resource<'p> for wgpu::Sampler {
 
 pub struct AnyAttachment;
 
+#[derive(Copy, Clone)]
 pub enum AnyAttachmentDescriptor<'p> {
     TextureView(&'p wgpu::TextureDescriptor<'p>),
     SwapChain(&'p wgpu::SwapChainDescriptor),
@@ -227,25 +233,184 @@ impl<'p> Resource<'p> for AnyAttachment {
 
 
 
+/// What `BasicPass` shows, for checking the inputs to its lighting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Shaded,
+    Normals,
+    ViewNormals,
+    Albedo,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 4] = [
+        DebugView::Shaded,
+        DebugView::Normals,
+        DebugView::ViewNormals,
+        DebugView::Albedo,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            DebugView::Shaded => "Shaded",
+            DebugView::Normals => "Normals",
+            DebugView::ViewNormals => "View space normals",
+            DebugView::Albedo => "Albedo",
+        }
+    }
+
+    /// The permutation of basic.frag which draws this view.
+    pub fn frag(self) -> ShaderName {
+        const SHADED: ShaderName = ShaderName {
+            file: "basic.frag",
+            defines: &[("NORMAL_MAP", None), ("DEBUG_VIEW", Some("DEBUG_VIEW_SHADED"))],
+        };
+        const NORMALS: ShaderName = ShaderName {
+            file: "basic.frag",
+            defines: &[("NORMAL_MAP", None), ("DEBUG_VIEW", Some("DEBUG_VIEW_NORMALS"))],
+        };
+        const VIEW_NORMALS: ShaderName = ShaderName {
+            file: "basic.frag",
+            defines: &[("NORMAL_MAP", None), ("DEBUG_VIEW", Some("DEBUG_VIEW_VIEW_NORMALS"))],
+        };
+        const ALBEDO: ShaderName = ShaderName {
+            file: "basic.frag",
+            defines: &[("NORMAL_MAP", None), ("DEBUG_VIEW", Some("DEBUG_VIEW_ALBEDO"))],
+        };
+
+        match self {
+            DebugView::Shaded => SHADED,
+            DebugView::Normals => NORMALS,
+            DebugView::ViewNormals => VIEW_NORMALS,
+            DebugView::Albedo => ALBEDO,
+        }
+    }
+}
+
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::ViewNormals
+    }
+}
+
+
 pub struct BasicPass {
     pub camera: Uniform<camera::GimbalCamera>,
     pub project: Uniform<glm::Mat4>,
 
-    u_cam_group: wgpu::BindGroup,    
+    /// Takes effect on the next `refresh`.
+    pub debug_view: DebugView,
+
+    /// Unset while not even the fallback shaders fit, and the pass is skipped.
+    built: Option<(BasicBindings, wgpu::RenderPipeline, wgpu::Texture)>,
+}
+
+/// Everything `BasicPass` binds, laid out to match its shaders.
+struct BasicBindings {
+    layout: reflect::PipelineLayout,
+    u_cam_group: wgpu::BindGroup,
     u_tex_group: wgpu::BindGroup,
     u_norm_group: wgpu::BindGroup,
-
-    pipeline_layout: wgpu::PipelineLayout,
-    pub pipeline: wgpu::RenderPipeline,
-    pub zbuffer: wgpu::Texture,
 }
 
 
 impl BasicPass {
 
-    fn build_pipeline(core: &Core, target: AnyAttachmentDescriptor, layout: &wgpu::PipelineLayout)
-        -> (wgpu::RenderPipeline, wgpu::Texture)
+    pub const VERT: ShaderName = ShaderName::new("basic.vert");
+    pub const TEXTURES: [&'static str; 2] = ["gray_marble.tif", "gray_marble_normal.tif"];
+
+    pub fn shaders(debug_view: DebugView) -> [ShaderName; 2] {
+        [BasicPass::VERT, debug_view.frag()]
+    }
+
+    /// Every shader the pass could use, whichever view it's showing.
+    pub fn all_shaders() -> impl Iterator<Item = ShaderName> {
+        std::iter::once(BasicPass::VERT)
+            .chain(DebugView::ALL.iter().map(|view| view.frag()))
+    }
+
+    /// How many bind groups `perform` sets.
+    const BIND_GROUPS: u32 = 3;
+
+    fn texture_group(core: &Core, layout: &reflect::PipelineLayout, set: u32, name: &'static str, label: &str)
+        -> Result<wgpu::BindGroup, reflect::InterfaceError>
+    {
+        let tex = core.textures.load_or_fallback(name);
+        let view = tex.texture.create_default_view();
+
+        layout.bind_group(core.device, set, label, &[
+            (0, reflect::BoundResource::TextureView(&view)),
+            (1, reflect::BoundResource::Sampler(&tex.sampler)),
+        ])
+    }
+
+    /// Lays out the pipeline to match its (possibly freshly reloaded) shaders,
+    /// then binds the camera and the (likewise) textures to it.
+    fn build_bindings(core: &Core, shaders: &[&ShaderInterface], camera: &Uniform<camera::GimbalCamera>, project: &Uniform<glm::Mat4>)
+        -> Result<BasicBindings, reflect::InterfaceError>
+    {
+        let layout = reflect::PipelineLayout::new(core.device, "BasicPass", shaders, BasicPass::BIND_GROUPS)?;
+
+        let u_cam_group = layout.bind_group(core.device, 0, "Camera uniform", &[
+            (0, camera.bound()),
+            (1, project.bound()),
+        ])?;
+
+        let [tex_name, norm_name] = BasicPass::TEXTURES;
+        let u_tex_group = BasicPass::texture_group(core, &layout, 1, tex_name, "Texture uniform")?;
+        let u_norm_group = BasicPass::texture_group(core, &layout, 2, norm_name, "Normal map uniform")?;
+
+        Ok(BasicBindings {
+            layout,
+            u_cam_group,
+            u_tex_group,
+            u_norm_group,
+        })
+    }
+
+    /// Builds everything which depends on the shaders or textures.
+    fn build(core: &Core, target: AnyAttachmentDescriptor, debug_view: DebugView, camera: &Uniform<camera::GimbalCamera>, project: &Uniform<glm::Mat4>)
+        -> Result<(BasicBindings, wgpu::RenderPipeline, wgpu::Texture), ShaderError>
     {
+        let [vert_name, frag_name] = BasicPass::shaders(debug_view);
+
+        core.shaders.build_pipeline("BasicPass", vert_name, frag_name, |vert_module, frag_module| {
+            let bindings = BasicPass::build_bindings(core, &[&vert_module.interface, &frag_module.interface], camera, project)?;
+            let (pipeline, zbuffer) = BasicPass::build_pipeline(core, target, vert_module, frag_module, &bindings.layout)?;
+            Ok((bindings, pipeline, zbuffer))
+        })
+    }
+
+    fn build_pipeline(
+        core: &Core,
+        target: AnyAttachmentDescriptor,
+        vert_module: &ShaderCacheEntry,
+        frag_module: &ShaderCacheEntry,
+        layout: &reflect::PipelineLayout,
+    ) -> Result<(wgpu::RenderPipeline, wgpu::Texture), reflect::InterfaceError>
+    {
+        let vertex_buffers = [
+            // positions
+            wgpu::VertexBufferDescriptor {
+                attributes: &wgpu::vertex_attr_array![0 => Float3],
+                step_mode: wgpu::InputStepMode::Vertex,
+                stride: wgpu::vertex_format_size!(Float3),
+            },
+            // texcoords
+            wgpu::VertexBufferDescriptor {
+                attributes: &wgpu::vertex_attr_array![1 => Float2],
+                step_mode: wgpu::InputStepMode::Vertex,
+                stride: wgpu::vertex_format_size!(Float2),
+            },
+            // normals
+            wgpu::VertexBufferDescriptor {
+                attributes: &wgpu::vertex_attr_array![2 => Float3],
+                step_mode: wgpu::InputStepMode::Vertex,
+                stride: wgpu::vertex_format_size!(Float3),
+            },
+        ];
+
+        layout.check_vertex_buffers(&vertex_buffers)?;
 
         let zbuffer_desc = wgpu::TextureDescriptor {
             label: Some("BasicRenderer depth buffer"),
@@ -263,11 +428,8 @@ impl BasicPass {
 
         let zbuffer = core.device.create_texture(&zbuffer_desc);
 
-        let vert_module = core.shaders.load("basic.vert");
-        let frag_module = core.shaders.load("basic.frag");
-
         let render_descriptor = wgpu::RenderPipelineDescriptor {
-            layout: &layout,
+            layout: &layout.layout,
             
             vertex_stage: vert_module.descriptor(),
             fragment_stage: Some(frag_module.descriptor()),
@@ -311,26 +473,7 @@ impl BasicPass {
             
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint32,
-                vertex_buffers: &[
-                    // positions
-                    wgpu::VertexBufferDescriptor {
-                        attributes: &wgpu::vertex_attr_array![0 => Float3],
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        stride: wgpu::vertex_format_size!(Float3),
-                    }, 
-                    // texcoords
-                    wgpu::VertexBufferDescriptor {
-                        attributes: &wgpu::vertex_attr_array![1 => Float2],
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        stride: wgpu::vertex_format_size!(Float2),
-                    },
-                    // normals
-                    wgpu::VertexBufferDescriptor {
-                        attributes: &wgpu::vertex_attr_array![2 => Float3],
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        stride: wgpu::vertex_format_size!(Float3),
-                    },
-                ],
+                vertex_buffers: &vertex_buffers,
             },
             
             sample_count: 1,
@@ -340,7 +483,7 @@ impl BasicPass {
 
         let pipeline = core.device.create_render_pipeline(&render_descriptor);
 
-        (pipeline, zbuffer)
+        Ok((pipeline, zbuffer))
     }
 
 }
@@ -380,91 +523,16 @@ impl<'p> Pass<'p> for BasicPass {
             ),
         );
 
-        let u_cam_descriptor = wgpu::BindGroupLayoutDescriptor {
-            label: Some("Camera uniform"),
-            bindings: &[
-                wgpu::BindGroupLayoutEntry::new(
-                    0, wgpu::ShaderStage::all(),
-                    Uniform::<camera::GimbalCamera>::bind_type(),
-                ),
-                wgpu::BindGroupLayoutEntry::new(
-                    1, wgpu::ShaderStage::all(),
-                    Uniform::<glm::Mat4>::bind_type(),
-                ),
-            ],
-        };
-
-        let u_cam_layout = core.device.create_bind_group_layout(&u_cam_descriptor);
-
-        let u_cam_bind_desc = wgpu::BindGroupDescriptor {
-            label: Some("Camera uniform"),
-            layout: &u_cam_layout,
-            bindings: &[
-                wgpu::Binding { binding: 0, resource: camera.bind() },
-                wgpu::Binding { binding: 1, resource: project.bind() },
-            ],
-        };
-
-        let u_cam_group = core.device.create_bind_group(&u_cam_bind_desc);
-
-        let tex = core.textures.load("gray_marble.tif");
-        
-        let u_tex_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Texture uniform"),
-            layout: &tex.bind_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&tex.texture.create_default_view()),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&tex.sampler),
-                },
-            ],
-        });
-
-
-        let norm = core.textures.load("gray_marble_normal.tif");
-
-        let u_norm_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Normal map uniform"),
-            layout: &norm.bind_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&norm.texture.create_default_view()),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&norm.sampler),
-                },
-            ],
-        });
-
-        let layout_descriptor = wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[
-                &u_cam_layout,
-                &tex.bind_layout,
-                &norm.bind_layout,
-            ],
-        };
-
-        let layout = core.device.create_pipeline_layout(&layout_descriptor);
-
-        let (pipeline, zbuffer) = BasicPass::build_pipeline(core, target, &layout);
+        let debug_view = DebugView::default();
+        // Any error is already shown with the rest of the shaders'.
+        let built = BasicPass::build(core, target, debug_view, &camera, &project).ok();
 
         let pass = Self {
             camera,
             project,
-            
-            u_cam_group,
-            u_tex_group,
+            debug_view,
 
-            u_norm_group,
-            pipeline_layout: layout,
-            pipeline,
-            zbuffer,
+            built,
         };
 
         (pass, ())
@@ -474,16 +542,22 @@ impl<'p> Pass<'p> for BasicPass {
     fn perform(self: &'p mut Self, _: (), input: InputHandle<'p, Self>) -> OutputHandle<'p, Self> {
         let (core, target) = input;
 
+        let (bindings, pipeline, zbuffer) = match &self.built {
+            Some((bindings, pipeline, zbuffer)) => (bindings, pipeline, zbuffer),
+            None => return,
+        };
+
+        // Without the model, the pass still clears the screen.
         let model = core.models.load(cache::models::ModelName {
             file: "torus.obj",
             name: "Torus", // FIXME: This is a _terrible_ name...
-        });
+        }).ok();
 
         self.camera.refresh(core);
         self.project.refresh(core);
 
 
-        let zbuffer_view = self.zbuffer.create_default_view();
+        let zbuffer_view = zbuffer.create_default_view();
 
         let mut encoder = core.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Basic Pass"),
@@ -514,17 +588,19 @@ impl<'p> Pass<'p> for BasicPass {
             )
         });
 
-        pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, &self.u_cam_group, &[]);
-        pass.set_bind_group(1, &self.u_tex_group, &[]);
-        pass.set_bind_group(2, &self.u_norm_group, &[]);
-        
-        pass.set_index_buffer(model.indices.slice(..));
-        pass.set_vertex_buffer(0, model.positions.slice(..));
-        pass.set_vertex_buffer(1, model.texcoords.as_ref().unwrap().slice(..));
-        pass.set_vertex_buffer(2, model.normals.as_ref().unwrap().slice(..));
+        if let Some(model) = &model {
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bindings.u_cam_group, &[]);
+            pass.set_bind_group(1, &bindings.u_tex_group, &[]);
+            pass.set_bind_group(2, &bindings.u_norm_group, &[]);
+
+            pass.set_index_buffer(model.indices.slice(..));
+            pass.set_vertex_buffer(0, model.positions.slice(..));
+            pass.set_vertex_buffer(1, model.texcoords.as_ref().unwrap().slice(..));
+            pass.set_vertex_buffer(2, model.normals.as_ref().unwrap().slice(..));
 
-        pass.draw_indexed(0..model.vertex_ct, 0, 0..1);
+            pass.draw_indexed(0..model.vertex_ct, 0, 0..1);
+        }
 
         drop(pass); // end borrow
 
@@ -546,10 +622,8 @@ impl<'p> Pass<'p> for BasicPass {
                 100.0,
             );
   
-        let (pipeline, zbuffer) = BasicPass::build_pipeline(core, target, &self.pipeline_layout);
-
-        self.pipeline = pipeline;
-        self.zbuffer = zbuffer;
+        // The shaders and textures may have been reloaded since.
+        self.built = BasicPass::build(core, target, self.debug_view, &self.camera, &self.project).ok();
     }
 
 }
@@ -604,8 +678,62 @@ impl<'p> Pass<'p> for PrePass {
 
 
 pub struct PostPass {
-    pipeline: wgpu::RenderPipeline,
-    tex_group: wgpu::BindGroup,
+    /// Unset while not even the fallback shaders fit, and the pass is skipped.
+    built: Option<(wgpu::RenderPipeline, wgpu::BindGroup)>,
+}
+
+impl PostPass {
+    pub const SHADERS: [ShaderName; 2] = [ShaderName::new("post.vert"), ShaderName::new("post.frag")];
+
+    fn build_pipeline(
+        core: &Core,
+        schain: &wgpu::SwapChainDescriptor,
+        vert_module: &ShaderCacheEntry,
+        frag_module: &ShaderCacheEntry,
+        layout: &reflect::PipelineLayout,
+    ) -> wgpu::RenderPipeline
+    {
+        let render_desc = wgpu::RenderPipelineDescriptor {
+            layout: &layout.layout,
+            
+            vertex_stage: vert_module.descriptor(),
+            fragment_stage: Some(frag_module.descriptor()),
+            
+            rasterization_state: Some(Default::default()),
+            
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            
+            color_states: &[
+                wgpu::ColorStateDescriptor {
+                    format: schain.format,
+                    color_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha_blend: wgpu::BlendDescriptor {
+                        src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                },
+            ],
+            
+            depth_stencil_state: None,
+
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: &[]
+            },
+            
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        };
+
+        core.device.create_render_pipeline(&render_desc)
+    }
 }
 
 impl<'p> Pass<'p> for PostPass {
@@ -619,6 +747,11 @@ impl<'p> Pass<'p> for PostPass {
     fn perform(self: &'p mut Self, _: (), input: InputHandle<'p, Self>) -> OutputHandle<'p, Self> {
         let (core, target) = input;
 
+        let (pipeline, tex_group) = match &self.built {
+            Some((pipeline, tex_group)) => (pipeline, tex_group),
+            None => return,
+        };
+
         let mut encoder = core.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Postpass"),
         });
@@ -637,8 +770,8 @@ impl<'p> Pass<'p> for PostPass {
             ],
         });
 
-        pass.set_pipeline(&self.pipeline);
-        pass.set_bind_group(0, &self.tex_group, &[]);
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, tex_group, &[]);
         pass.draw(0..3, 0..1);
 
         drop(pass); // end borrow
@@ -651,23 +784,7 @@ impl<'p> Pass<'p> for PostPass {
     fn construct(texture: Self::Config, input: InputDesc<'p, Self>) -> (Self, OutputDesc<'p, Self>) {
         let (core, schain) = input;
 
-        let tex_layout = core.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Postpass input texture"),
-            bindings: &[
-                wgpu::BindGroupLayoutEntry::new(
-                    0, wgpu::ShaderStage::FRAGMENT,
-                    wgpu::BindingType::SampledTexture {
-                        dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                        component_type: wgpu::TextureComponentType::Float,
-                    },
-                ),
-                wgpu::BindGroupLayoutEntry::new(
-                    1, wgpu::ShaderStage::FRAGMENT,
-                    wgpu::BindingType::Sampler { comparison: false },
-                ),
-            ],
-        });
+        let [vert_name, frag_name] = PostPass::SHADERS;
 
         let sample_desc = wgpu::SamplerDescriptor {
             label: Some("Postpass sampler"),  
@@ -678,76 +795,25 @@ impl<'p> Pass<'p> for PostPass {
         };
 
         let sampler = core.device.create_sampler(&sample_desc);
+        let view = texture.create_default_view();
 
-        let tex_group = core.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Postpass bind group"),
-            layout: &tex_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture.create_default_view()),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                }
-            ],
-        });
-
-
-        let layout = core.device.create_pipeline_layout(
-            &wgpu::PipelineLayoutDescriptor {
-                bind_group_layouts: &[&tex_layout],
-            },
-        );
-
-        let vert_module = core.shaders.load("post.vert");
-        let frag_module = core.shaders.load("post.frag");
+        let built = core.shaders.build_pipeline("PostPass", vert_name, frag_name, |vert_module, frag_module| {
+            let layout = reflect::PipelineLayout::new(core.device, "Postpass", &[&vert_module.interface, &frag_module.interface], 1)?;
+            layout.check_vertex_buffers(&[])?;
 
-        let render_desc = wgpu::RenderPipelineDescriptor {
-            layout: &layout,
-            
-            vertex_stage: vert_module.descriptor(),
-            fragment_stage: Some(frag_module.descriptor()),
-            
-            rasterization_state: Some(Default::default()),
-            
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            
-            color_states: &[
-                wgpu::ColorStateDescriptor {
-                    format: schain.format,
-                    color_blend: wgpu::BlendDescriptor {
-                        src_factor: wgpu::BlendFactor::SrcAlpha,
-                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                        operation: wgpu::BlendOperation::Add,
-                    },
-                    alpha_blend: wgpu::BlendDescriptor {
-                        src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
-                        dst_factor: wgpu::BlendFactor::One,
-                        operation: wgpu::BlendOperation::Add,
-                    },
-                    write_mask: wgpu::ColorWrite::ALL,
-                },
-            ],
-            
-            depth_stencil_state: None,
+            let tex_group = layout.bind_group(core.device, 0, "Postpass bind group", &[
+                (0, reflect::BoundResource::TextureView(&view)),
+                (1, reflect::BoundResource::Sampler(&sampler)),
+            ])?;
 
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[]
-            },
-            
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        };
+            let pipeline = PostPass::build_pipeline(core, schain, vert_module, frag_module, &layout);
 
-        let pipeline = core.device.create_render_pipeline(&render_desc);
+            Ok((pipeline, tex_group))
+        });
 
+        // Any error is already shown with the rest of the shaders'.
         let pass = Self {
-            pipeline,
-            tex_group,
+            built: built.ok(),
         };
 
         (pass, ())
@@ -763,6 +829,18 @@ pub struct MainPass {
     pub post: PostPass,
 }
 
+impl MainPass {
+    /// Whether the pass needs refreshing to pick up `changed` assets.
+    ///
+    /// Models are looked up afresh every frame, so never need a refresh.
+    pub fn depends_on(changed: &ChangedAssets) -> bool {
+        changed.shaders.iter().any(|name| {
+            BasicPass::all_shaders().any(|basic| basic == *name) || PostPass::SHADERS.contains(name)
+        })
+        || changed.textures.iter().any(|name| BasicPass::TEXTURES.contains(name))
+    }
+}
+
 
 impl<'p> Pass<'p> for MainPass {
 
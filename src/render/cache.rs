@@ -3,12 +3,19 @@ pub mod shaders;
 pub mod textures;
 pub mod models;
 
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::Mutex;
+
 
 pub trait AssetCache<Asset> {
     type AssetName: 'static;
     type AssetRef: std::ops::Deref<Target=Asset>;
     
-    fn load(self, name: Self::AssetName) -> Self::AssetRef;
+    fn load(self, name: Self::AssetName) -> anyhow::Result<Self::AssetRef>;
     fn invalidate(self, name: Self::AssetName);
     fn clear(self);
 }
@@ -20,3 +27,51 @@ pub trait AssetCache<Asset> {
 
 
 
+
+
+/// The files an asset was built from, and when each was last modified,
+/// so that edits to any of them can be noticed.
+pub struct SourceFiles(Vec<(PathBuf, Option<SystemTime>)>);
+
+impl SourceFiles {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self(paths.into_iter()
+            .map(|path| {
+                let modified = modified_time(&path);
+                (path, modified)
+            })
+            .collect())
+    }
+
+    /// Whether any of the files has been modified, or has disappeared, since.
+    pub fn changed(&self) -> bool {
+        self.0.iter().any(|(path, modified)| modified_time(path) != *modified)
+    }
+}
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+
+/// Textures and models which currently fail to load, with why, shared
+/// with whatever displays them. Shaders have `shaders::ShaderErrors`.
+#[derive(Clone, Default)]
+pub struct AssetErrors(Arc<Mutex<BTreeMap<String, String>>>);
+
+impl AssetErrors {
+    /// Every outstanding error, as `(asset, error)`, ordered by asset name.
+    pub fn list(&self) -> Vec<(String, String)> {
+        self.0.lock().iter()
+            .map(|(name, error)| (name.clone(), error.clone()))
+            .collect()
+    }
+
+    fn insert(&self, name: &str, error: String) {
+        self.0.lock().insert(name.to_owned(), error);
+    }
+
+    fn remove(&self, name: &str) {
+        self.0.lock().remove(name);
+    }
+}
@@ -1,22 +1,27 @@
 
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use nalgebra as na;
 
-use crate::render::cache::AssetCache;
+use crate::render::cache::{AssetCache, AssetErrors, SourceFiles};
 use crate::render::bytes;
 
 use chashmap::CHashMap;
+use parking_lot::Mutex;
 
 
-fn load_model_file(name: &'static str) -> anyhow::Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
-    use std::path::PathBuf;
-
+fn model_path(name: &str) -> PathBuf {
     let mut path = PathBuf::from("assets/models");
     path.push(name);
+    path
+}
 
+fn load_model_file(path: &std::path::Path) -> anyhow::Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
     let path = path.canonicalize()?;
-    let data = tobj::load_obj(&path, false)?;
+    let (models, materials) = tobj::load_obj(&path, false)?;
 
-    Ok(data)
+    Ok((models, materials))
 }
 
 
@@ -30,6 +35,9 @@ pub struct ModelCache {
     device: &'static wgpu::Device,
     known_files: CHashMap<&'static str, ()>,
     obj_cache: CHashMap<String, ModelCacheEntry>,
+    // Keyed by file, like `known_files`.
+    sources: Mutex<HashMap<&'static str, SourceFiles>>,
+    errors: AssetErrors,
 }
 
 pub type ModelRef<'a> = chashmap::ReadGuard<'a, String, ModelCacheEntry>;
@@ -41,23 +49,71 @@ impl ModelCache {
     pub fn new(device: &'static wgpu::Device) -> Self {
         let known_files = CHashMap::new();
         let obj_cache = CHashMap::new();
+        let sources = Mutex::new(HashMap::new());
+        let errors = AssetErrors::default();
         Self {
             device,
             known_files,
             obj_cache,
+            sources,
+            errors,
+        }
+    }
+
+    /// The model files which failed the last time they were loaded.
+    pub fn errors(&self) -> AssetErrors {
+        self.errors.clone()
+    }
+
+    /// Invalidates every model file which has changed since it was loaded.
+    /// Returns the names of those files.
+    pub fn invalidate_changed(&self) -> Vec<&'static str> {
+        let changed = self.sources.lock().iter()
+            .filter(|(_, sources)| sources.changed())
+            .map(|(&file, _)| file)
+            .collect::<Vec<_>>();
+
+        for &file in &changed {
+            self.known_files.remove(file);
+            self.sources.lock().remove(file);
         }
+
+        changed
     }
 
-    pub fn load(&self, ModelName { file, name }: ModelName) -> ModelRef {
-        
-        if let Some(_) = self.known_files.get(file) {
-            return self.obj_cache.get(name).unwrap()
+    /// Loads model `name` from `file` in `assets/models`.
+    ///
+    /// If the file fails to load, the error is shown along with the
+    /// shaders', and the models from before the file changed are kept.
+    /// It isn't tried again until it changes.
+    pub fn load(&self, ModelName { file, name }: ModelName) -> anyhow::Result<ModelRef> {
+
+        if self.known_files.get(file).is_none() {
+            self.load_file(file);
         }
-        
-        let file_data = load_model_file(file)
-            .expect("Failed to load model file!");
-        
-        for model in file_data.0 {
+
+        self.obj_cache.get(name)
+            .ok_or_else(|| anyhow::anyhow!("there's no model {} in {}", name, file))
+    }
+
+    fn load_file(&self, file: &'static str) {
+
+        // Watched even if it fails to load, so that fixing it is noticed.
+        let path = model_path(file);
+        self.sources.lock().insert(file, SourceFiles::new(Some(path.clone())));
+        self.known_files.insert(file, ());
+
+        let models = match load_model_file(&path) {
+            Ok((models, _materials)) => models,
+            Err(e) => {
+                eprintln!("Failed to load model file {}: {}", file, e);
+                self.errors.insert(file, e.to_string());
+                return;
+            },
+        };
+        self.errors.remove(file);
+
+        for model in models {
 
             eprintln!("positions.len: {}\nindices.len: {}\ntexcoords.len: {}\nnormals.len: {}",
                 model.mesh.positions.len(),
@@ -114,9 +170,6 @@ impl ModelCache {
             eprintln!("loaded model name: {}, vertices: {}", &model.name, &cache_entry.vertex_ct);
             self.obj_cache.insert(model.name, cache_entry);
         }
-
-        self.known_files.insert(file, ());
-        self.obj_cache.get(name).unwrap()
     }
 
 }
@@ -137,16 +190,18 @@ impl<'a> AssetCache<ModelCacheEntry> for &'a ModelCache {
     type AssetName = ModelName;
     type AssetRef = ModelRef<'a>;
 
-    fn load(self, name: Self::AssetName) -> Self::AssetRef {
+    fn load(self, name: Self::AssetName) -> anyhow::Result<Self::AssetRef> {
         ModelCache::load(self, name)
     }
     
     fn invalidate(self, name: Self::AssetName) {
         self.known_files.remove(name.file);
+        self.sources.lock().remove(name.file);
     }
 
     fn clear(self) {
         self.known_files.clear();
         self.obj_cache.clear();
+        self.sources.lock().clear();
     }
 }
\ No newline at end of file
@@ -1,47 +1,63 @@
 
-use crate::render::cache::AssetCache;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::render::cache::{AssetCache, SourceFiles};
+use crate::render::reflect::{self, InterfaceError, ShaderInterface};
+#[cfg(feature = "shaderc")]
+use crate::util::bytes;
 
 use chashmap::CHashMap;
 use parking_lot::Mutex;
 
-// This is probably enough...?
-const MAX_INCLUDE_DEPTH: usize = 5;
+/// Where shader sources live, and where `#include <...>` looks.
+///
+/// GLSL stages go by their extension: `.vert`, `.frag` or `.comp`.
+/// WGSL files name their stage the same way, as in `post.frag.wgsl`,
+/// and have a single entry point, `main`.
+pub const SHADER_DIR: &str = "assets/shaders";
 
+/// Where compiled SPIR-V is kept between runs.
+#[cfg(feature = "shaderc")]
+const SPIRV_CACHE_DIR: &str = "assets/shaders/.cache";
 
-fn load_shader_file(name: &str, include_type: shaderc::IncludeType, containing_file: &str, include_depth: usize)
-    -> Result<shaderc::ResolvedInclude, String>
-{
-    use std::fs::File;
-    use std::path::PathBuf;
-    use std::io::{BufReader, Read};
+/// Bump this to invalidate every cached shader, e.g. when upgrading shaderc.
+#[cfg(feature = "shaderc")]
+const SPIRV_CACHE_VERSION: u32 = 1;
 
-    if include_depth > MAX_INCLUDE_DEPTH {
-        return Err(format!("#include of: {} in: {} exceeded max include depth ({})", name, containing_file, MAX_INCLUDE_DEPTH));
-    }
+/// Where `compile-shaders` puts the SPIR-V that builds without shaderc load.
+pub const PRECOMPILED_DIR: &str = "assets/shaders/compiled";
 
-    let path = match include_type {
-        shaderc::IncludeType::Standard => PathBuf::from("assets/shaders/"),
-        shaderc::IncludeType::Relative => PathBuf::from(containing_file),
-    };
+/// Where the fallback shaders' SPIR-V is kept, in the repository, since
+/// every build embeds it. `compile-shaders` regenerates it.
+pub const BUILTIN_DIR: &str = "assets/shaders/builtin";
 
-    let mut path = match path.canonicalize() {
-        Err(io_error) => return Err(format!("{}", io_error)),
-        Ok(path) => path,
-    };
+pub(crate) const SPIRV_MAGIC: u32 = 0x0723_0203;
 
-    path.push(name);
 
-    let file = match File::open(&path) {
-        Err(io_error) => return Err(format!("{}", io_error)),
-        Ok(file) => file,
+#[cfg(feature = "shaderc")]
+fn load_shader_file(name: &str, include_type: shaderc::IncludeType, containing_file: &str)
+    -> Result<shaderc::ResolvedInclude, String>
+{
+    let dir = match include_type {
+        shaderc::IncludeType::Standard => PathBuf::from(SHADER_DIR),
+        // Relative to the directory of the including file, not the file itself.
+        shaderc::IncludeType::Relative => Path::new(containing_file)
+            .parent()
+            .map(Path::to_owned)
+            .unwrap_or_default(),
     };
 
-    let mut buf_reader = BufReader::new(file);
-    let mut content = String::new();
+    // Canonical, so that the same file is always recognised as such.
+    let path = match dir.join(name).canonicalize() {
+        Err(io_error) => return Err(format!("{}: {}", dir.join(name).display(), io_error)),
+        Ok(path) => path,
+    };
 
-    match buf_reader.read_to_string(&mut content) {
+    let content = match std::fs::read_to_string(&path) {
         Err(_) => return Err(format!("File `{}` did not contain proper utf-8.", path.display())),
-        Ok(_n_bytes_read) => (),
+        Ok(content) => content,
     };
 
     let resolved_name = path.to_string_lossy().into();
@@ -53,6 +69,377 @@ fn load_shader_file(name: &str, include_type: shaderc::IncludeType, containing_f
 }
 
 
+/// Which files one shader `#include`s, and through which others.
+#[derive(Clone, Debug, Default)]
+pub struct IncludeGraph {
+    edges: Vec<(PathBuf, PathBuf)>,
+    // The files being included right now, outermost first, to catch cycles.
+    chain: Vec<PathBuf>,
+}
+
+impl IncludeGraph {
+    /// Each `#include`, as the (canonical) paths of the including and included files.
+    pub fn edges(&self) -> &[(PathBuf, PathBuf)] {
+        &self.edges
+    }
+
+    /// Every file included, directly or not, each once.
+    pub fn included_files(&self) -> Vec<&Path> {
+        let mut files = Vec::<&Path>::new();
+        for (_, included) in &self.edges {
+            if !files.contains(&included.as_path()) {
+                files.push(included);
+            }
+        }
+        files
+    }
+
+    pub fn includes(&self, file: &Path) -> bool {
+        self.edges.iter().any(|(_, included)| included == file)
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn resolve(&mut self, name: &str, include_type: shaderc::IncludeType, containing_file: &str)
+        -> Result<shaderc::ResolvedInclude, String>
+    {
+        let resolved = load_shader_file(name, include_type, containing_file)?;
+        self.enter(PathBuf::from(containing_file), PathBuf::from(&resolved.resolved_name))?;
+        Ok(resolved)
+    }
+
+    /// Notes that `containing` includes `path`, which must be noted in the
+    /// order a preprocessor would meet them: depth-first.
+    fn enter(&mut self, containing: PathBuf, path: PathBuf) -> Result<(), String> {
+        // Since includes come depth-first, whatever the including file is
+        // nested in is still on the chain; anything after it is done.
+        match self.chain.iter().position(|file| *file == containing) {
+            Some(i) => self.chain.truncate(i + 1),
+            None => self.chain = vec![containing.clone()],
+        }
+
+        if let Some(start) = self.chain.iter().position(|file| *file == path) {
+            let cycle = self.chain[start..].iter()
+                .chain(std::iter::once(&path))
+                .map(PathBuf::as_path)
+                .map(shader_path_display)
+                .collect::<Vec<_>>();
+            return Err(format!("#include cycle: {}", cycle.join(" -> ")));
+        }
+
+        // Both preprocessing and compiling resolve every include.
+        let edge = (containing, path.clone());
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+        self.chain.push(path);
+
+        Ok(())
+    }
+}
+
+/// `file`, relative to `SHADER_DIR` where possible, to keep messages short.
+fn shader_path_display(file: &Path) -> String {
+    let shader_dir = Path::new(SHADER_DIR).canonicalize().unwrap_or_default();
+    file.strip_prefix(&shader_dir).unwrap_or(file).display().to_string()
+}
+
+
+/// The file named by an `#include` line, and whether it's relative to
+/// the including file (`"..."`) rather than the shader directory (`<...>`).
+fn include_directive(line: &str) -> Option<(&str, bool)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim();
+    if let Some(name) = rest.strip_prefix('<') {
+        Some((&name[.. name.find('>')?], false))
+    } else if let Some(name) = rest.strip_prefix('"') {
+        Some((&name[.. name.find('"')?], true))
+    } else {
+        None
+    }
+}
+
+fn scan_file(dir: &Path, file: &Path, graph: &mut IncludeGraph) -> Result<(), String> {
+    let source = std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    for line in source.lines() {
+        let (name, relative) = match include_directive(line) {
+            Some(include) => include,
+            None => continue,
+        };
+
+        let base = if relative { file.parent().unwrap_or(dir) } else { dir };
+        let included = base.join(name).canonicalize()
+            .map_err(|e| format!("{}: {}", base.join(name).display(), e))?;
+
+        graph.enter(file.to_owned(), included.clone())?;
+        scan_file(dir, &included, graph)?;
+    }
+
+    Ok(())
+}
+
+/// Finds what every shader in `dir` `#include`s, by reading rather than
+/// compiling them, so that it covers shaders the game hasn't loaded.
+///
+/// Every `#include` counts, even one a `#define` would skip, so this may
+/// list more than a shader really uses. Each shader's graph is keyed by
+/// its file name, or is why its includes couldn't be followed.
+pub fn scan_includes(dir: impl AsRef<Path>) -> std::io::Result<BTreeMap<String, Result<IncludeGraph, String>>> {
+    let dir = dir.as_ref().canonicalize()?;
+    let mut graphs = BTreeMap::new();
+
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type()?.is_file() || ShaderKind::of(&file_name).is_none() {
+            continue;
+        }
+
+        let mut graph = IncludeGraph::default();
+        let scanned = scan_file(&dir, &entry.path(), &mut graph).map(|()| graph);
+        graphs.insert(file_name, scanned);
+    }
+
+    Ok(graphs)
+}
+
+/// The shaders in `dir` which `#include` `file` (relative to `dir`), directly or not,
+/// as far as `scan_includes` can tell.
+pub fn shaders_including(dir: impl AsRef<Path>, file: impl AsRef<Path>) -> std::io::Result<Vec<String>> {
+    let dir = dir.as_ref();
+    let file = dir.join(file).canonicalize()?;
+
+    Ok(scan_includes(dir)?.into_iter()
+        .filter(|(_, graph)| graph.as_ref().map_or(false, |graph| graph.includes(&file)))
+        .map(|(name, _)| name)
+        .collect())
+}
+
+
+/// A shader file, compiled with a set of `#define`s.
+///
+/// Each distinct set of defines is its own entry in the cache, so list
+/// them in a consistent order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ShaderName {
+    pub file: &'static str,
+    /// Each macro's name, and its value if it has one.
+    pub defines: &'static [(&'static str, Option<&'static str>)],
+}
+
+impl ShaderName {
+    pub const fn new(file: &'static str) -> Self {
+        Self { file, defines: &[] }
+    }
+
+    /// Names the files this permutation is cached under on disk.
+    fn cache_stem(&self) -> String {
+        use std::hash::{Hash, Hasher};
+
+        if self.defines.is_empty() {
+            return self.file.to_owned();
+        }
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.defines.hash(&mut hasher);
+        format!("{}.{:08x}", self.file, hasher.finish() as u32)
+    }
+
+    /// WGSL goes straight to wgpu, rather than through shaderc.
+    pub fn is_wgsl(&self) -> bool {
+        Path::new(self.file).extension().map_or(false, |ext| ext == "wgsl")
+    }
+
+    /// Where `compile-shaders` puts this permutation.
+    pub fn precompiled_path(&self) -> PathBuf {
+        Path::new(PRECOMPILED_DIR).join(format!("{}.spv", self.cache_stem()))
+    }
+}
+
+impl From<&'static str> for ShaderName {
+    fn from(file: &'static str) -> Self {
+        Self::new(file)
+    }
+}
+
+impl std::fmt::Display for ShaderName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        for (i, (name, value)) in self.defines.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " [" } else { ", " }, name)?;
+            if let Some(value) = value {
+                write!(f, "={}", value)?;
+            }
+        }
+        if !self.defines.is_empty() {
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Optimization {
+    Zero,
+    Size,
+    Performance,
+}
+
+/// Everything about how shaders are compiled, other than their source.
+///
+/// `shaderc::CompileOptions` can't be inspected once built, so these are
+/// kept separately, both to build it from and to key the SPIR-V cache with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderOptions {
+    pub auto_bind_uniforms: bool,
+    pub warnings_as_errors: bool,
+    pub suppress_warnings: bool,
+    pub optimization: Optimization,
+}
+
+impl Default for ShaderOptions {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::debug()
+        } else {
+            Self::release()
+        }
+    }
+}
+
+impl ShaderOptions {
+    /// Some more careful options, for debug builds.
+    pub fn debug() -> Self {
+        Self {
+            auto_bind_uniforms: false,
+            warnings_as_errors: true,
+            suppress_warnings: false,
+            optimization: Optimization::Zero,
+        }
+    }
+
+    /// Go all-out, for release builds.
+    pub fn release() -> Self {
+        Self {
+            auto_bind_uniforms: false,
+            warnings_as_errors: false,
+            suppress_warnings: true,
+            optimization: Optimization::Performance,
+        }
+    }
+
+    #[cfg(feature = "shaderc")]
+    pub fn compile_options(&self) -> shaderc::CompileOptions<'static> {
+        self.compile_options_recording(Arc::new(Mutex::new(IncludeGraph::default())))
+    }
+
+    /// Like `compile_options`, but noting down every `#include` in `includes`.
+    #[cfg(feature = "shaderc")]
+    fn compile_options_recording(&self, includes: Arc<Mutex<IncludeGraph>>) -> shaderc::CompileOptions<'static> {
+        let mut options = self.compile_options_without_includes();
+        options.set_include_callback(move |name, include_type, containing_file, _depth| {
+            includes.lock().resolve(name, include_type, containing_file)
+        });
+        options
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn compile_options_without_includes(&self) -> shaderc::CompileOptions<'static> {
+        let mut options = shaderc::CompileOptions::new().expect("Failed to set glsl compiler options.");
+        options.set_auto_bind_uniforms(self.auto_bind_uniforms);
+
+        if self.warnings_as_errors {
+            options.set_warnings_as_errors();
+        }
+        if self.suppress_warnings {
+            options.set_suppress_warnings();
+        }
+
+        options.set_optimization_level(match self.optimization {
+            Optimization::Zero => shaderc::OptimizationLevel::Zero,
+            Optimization::Size => shaderc::OptimizationLevel::Size,
+            Optimization::Performance => shaderc::OptimizationLevel::Performance,
+        });
+
+        options
+    }
+}
+
+
+#[cfg(feature = "shaderc")]
+fn add_defines(options: &mut shaderc::CompileOptions, name: ShaderName) {
+    for &(macro_name, value) in name.defines {
+        options.add_macro_definition(macro_name, value);
+    }
+}
+
+
+/// Identifies one compilation of a shader: its fully preprocessed source
+/// (which takes in every `#include` and `#define`), what kind of shader
+/// it is, and the options it was compiled with.
+#[cfg(feature = "shaderc")]
+fn spirv_cache_key(name: ShaderName, preprocessed: &str, kind: ShaderKind, options: &ShaderOptions) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    // WTF: `DefaultHasher` may change between versions of rust, but
+    // that only costs a recompile of everything, so it'll do.
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    SPIRV_CACHE_VERSION.hash(&mut hasher);
+    name.defines.hash(&mut hasher);
+    preprocessed.hash(&mut hasher);
+    kind.hash(&mut hasher);
+    options.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(feature = "shaderc")]
+fn spirv_cache_path(stem: &str, key: u64) -> PathBuf {
+    PathBuf::from(SPIRV_CACHE_DIR).join(format!("{}.{:016x}.spv", stem, key))
+}
+
+fn read_spirv(path: &Path) -> Option<Vec<u32>> {
+    spirv_words(&std::fs::read(path).ok()?)
+}
+
+fn spirv_words(data: &[u8]) -> Option<Vec<u32>> {
+    if data.len() % 4 != 0 {
+        return None;
+    }
+
+    let words = data.chunks_exact(4)
+        .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+        .collect::<Vec<_>>();
+
+    match words.first() {
+        Some(&SPIRV_MAGIC) => Some(words),
+        _ => None,
+    }
+}
+
+/// Saves `spirv` to `path`, clearing out whatever was cached under `stem` before.
+#[cfg(feature = "shaderc")]
+fn write_cached_spirv(stem: &str, path: &Path, spirv: &[u32]) -> std::io::Result<()> {
+    std::fs::create_dir_all(SPIRV_CACHE_DIR)?;
+
+    let stale_prefix = format!("{}.", stem);
+    for entry in std::fs::read_dir(SPIRV_CACHE_DIR)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        let is_stale_copy = file_name.starts_with(&stale_prefix)
+            && file_name.ends_with(".spv")
+            // Don't mistake e.g. `basic.frag.inc`, or a permutation of
+            // `basic.frag` with defines, for a copy of `basic.frag`.
+            && file_name[stale_prefix.len()..].len() == "0123456789abcdef.spv".len();
+        if is_stale_copy {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    std::fs::write(path, bytes::of_slice(spirv))
+}
+
+
 pub struct ShaderCache {
     device: &'static wgpu::Device,
 
@@ -62,9 +449,10 @@ pub struct ShaderCache {
     // to cause any deadlocks, since the shader cache will probably be used
     // from only one thread anyway (?)
     // TODO: Switch out for RefCell...?
+    #[cfg(feature = "shaderc")]
     compiler: Mutex<shaderc::Compiler>,
     
-    options: shaderc::CompileOptions<'static>,
+    options: ShaderOptions,
 
     // WTF: We only ever need immutable borrows of the ShaderCacheEntries
     // themselves, but previously this would have required either
@@ -73,13 +461,28 @@ pub struct ShaderCache {
     //
     // With CHashMap, the locking seems to be done at the per-key level,
     // which is exactly the kind of mechanism we want.
-    cache: CHashMap<&'static str, ShaderCacheEntry>,
+    cache: CHashMap<ShaderName, ShaderCacheEntry>,
+
+    // Invalidated shaders, to keep using until a pass accepts their replacements.
+    last_good: CHashMap<ShaderName, ShaderCacheEntry>,
+    // Shaders which compiled but didn't fit their pass, until their files change.
+    rejected: Mutex<HashMap<ShaderName, ShaderError>>,
+    fallbacks: CHashMap<ShaderName, ShaderCacheEntry>,
+
+    // Each loaded shader's file, and every file it `#include`s.
+    sources: Mutex<HashMap<ShaderName, SourceFiles>>,
+    includes: Mutex<HashMap<ShaderName, IncludeGraph>>,
+    errors: ShaderErrors,
 }
 
 pub struct ShaderCacheEntry {
     // origin_path: std::path::PathBuf,
     // psd: wgpu::ProgrammableStageDescriptor<'static>,
     pub module: wgpu::ShaderModule,
+    /// What the shader expects to be bound, and fed in as vertices.
+    pub interface: ShaderInterface,
+    /// Whether this is a stand-in for a shader which couldn't be used.
+    pub is_fallback: bool,
 }
 
 impl ShaderCacheEntry {
@@ -91,122 +494,750 @@ impl ShaderCacheEntry {
     }
 }
 
-pub type ShaderRef<'a> = chashmap::ReadGuard<'a, &'static str, ShaderCacheEntry>;
+pub type ShaderRef<'a> = chashmap::ReadGuard<'a, ShaderName, ShaderCacheEntry>;
 
-impl ShaderCache {
 
-    pub fn new(device: &'static wgpu::Device) -> Self {
-        let mut options = shaderc::CompileOptions::new().expect("Failed to set glsl compiler options.");
-        options.set_auto_bind_uniforms(false);
-        options.set_include_callback(load_shader_file);
-        
-        if cfg!(debug_assertions) {
-            // debug mode, add some more careful options.
-            options.set_warnings_as_errors();
-            options.set_optimization_level(shaderc::OptimizationLevel::Zero);
-        } else {
-            // release mode, go all-out.
-            options.set_suppress_warnings();
-            options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+/// Why a shader couldn't be loaded.
+#[derive(Clone, Debug)]
+pub enum ShaderError {
+    Load { name: ShaderName, reason: String },
+    UnknownKind(ShaderName),
+    /// `diagnostics` is the compiler's output, as `file:line: error: ...` lines.
+    Compile { name: ShaderName, diagnostics: String },
+    /// It compiled, but uses something reflection doesn't understand.
+    Interface { name: ShaderName, error: InterfaceError },
+    /// It compiled, but doesn't fit the pass which uses it.
+    Mismatch { name: ShaderName, pass: &'static str, error: InterfaceError },
+    /// Not even the fallback shaders fit the pass `name` is part of, so it's skipped.
+    Unusable { name: ShaderName, pass: &'static str, error: InterfaceError },
+}
+
+impl ShaderError {
+    pub fn name(&self) -> ShaderName {
+        match self {
+            ShaderError::Load { name, .. } => *name,
+            ShaderError::UnknownKind(name) => *name,
+            ShaderError::Compile { name, .. } => *name,
+            ShaderError::Interface { name, .. } => *name,
+            ShaderError::Mismatch { name, .. } => *name,
+            ShaderError::Unusable { name, .. } => *name,
         }
+    }
+}
+
+impl std::fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShaderError::Load { name, reason } =>
+                write!(f, "Failed to load shader {}: {}", name, reason),
+            ShaderError::UnknownKind(name) =>
+                write!(f, "Unknown or missing shader extension: {}", name),
+            ShaderError::Compile { name, diagnostics } =>
+                write!(f, "Failed to compile shader {}:\n{}", name, diagnostics),
+            ShaderError::Interface { name, error } =>
+                write!(f, "Failed to reflect shader {}: {}", name, error),
+            ShaderError::Mismatch { name, pass, error } =>
+                write!(f, "Shader {} doesn't fit {}: {}", name, pass, error),
+            ShaderError::Unusable { pass, error, .. } =>
+                write!(f, "{} is skipped, since not even the fallback shaders fit it: {}", pass, error),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+#[cfg(feature = "shaderc")]
+fn compile_error(name: ShaderName, error: shaderc::Error) -> ShaderError {
+    let diagnostics = match error {
+        shaderc::Error::CompilationError(_, diagnostics) => diagnostics,
+        error => error.to_string(),
+    };
+    ShaderError::Compile { name, diagnostics }
+}
+
+
+/// The shaders which currently fail to load, shared with whatever displays them.
+#[derive(Clone, Default)]
+pub struct ShaderErrors(Arc<Mutex<BTreeMap<ShaderName, ShaderError>>>);
+
+impl ShaderErrors {
+    /// Every outstanding error, ordered by shader name.
+    pub fn list(&self) -> Vec<ShaderError> {
+        self.0.lock().values().cloned().collect()
+    }
+}
 
-        let compiler = shaderc::Compiler::new().expect("Failed to initialize glsl compiler.");
-        let compiler = Mutex::new(compiler);
-        let cache    = CHashMap::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderKind {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderKind {
+    pub const ALL: [ShaderKind; 3] = [ShaderKind::Vertex, ShaderKind::Fragment, ShaderKind::Compute];
+
+    /// Going by the file's extension, or the one before `.wgsl`.
+    pub fn of(file: &str) -> Option<Self> {
+        let mut path = Path::new(file);
+        if path.extension().map_or(false, |ext| ext == "wgsl") {
+            path = Path::new(path.file_stem()?);
+        }
+
+        match path.extension() {
+            Some(os_str) if os_str == "frag" => Some(ShaderKind::Fragment),
+            Some(os_str) if os_str == "vert" => Some(ShaderKind::Vertex),
+            Some(os_str) if os_str == "comp" => Some(ShaderKind::Compute),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ShaderKind::Vertex => "vert",
+            ShaderKind::Fragment => "frag",
+            ShaderKind::Compute => "comp",
+        }
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn shaderc(self) -> shaderc::ShaderKind {
+        match self {
+            ShaderKind::Vertex => shaderc::ShaderKind::Vertex,
+            ShaderKind::Fragment => shaderc::ShaderKind::Fragment,
+            ShaderKind::Compute => shaderc::ShaderKind::Compute,
+        }
+    }
+
+    /// The key the fallback for this kind of shader is cached under.
+    fn fallback_name(self) -> ShaderName {
+        match self {
+            ShaderKind::Vertex => ShaderName::new("<fallback>.vert"),
+            ShaderKind::Fragment => ShaderName::new("<fallback>.frag"),
+            ShaderKind::Compute => ShaderName::new("<fallback>.comp"),
+        }
+    }
+
+    /// Where the fallback for this kind of shader is kept.
+    pub fn builtin_fallback_path(self) -> PathBuf {
+        Path::new(BUILTIN_DIR).join(format!("fallback.{}.spv", self.extension()))
+    }
+
+    /// The fallback for this kind of shader, as embedded in the game.
+    pub fn fallback_spirv(self) -> Vec<u32> {
+        let data: &[u8] = match self {
+            ShaderKind::Vertex => include_bytes!("../../../assets/shaders/builtin/fallback.vert.spv"),
+            ShaderKind::Fragment => include_bytes!("../../../assets/shaders/builtin/fallback.frag.spv"),
+            ShaderKind::Compute => include_bytes!("../../../assets/shaders/builtin/fallback.comp.spv"),
+        };
+        spirv_words(data).expect("Built-in fallback shader isn't SPIR-V.")
+    }
+}
+
+
+// Stand-ins for shaders which fail to compile. The vertex shader needs no
+// vertex buffers and covers the screen, so that any pipeline using these
+// is both valid and obviously broken.
+// These are only the sources of the SPIR-V in `BUILTIN_DIR`, which is what
+// the game actually uses, so that builds without shaderc have it too.
+#[cfg(feature = "shaderc")]
+const FALLBACK_VERT: &str = r#"
+    #version 450
+    void main() {
+        vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+        gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+    }
+"#;
+
+#[cfg(feature = "shaderc")]
+const FALLBACK_FRAG: &str = r#"
+    #version 450
+    layout(location = 0) out vec4 color;
+    void main() {
+        color = vec4(1.0, 0.0, 1.0, 1.0);
+    }
+"#;
+
+#[cfg(feature = "shaderc")]
+const FALLBACK_COMP: &str = r#"
+    #version 450
+    layout(local_size_x = 1) in;
+    void main() {}
+"#;
+
+
+/// Compiles the fallback for `kind` of shader, to regenerate what's in `BUILTIN_DIR`.
+#[cfg(feature = "shaderc")]
+pub fn compile_fallback(compiler: &mut shaderc::Compiler, kind: ShaderKind) -> shaderc::CompilationArtifact {
+    let source = match kind {
+        ShaderKind::Vertex => FALLBACK_VERT,
+        ShaderKind::Fragment => FALLBACK_FRAG,
+        ShaderKind::Compute => FALLBACK_COMP,
+    };
+
+    compiler
+        .compile_into_spirv(source, kind.shaderc(), kind.fallback_name().file, "main", None)
+        .expect("Failed to compile built-in fallback shader.")
+}
+
+/// Compiles `name` from scratch with `options`, bypassing every cache.
+#[cfg(feature = "shaderc")]
+pub fn compile_uncached(compiler: &mut shaderc::Compiler, options: &ShaderOptions, name: ShaderName)
+    -> Result<shaderc::CompilationArtifact, ShaderError>
+{
+    let resolved_file = load_shader_file(name.file, shaderc::IncludeType::Standard, "")
+        .map_err(|reason| ShaderError::Load { name, reason })?;
+
+    let shader_type = ShaderKind::of(name.file).ok_or(ShaderError::UnknownKind(name))?;
+
+    let mut compile_options = options.compile_options();
+    add_defines(&mut compile_options, name);
+
+    compiler.compile_into_spirv(
+        &resolved_file.content,
+        shader_type.shaderc(),
+        &resolved_file.resolved_name,
+        "main",
+        Some(&compile_options),
+    ).map_err(|e| compile_error(name, e))
+}
+
+/// Translates WGSL to SPIR-V, only so it can be reflected. wgpu is still
+/// handed the WGSL itself.
+pub fn translate_wgsl(name: ShaderName, source: &str) -> Result<Vec<u32>, ShaderError> {
+    // WTF: naga's WGSL errors only implement `Debug`, but they do carry the
+    // (line, column) they stopped at, so report it the way shaderc does.
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| {
+        let (line, column) = e.pos;
+        ShaderError::Compile {
+            name,
+            diagnostics: format!("{}:{}:{}: error: {:?}", name.file, line, column, e.error),
+        }
+    })?;
+
+    let mut writer = naga::back::spv::Writer::new(&module.header, naga::back::spv::WriterFlags::NONE);
+    Ok(writer.write(&module))
+}
+
+
+impl ShaderCache {
+
+    pub fn new(device: &'static wgpu::Device) -> Self {
+        let options   = ShaderOptions::default();
+        #[cfg(feature = "shaderc")]
+        let compiler  = Mutex::new(shaderc::Compiler::new().expect("Failed to initialize glsl compiler."));
+        let cache     = CHashMap::new();
+        let last_good = CHashMap::new();
+        let rejected  = Mutex::new(HashMap::new());
+        let fallbacks = CHashMap::new();
+        let sources   = Mutex::new(HashMap::new());
+        let includes  = Mutex::new(HashMap::new());
+        let errors    = ShaderErrors::default();
 
         Self { 
             device, 
+            #[cfg(feature = "shaderc")]
             compiler, 
             options, 
             cache, 
+            last_good,
+            rejected,
+            fallbacks,
+            sources,
+            includes,
+            errors,
         }
     }
 
-    pub fn options(&mut self) -> &mut shaderc::CompileOptions<'static> {
+    /// Invalidates every shader whose file, or any file it `#include`s,
+    /// has changed since it was loaded. Returns the names of those shaders.
+    pub fn invalidate_changed(&self) -> Vec<ShaderName> {
+        let changed = self.sources.lock().iter()
+            .filter(|(_, sources)| sources.changed())
+            .map(|(&name, _)| name)
+            .collect::<Vec<_>>();
+
+        for &name in &changed {
+            AssetCache::invalidate(self, name);
+        }
+
+        changed
+    }
+
+    /// The loaded shaders which `#include` `file` (relative to `SHADER_DIR`), directly or not.
+    pub fn dependents(&self, file: impl AsRef<Path>) -> Vec<ShaderName> {
+        let file = match Path::new(SHADER_DIR).join(file).canonicalize() {
+            Ok(file) => file,
+            Err(_) => return Vec::new(),
+        };
+
+        self.includes.lock().iter()
+            .filter(|(_, graph)| graph.includes(&file))
+            .map(|(&name, _)| name)
+            .collect()
+    }
+
+    /// Invalidates every loaded shader which `#include`s `file`, so they
+    /// all reload together. Returns the names of those shaders.
+    pub fn invalidate_dependents(&self, file: impl AsRef<Path>) -> Vec<ShaderName> {
+        let dependents = self.dependents(file);
+        for &name in &dependents {
+            AssetCache::invalidate(self, name);
+        }
+        dependents
+    }
+
+    /// What `name` `#include`d, the last time it was compiled.
+    pub fn include_graph(&self, name: impl Into<ShaderName>) -> Option<IncludeGraph> {
+        self.includes.lock().get(&name.into()).cloned()
+    }
+
+    /// Changes here apply to shaders loaded afterwards.
+    pub fn options(&mut self) -> &mut ShaderOptions {
         &mut self.options
     }
 
-    pub fn load(&self, name: &'static str) -> ShaderRef {        
+    /// The shaders which failed the last time they were loaded.
+    pub fn errors(&self) -> ShaderErrors {
+        self.errors.clone()
+    }
+
+    pub fn load(&self, name: impl Into<ShaderName>) -> Result<ShaderRef, ShaderError> {
+        let name = name.into();
        
-        if let Some(shader) = self.cache.get(name) {
+        if let Some(shader) = self.cache.get(&name) {
+            return Ok(shader);
+        }
+
+        if let Some(e) = self.rejected.lock().get(&name) {
+            return Err(e.clone());
+        }
+
+        match self.compile(name) {
+            Ok(entry) => {
+                self.errors.0.lock().remove(&name);
+                self.cache.insert_new(name, entry);
+                Ok(self.cache.get(&name).unwrap())
+            },
+            Err(e) => {
+                self.errors.0.lock().insert(name, e.clone());
+                Err(e)
+            },
+        }
+    }
+
+    /// Loads the vertex and fragment shaders of one pipeline.
+    ///
+    /// A shader which fails to load is replaced by the last version of it
+    /// which worked, if there was one. Failing that, both are replaced by
+    /// the fallback shaders, since one stage alone wouldn't match the other.
+    pub fn load_pipeline(&self, vert: impl Into<ShaderName>, frag: impl Into<ShaderName>) -> (ShaderRef, ShaderRef) {
+        match (self.load_or_last_good(vert.into()), self.load_or_last_good(frag.into())) {
+            (Some(vert), Some(frag)) => (vert, frag),
+            _ => (
+                self.fallback(ShaderKind::Vertex),
+                self.fallback(ShaderKind::Fragment),
+            ),
+        }
+    }
+
+    /// Loads the shaders of one pipeline, and builds `pass` with `build`.
+    ///
+    /// If `build` finds that the shaders don't fit the pass, the error is
+    /// shown along with any others, and the pass is built again from the
+    /// last versions of the shaders it accepted, or else the fallbacks.
+    /// If not even the fallbacks fit, that's shown too, and returned.
+    pub fn build_pipeline<T, F>(&self, pass: &'static str, vert: impl Into<ShaderName>, frag: impl Into<ShaderName>, mut build: F)
+        -> Result<T, ShaderError>
+    where
+        F: FnMut(&ShaderCacheEntry, &ShaderCacheEntry) -> Result<T, InterfaceError>,
+    {
+        let names = [vert.into(), frag.into()];
+
+        self.build_with(pass, &names, || {
+            let (vert, frag) = self.load_pipeline(names[0], names[1]);
+            let built = build(&vert, &frag);
+            drop((vert, frag)); // release the cache's locks
+            built
+        })
+    }
+
+    /// Loads a compute shader, and builds `pass` with `build`, falling back
+    /// the same way as `build_pipeline`.
+    pub fn build_compute<T, F>(&self, pass: &'static str, name: impl Into<ShaderName>, mut build: F) -> Result<T, ShaderError>
+    where
+        F: FnMut(&ShaderCacheEntry) -> Result<T, InterfaceError>,
+    {
+        let names = [name.into()];
+
+        self.build_with(pass, &names, || {
+            let module = self.load_compute(names[0]);
+            let built = build(&module);
+            drop(module); // release the cache's lock
+            built
+        })
+    }
+
+    fn build_with<T>(&self, pass: &'static str, names: &[ShaderName], mut build: impl FnMut() -> Result<T, InterfaceError>)
+        -> Result<T, ShaderError>
+    {
+        // Each failure takes every suspect back a step: from the current
+        // version, to the last good one, to the fallbacks, which fit most anything.
+        let mut rejections = 0;
+        loop {
+            match build() {
+                Ok(built) => {
+                    self.accept(names);
+                    return Ok(built);
+                },
+                Err(error) if rejections == 2 => return Err(self.skip(pass, names[0], error)),
+                Err(error) => {
+                    self.reject(pass, names, error);
+                    rejections += 1;
+                },
+            }
+        }
+    }
+
+    /// Notes that `pass` can't be built at all, to be shown with the other errors.
+    fn skip(&self, pass: &'static str, name: ShaderName, error: InterfaceError) -> ShaderError {
+        eprintln!("{} doesn't fit even the fallback shaders, so it's skipped: {}", pass, error);
+
+        let skipped = ShaderError::Unusable { name, pass, error };
+        self.errors.0.lock().insert(name, skipped.clone());
+        skipped
+    }
+
+    /// Notes that `names` fit their pass, so their older versions can go.
+    fn accept(&self, names: &[ShaderName]) {
+        for name in names {
+            if self.cache.contains_key(name) {
+                self.last_good.remove(name);
+            }
+        }
+    }
+
+    /// Stops using whichever of `names` are most likely why they don't fit
+    /// `pass`, until their files change.
+    fn reject(&self, pass: &'static str, names: &[ShaderName], error: InterfaceError) {
+        eprintln!("{} doesn't fit its shaders: {}", pass, error);
+
+        // Shaders which were reloaded since the pass last accepted them are
+        // the likely culprits. Failing that, the pass has to do without all of them.
+        let reloaded = names.iter()
+            .filter(|name| self.cache.contains_key(name) && self.last_good.contains_key(name))
+            .copied()
+            .collect::<Vec<_>>();
+        let suspects = if reloaded.is_empty() { names.to_vec() } else { reloaded };
+
+        let mut errors = self.errors.0.lock();
+        let mut rejected = self.rejected.lock();
+        for name in suspects {
+            let mismatch = ShaderError::Mismatch { name, pass, error: error.clone() };
+
+            // Without a current version, it was the last good one which didn't fit.
+            if self.cache.remove(&name).is_none() {
+                self.last_good.remove(&name);
+            }
+
+            errors.insert(name, mismatch.clone());
+            rejected.insert(name, mismatch);
+        }
+    }
+
+    /// Loads a compute shader, falling back the same way as `load_pipeline`.
+    pub fn load_compute(&self, name: impl Into<ShaderName>) -> ShaderRef {
+        match self.load_or_last_good(name.into()) {
+            Some(shader) => shader,
+            None => self.fallback(ShaderKind::Compute),
+        }
+    }
+
+    fn load_or_last_good(&self, name: ShaderName) -> Option<ShaderRef> {
+        match self.load(name) {
+            Ok(shader) => Some(shader),
+            Err(e) => {
+                eprintln!("{}", e);
+                self.last_good.get(&name)
+            },
+        }
+    }
+
+    fn fallback(&self, kind: ShaderKind) -> ShaderRef {
+        let name = kind.fallback_name();
+
+        if let Some(shader) = self.fallbacks.get(&name) {
             return shader;
         }
 
-        let resolved_file = load_shader_file(name, shaderc::IncludeType::Standard, "", 0)
-            .expect("Failed to load shader resource.");
+        let spirv = kind.fallback_spirv();
 
-        let glsl_path  = std::path::Path::new(&resolved_file.resolved_name);
-        
-        let shader_type = match glsl_path.extension() {
-            Some(os_str) if os_str == "frag" => shaderc::ShaderKind::Fragment,
-            Some(os_str) if os_str == "vert" => shaderc::ShaderKind::Vertex,
-            Some(os_str) if os_str == "comp" => shaderc::ShaderKind::Compute,
-            _ => panic!("Unknown or missing shader extension: {}", name),
-        };
+        let mut entry = self.create_entry(name, &spirv, wgpu::ShaderModuleSource::SpirV(&spirv))
+            .expect("Failed to reflect built-in fallback shader.");
+        entry.is_fallback = true;
+
+        self.fallbacks.insert_new(name, entry);
+        self.fallbacks.get(&name).unwrap()
+    }
+
+    fn compile(&self, name: ShaderName) -> Result<ShaderCacheEntry, ShaderError> {
+        if name.is_wgsl() {
+            self.compile_wgsl(name)
+        } else {
+            self.compile_glsl(name)
+        }
+    }
+
+    fn compile_wgsl(&self, name: ShaderName) -> Result<ShaderCacheEntry, ShaderError> {
+        let path = Path::new(SHADER_DIR).join(name.file);
+
+        // Watch the file even if it can't be loaded yet, so fixing it is noticed.
+        self.sources.lock().insert(name, SourceFiles::new(Some(path.clone())));
+
+        ShaderKind::of(name.file).ok_or(ShaderError::UnknownKind(name))?;
+
+        if !name.defines.is_empty() {
+            return Err(ShaderError::Load { name, reason: "WGSL has no preprocessor to take #defines.".to_owned() });
+        }
+
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| ShaderError::Load { name, reason: format!("{}: {}", path.display(), e) })?;
+
+        let spirv = translate_wgsl(name, &source)?;
+
+        eprintln!("Successfully loaded shader: {}.", name);
+
+        self.create_entry(name, &spirv, wgpu::ShaderModuleSource::Wgsl(&source))
+    }
+
+    /// Loads SPIR-V precompiled by `compile-shaders`, without shaderc to compile anything fresh.
+    #[cfg(not(feature = "shaderc"))]
+    fn compile_glsl(&self, name: ShaderName) -> Result<ShaderCacheEntry, ShaderError> {
+        let path = name.precompiled_path();
+
+        // Watch the file even if it isn't there yet, so compiling it is noticed.
+        self.sources.lock().insert(name, SourceFiles::new(Some(path.clone())));
+
+        let spirv = read_spirv(&path).ok_or_else(|| ShaderError::Load {
+            name,
+            reason: format!("No precompiled SPIR-V at {}; run `compile-shaders` with shaderc.", path.display()),
+        })?;
+
+        self.create_entry(name, &spirv, wgpu::ShaderModuleSource::SpirV(&spirv))
+    }
+
+    /// Caches `spirv` as `name`, as though it had been compiled from a file.
+    #[cfg(test)]
+    pub(crate) fn insert_spirv(&self, name: ShaderName, spirv: &[u32]) -> Result<(), ShaderError> {
+        let entry = self.create_entry(name, spirv, wgpu::ShaderModuleSource::SpirV(spirv))?;
+        self.cache.insert(name, entry);
+        Ok(())
+    }
+
+    /// `spirv` is what to reflect, which for WGSL isn't what wgpu is given.
+    fn create_entry(&self, name: ShaderName, spirv: &[u32], source: wgpu::ShaderModuleSource)
+        -> Result<ShaderCacheEntry, ShaderError>
+    {
+        let interface = reflect::reflect(spirv)
+            .map_err(|error| ShaderError::Interface { name, error })?;
+
+        let shader_module = self.device.create_shader_module(source);
+
+        Ok(ShaderCacheEntry {
+            module: shader_module,
+            interface,
+            is_fallback: false,
+        })
+    }
+
+    #[cfg(feature = "shaderc")]
+    fn compile_glsl(&self, name: ShaderName) -> Result<ShaderCacheEntry, ShaderError> {
+
+        // Watch the file even if it can't be loaded yet, so fixing it is noticed.
+        self.sources.lock().insert(name, SourceFiles::new(Some(Path::new(SHADER_DIR).join(name.file))));
+
+        let resolved_file = load_shader_file(name.file, shaderc::IncludeType::Standard, "")
+            .map_err(|reason| ShaderError::Load { name, reason })?;
         
-        // TODO: Write out to spirv file so we don't have to always recompile?
-        // let spirv_path = glsl_path.with_extension("spv");
+        let shader_type = ShaderKind::of(name.file).ok_or(ShaderError::UnknownKind(name))?;
         
+        let includes = Arc::new(Mutex::new(IncludeGraph::default()));
+        let mut options = self.options.compile_options_recording(includes.clone());
+        add_defines(&mut options, name);
         let mut compiler = self.compiler.lock();
 
+        // Preprocessing is cheap next to compiling, and pulls in every
+        // `#include`, so its output tells us whether the cache is stale.
         let preprocessed = compiler.preprocess(
             &resolved_file.content,
             &resolved_file.resolved_name,
             "main",
-            Some(&self.options),
-        ).expect("Failed to preprocess shader!");
+            Some(&options),
+        );
+
+        // Even if preprocessing failed, any includes it did find are worth watching.
+        let includes = includes.lock().clone();
+        let source_paths = std::iter::once(PathBuf::from(&resolved_file.resolved_name))
+            .chain(includes.included_files().into_iter().map(Path::to_owned))
+            .collect::<Vec<_>>();
+        self.sources.lock().insert(name, SourceFiles::new(source_paths));
+        self.includes.lock().insert(name, includes);
+
+        let preprocessed = preprocessed.map_err(|e| compile_error(name, e))?;
 
         if preprocessed.get_num_warnings() != 0 {
             eprintln!("{}", preprocessed.get_warning_messages());
         }
-        
-        let spirv = match compiler.compile_into_spirv(
-            &resolved_file.content,
-            shader_type,
-            &resolved_file.resolved_name,
-            "main",
-            Some(&self.options),
-        ) {
-            Ok(spirv) => spirv,
-            Err(e) => {
-                panic!("Failed to compile shader! {}", e);
-            },
-        };
 
-        if spirv.get_num_warnings() != 0 {
-            eprintln!("{}", spirv.get_warning_messages());
-        }
+        let cache_stem = name.cache_stem();
+        let cache_key  = spirv_cache_key(name, &preprocessed.as_text(), shader_type, &self.options);
+        let spirv_path = spirv_cache_path(&cache_stem, cache_key);
 
-        eprintln!("Successfully compiled shader: {}.", name);
+        let spirv = match read_spirv(&spirv_path) {
+            Some(spirv) => spirv,
+            None => {
+                let spirv = compiler.compile_into_spirv(
+                    &resolved_file.content,
+                    shader_type.shaderc(),
+                    &resolved_file.resolved_name,
+                    "main",
+                    Some(&options),
+                ).map_err(|e| compile_error(name, e))?;
 
-        let shader_module =
-            self.device.create_shader_module(
-                wgpu::ShaderModuleSource::SpirV(spirv.as_binary())
-            );
+                if spirv.get_num_warnings() != 0 {
+                    eprintln!("{}", spirv.get_warning_messages());
+                }
 
-        self.cache.insert_new(name, ShaderCacheEntry {
-            module: shader_module,
-        });
+                eprintln!("Successfully compiled shader: {}.", name);
 
-        self.cache.get(name).unwrap()
+                if let Err(e) = write_cached_spirv(&cache_stem, &spirv_path, spirv.as_binary()) {
+                    eprintln!("Failed to cache compiled shader {}: {}", name, e);
+                }
+
+                spirv.as_binary().to_vec()
+            },
+        };
+
+        self.create_entry(name, &spirv, wgpu::ShaderModuleSource::SpirV(&spirv))
     }
 }
 
 
 impl<'a> AssetCache<ShaderCacheEntry> for &'a ShaderCache {
-    type AssetName = &'static str;
+    type AssetName = ShaderName;
     type AssetRef = ShaderRef<'a>;
     
-    fn load(self, name: &'static str) -> Self::AssetRef {
-        ShaderCache::load(self, name)
+    /// Never fails, but falls back the same way as `load_pipeline`.
+    fn load(self, name: ShaderName) -> anyhow::Result<Self::AssetRef> {
+        Ok(match self.load_or_last_good(name) {
+            Some(shader) => shader,
+            None => self.fallback(ShaderKind::of(name.file).unwrap_or(ShaderKind::Fragment)),
+        })
     }
 
-    fn invalidate(self, name: &'static str) {
-        self.cache.remove(name);
+    /// Keeps the old module around, in case its replacement fails to compile
+    /// or doesn't fit its pass.
+    fn invalidate(self, name: ShaderName) {
+        if let Some(entry) = self.cache.remove(&name) {
+            self.last_good.insert(name, entry);
+        }
+        self.rejected.lock().remove(&name);
+        self.sources.lock().remove(&name);
+        self.includes.lock().remove(&name);
     }
 
     fn clear(self) {
-        self.cache.clear();
+        for (name, entry) in self.cache.clear() {
+            self.last_good.insert(name, entry);
+        }
+        self.rejected.lock().clear();
+        self.sources.lock().clear();
+        self.includes.lock().clear();
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory of shaders with the given `(file, source)`s.
+    fn shader_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shader-test-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            std::fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn reads_include_directives() {
+        assert_eq!(include_directive("#include <lighting.glsl>"), Some(("lighting.glsl", false)));
+        assert_eq!(include_directive("  #  include \"common/util.glsl\""), Some(("common/util.glsl", true)));
+        assert_eq!(include_directive("#define include"), None);
+        assert_eq!(include_directive("#include lighting.glsl"), None);
+    }
+
+    #[test]
+    fn diamond_includes_are_not_cycles() {
+        let dir = shader_dir("diamond", &[
+            ("main.frag", "#include <left.glsl>\n#include <right.glsl>\n"),
+            ("left.glsl", "#include <common.glsl>\n"),
+            ("right.glsl", "#include <common.glsl>\n"),
+            ("common.glsl", "// nothing\n"),
+            ("other.frag", "#include <right.glsl>\n"),
+        ]);
+
+        let graphs = scan_includes(&dir).unwrap();
+        let graph = graphs["main.frag"].as_ref().unwrap();
+        assert_eq!(graph.edges().len(), 4);
+        assert_eq!(graph.included_files().len(), 3);
+
+        assert_eq!(shaders_including(&dir, "common.glsl").unwrap(), vec!["main.frag", "other.frag"]);
+        assert_eq!(shaders_including(&dir, "left.glsl").unwrap(), vec!["main.frag"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let dir = shader_dir("cycle", &[
+            ("main.frag", "#include <a.glsl>\n"),
+            ("a.glsl", "#include \"b.glsl\"\n"),
+            ("b.glsl", "#include <a.glsl>\n"),
+        ]);
+
+        let graphs = scan_includes(&dir).unwrap();
+        let a = dir.join("a.glsl").canonicalize().unwrap();
+        let b = dir.join("b.glsl").canonicalize().unwrap();
+        assert_eq!(
+            graphs["main.frag"].as_ref().unwrap_err(),
+            &format!("#include cycle: {} -> {} -> {}", a.display(), b.display(), a.display()),
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deep_includes_are_not_cycles() {
+        let mut graph = IncludeGraph::default();
+        let file = |i: usize| PathBuf::from(format!("{}.glsl", i));
+
+        (0 .. 100).try_for_each(|i| graph.enter(file(i), file(i + 1))).unwrap();
+        assert!(graph.enter(file(100), file(50)).unwrap_err().starts_with("#include cycle"));
+    }
+
+    #[test]
+    fn fallbacks_fit_any_pass() {
+        for &kind in &ShaderKind::ALL {
+            let interface = reflect::reflect(&kind.fallback_spirv())
+                .unwrap_or_else(|e| panic!("{:?} fallback: {}", kind, e));
+
+            assert!(interface.bindings.is_empty(), "{:?} fallback binds something", kind);
+            assert!(interface.vertex_inputs.is_empty(), "{:?} fallback reads vertices", kind);
+        }
+    }
+}
@@ -1,18 +1,23 @@
 
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use crate::render::{
     bytes,
-    cache::AssetCache,
+    cache::{AssetCache, AssetErrors, SourceFiles},
 };
 
 use chashmap::CHashMap;
+use parking_lot::Mutex;
 
 
-fn load_texture_file(name: &str) -> anyhow::Result<image::DynamicImage> {
-    use std::path::PathBuf;
-
+fn texture_path(name: &str) -> PathBuf {
     let mut path = PathBuf::from("assets/textures");
     path.push(name);
+    path
+}
 
+fn load_texture_file(path: &std::path::Path) -> anyhow::Result<image::DynamicImage> {
     let path = path.canonicalize()?;
     let img = image::open(&path)?;
 
@@ -20,6 +25,11 @@ fn load_texture_file(name: &str) -> anyhow::Result<image::DynamicImage> {
 }
 
 
+/// What stands in for a texture which has never loaded, in a colour nobody would pick.
+const FALLBACK: &str = "<fallback>";
+const FALLBACK_PIXEL: [u8; 4] = [255, 0, 255, 255];
+
+
 enum Raw {
     VecU16(Vec<u16>),
     VecU8(Vec<u8>),
@@ -29,6 +39,11 @@ pub struct TextureCache {
     device: &'static wgpu::Device,
     queue:  &'static wgpu::Queue,
     cache: CHashMap<&'static str, TextureCacheEntry>,
+    // Invalidated textures, to keep using until their replacements load.
+    last_good: CHashMap<&'static str, TextureCacheEntry>,
+    fallback: CHashMap<&'static str, TextureCacheEntry>,
+    sources: Mutex<HashMap<&'static str, SourceFiles>>,
+    errors: AssetErrors,
 }
 
 pub struct TextureCacheEntry {
@@ -47,21 +62,92 @@ impl TextureCache {
 
     pub fn new(device: &'static wgpu::Device, queue: &'static wgpu::Queue) -> Self {
         let cache = CHashMap::new();
+        let last_good = CHashMap::new();
+        let fallback = CHashMap::new();
+        let sources = Mutex::new(HashMap::new());
+        let errors = AssetErrors::default();
         Self {
             device,
             queue,
             cache,
+            last_good,
+            fallback,
+            sources,
+            errors,
+        }
+    }
+
+    /// The textures which failed the last time they were loaded.
+    pub fn errors(&self) -> AssetErrors {
+        self.errors.clone()
+    }
+
+    /// Invalidates every texture whose file has changed since it was loaded.
+    /// Returns the names of those textures.
+    pub fn invalidate_changed(&self) -> Vec<&'static str> {
+        let changed = self.sources.lock().iter()
+            .filter(|(_, sources)| sources.changed())
+            .map(|(&name, _)| name)
+            .collect::<Vec<_>>();
+
+        for &name in &changed {
+            AssetCache::invalidate(self, name);
         }
+
+        changed
     }
 
-    pub fn load(&self, name: &'static str) -> TextureRef {
+    /// Loads `name` from `assets/textures`.
+    ///
+    /// If that fails, the error is shown along with the shaders', and the
+    /// version of the texture from before its file changed is kept, if any.
+    pub fn load(&self, name: &'static str) -> anyhow::Result<TextureRef> {
 
         if let Some(texture) = self.cache.get(name) {
+            return Ok(texture);
+        }
+
+        // Watched even if it fails to load, so that fixing it is noticed.
+        let path = texture_path(name);
+        self.sources.lock().insert(name, SourceFiles::new(Some(path.clone())));
+
+        match load_texture_file(&path).and_then(|img| self.create(name, img)) {
+            Ok(entry) => {
+                self.errors.remove(name);
+                self.last_good.remove(name);
+                self.cache.insert(name, entry);
+                Ok(self.cache.get(name).unwrap())
+            },
+            Err(e) => {
+                eprintln!("Failed to load texture {}: {}", name, e);
+                self.errors.insert(name, e.to_string());
+                self.last_good.get(name).ok_or(e)
+            },
+        }
+    }
+
+    /// Loads `name`, or else a placeholder texture, so that there's always something to draw.
+    pub fn load_or_fallback(&self, name: &'static str) -> TextureRef {
+        match self.load(name) {
+            Ok(texture) => texture,
+            Err(_) => self.fallback(),
+        }
+    }
+
+    fn fallback(&self) -> TextureRef {
+        if let Some(texture) = self.fallback.get(FALLBACK) {
             return texture;
         }
-        
-        let img = load_texture_file(name).expect("Failed to load texture!");
-        
+
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(1, 1, FALLBACK_PIXEL.to_vec()).unwrap());
+        let entry = self.create(FALLBACK, img).expect("Failed to create fallback texture.");
+
+        self.fallback.insert(FALLBACK, entry);
+        self.fallback.get(FALLBACK).unwrap()
+    }
+
+    fn create(&self, name: &'static str, img: image::DynamicImage) -> anyhow::Result<TextureCacheEntry> {
+
         use image::{ColorType, DynamicImage::*, GenericImageView as _};
 
         let (width, height) = img.dimensions();
@@ -79,12 +165,12 @@ impl TextureCache {
                 , 4),
             
             ColorType::Rgba16 =>
-                ( Raw::VecU16(match img { ImageRgba16(img) => img.into_raw(), _ => panic!("Image lied about its formatting!") })
+                ( Raw::VecU16(match img { ImageRgba16(img) => img.into_raw(), _ => anyhow::bail!("image lied about its formatting") })
                 , wgpu::TextureFormat::Rgba16Float
                 , 8),
             
             other =>
-                panic!("Unsupported image color format: {:?}", other),
+                anyhow::bail!("unsupported image color format: {:?}", other),
         };
     
         let raw_bytes = match &raw {
@@ -148,15 +234,12 @@ impl TextureCache {
             ],
         });
     
-        let entry = TextureCacheEntry {
+        Ok(TextureCacheEntry {
             texture,
             sampler,
             format,
             bind_layout,
-        };
-
-        self.cache.insert(name, entry);
-        self.cache.get(name).unwrap()
+        })
     }
 }
 
@@ -164,16 +247,23 @@ impl<'a> AssetCache<TextureCacheEntry> for &'a TextureCache {
     type AssetName = &'static str;
     type AssetRef = TextureRef<'a>;
     
-    fn load(self, name: &'static str) -> Self::AssetRef {
+    fn load(self, name: &'static str) -> anyhow::Result<Self::AssetRef> {
         TextureCache::load(self, name)
     }
-    
+
+    /// Keeps the old texture around, in case its replacement fails to load.
     fn invalidate(self, name: &'static str) {
-        self.cache.remove(name);
+        if let Some(entry) = self.cache.remove(name) {
+            self.last_good.insert(name, entry);
+        }
+        self.sources.lock().remove(name);
     }
 
     fn clear(self) {
-        self.cache.clear();
+        for (name, entry) in self.cache.clear() {
+            self.last_good.insert(name, entry);
+        }
+        self.sources.lock().clear();
     }
 }
 
@@ -1,9 +1,8 @@
 
 use nalgebra_glm as glm;
-use crate::util::bytes;
+use crate::render::layout;
 
 
-#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct GimbalCamera {
     view:   glm::Mat4,
@@ -13,7 +12,30 @@ pub struct GimbalCamera {
     top:    glm::Vec3,
 }
 
-unsafe impl bytes::IntoBytes for GimbalCamera {}
+/// The camera as the shaders' `Camera` block sees it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CameraBlock {
+    pub view: layout::Mat4,
+    pub pos:  layout::Vec3,
+    pub dir:  layout::Vec3,
+    pub top:  layout::Vec3,
+}
+
+crate::block!(CameraBlock { view, pos, dir, top });
+
+impl layout::AsBlock for GimbalCamera {
+    type Block = CameraBlock;
+
+    fn as_block(&self) -> CameraBlock {
+        CameraBlock {
+            view: self.view.into(),
+            pos:  self.pos.into(),
+            dir:  self.dir.into(),
+            top:  self.top.into(),
+        }
+    }
+}
 
 impl GimbalCamera {
     pub fn new(pos: glm::Vec3, center: glm::Vec3, top: glm::Vec3) -> Self {
@@ -24,6 +46,48 @@ impl GimbalCamera {
         }
     }
 
+    #[inline]
+    pub fn position(&self) -> glm::Vec3 {
+        self.pos
+    }
+
+    #[inline]
+    pub fn target(&self) -> glm::Vec3 {
+        self.center
+    }
+
+    /// Moves the camera to `pos`, still facing its current target.
+    /// Returns false, leaving the camera as it was, if `pos` is the target,
+    /// since there'd be no way left to face.
+    #[inline]
+    pub fn set_position(&mut self, pos: glm::Vec3) -> bool {
+        match facing(pos, self.center) {
+            Some(dir) => {
+                self.pos = pos;
+                self.dir = dir;
+                self.refresh_view_matrix();
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Turns the camera to face `center`, which also becomes
+    /// the point it orbits around and zooms towards.
+    /// Returns false, leaving the camera as it was, if `center` is where the camera is.
+    #[inline]
+    pub fn set_target(&mut self, center: glm::Vec3) -> bool {
+        match facing(self.pos, center) {
+            Some(dir) => {
+                self.center = center;
+                self.dir = dir;
+                self.refresh_view_matrix();
+                true
+            },
+            None => false,
+        }
+    }
+
     #[inline]
     fn refresh_view_matrix(&mut self) {
         self.view = glm::look_at_lh(&self.pos, &(self.pos + self.dir), &self.top);
@@ -70,3 +134,66 @@ impl GimbalCamera {
         self.refresh_view_matrix();
     }
 }
+
+
+/// Which way is forward from `pos`, looking at `center`, unless they're
+/// too close together to tell.
+fn facing(pos: glm::Vec3, center: glm::Vec3) -> Option<glm::Vec3> {
+    let dir = center - pos;
+    let len = dir.norm();
+    if len.is_finite() && len > 1e-6 {
+        Some(dir / len)
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> GimbalCamera {
+        GimbalCamera::new(glm::vec3(0.0, 0.0, -5.0), glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn moves_and_retargets() {
+        let mut camera = camera();
+
+        assert!(camera.set_position(glm::vec3(0.0, 0.0, 5.0)));
+        assert_eq!(camera.position(), glm::vec3(0.0, 0.0, 5.0));
+        assert_eq!(camera.dir, glm::vec3(0.0, 0.0, -1.0));
+
+        assert!(camera.set_target(glm::vec3(3.0, 0.0, 5.0)));
+        assert_eq!(camera.target(), glm::vec3(3.0, 0.0, 5.0));
+        assert_eq!(camera.dir, glm::vec3(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ignores_looking_at_itself() {
+        let mut camera = camera();
+        let before = (camera.view, camera.pos, camera.center, camera.dir);
+
+        assert!(!camera.set_position(camera.target()));
+        assert!(!camera.set_target(camera.position()));
+        assert!(!camera.set_target(glm::vec3(f32::NAN, 0.0, 0.0)));
+
+        assert_eq!((camera.view, camera.pos, camera.center, camera.dir), before);
+        assert!(camera.view.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn block_matches_the_shaders() {
+        use layout::{Block, Field};
+
+        // As in the `Camera` block in basic.vert and basic.frag.
+        assert_eq!(CameraBlock::fields(), [
+            Field { name: Some("view"), offset: 0 },
+            Field { name: Some("pos"), offset: 64 },
+            Field { name: Some("dir"), offset: 80 },
+            Field { name: Some("top"), offset: 96 },
+        ]);
+        assert_eq!(std::mem::size_of::<CameraBlock>(), 112);
+    }
+}
@@ -0,0 +1,435 @@
+use std::marker::PhantomData;
+
+use crate::render::{
+    reflect, ChangedAssets, Core, Pass, Resource, ShaderCache, ShaderName, With,
+    InputDesc, InputHandle, OutputDesc, OutputHandle,
+};
+use crate::util::bytes;
+
+
+/// A buffer of `T`s, which compute shaders read and write, and which
+/// can then be drawn from directly as vertices.
+pub struct StorageBuffer<T> {
+    pub buffer: wgpu::Buffer,
+    len: u64,
+    marker: PhantomData<T>,
+}
+
+impl<T: Sized + bytes::IntoBytes> StorageBuffer<T> {
+    fn usage() -> wgpu::BufferUsage {
+        wgpu::BufferUsage::STORAGE
+            | wgpu::BufferUsage::VERTEX
+            | wgpu::BufferUsage::COPY_DST
+            | wgpu::BufferUsage::COPY_SRC
+    }
+
+    /// Room for `len` `T`s, zeroed.
+    pub fn new(device: &wgpu::Device, label: &str, len: u64) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: len * std::mem::size_of::<T>() as u64,
+            usage: Self::usage(),
+            mapped_at_creation: true,
+        });
+
+        // WTF: wgpu leaves new buffers' contents undefined, so they're zeroed by hand.
+        for byte in buffer.slice(..).get_mapped_range_mut().iter_mut() {
+            *byte = 0;
+        }
+        buffer.unmap();
+
+        Self { buffer, len, marker: PhantomData }
+    }
+
+    pub fn with_data(device: &wgpu::Device, data: &[T]) -> Self {
+        let buffer = device.create_buffer_with_data(bytes::of_slice(data), Self::usage());
+        Self { buffer, len: data.len() as u64, marker: PhantomData }
+    }
+
+    /// Overwrites the buffer from its start.
+    pub fn write(&self, core: &Core, data: &[T]) {
+        assert!(data.len() as u64 <= self.len, "Writing past the end of a storage buffer.");
+        core.queue.write_buffer(&self.buffer, 0, bytes::of_slice(data));
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn bound(&self) -> reflect::BoundResource {
+        reflect::BoundResource::Buffer {
+            buffer: &self.buffer,
+            size: self.len * std::mem::size_of::<T>() as u64,
+            // The shader's block usually wraps an array of `T`s, rather than being one.
+            fields: None,
+        }
+    }
+}
+
+impl<'p, T: 'p> Resource<'p> for StorageBuffer<T> {
+    type Descriptor = StorageBuffer<T>;
+    type Handle = StorageBuffer<T>;
+}
+
+
+/// A 2D texture which compute shaders write to, and later passes sample.
+pub struct StorageTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub size: wgpu::Extent3d,
+    pub format: wgpu::TextureFormat,
+}
+
+impl StorageTexture {
+    pub fn new(device: &wgpu::Device, label: &str, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let size = wgpu::Extent3d { width, height, depth: 1 };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::STORAGE
+                | wgpu::TextureUsage::SAMPLED
+                | wgpu::TextureUsage::COPY_SRC
+                | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let view = texture.create_default_view();
+
+        Self { texture, view, size, format }
+    }
+
+    pub fn bound(&self) -> reflect::BoundResource {
+        reflect::BoundResource::StorageTexture {
+            view: &self.view,
+            dimension: wgpu::TextureViewDimension::D2,
+            format: self.format,
+        }
+    }
+}
+
+impl<'p> Resource<'p> for StorageTexture {
+    type Descriptor = StorageTexture;
+    type Handle = StorageTexture;
+}
+
+
+/// How much work one `perform` of a `ComputePass` covers, in invocations.
+/// It's rounded up to whole workgroups, so shaders should check their bounds.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dispatch {
+    pub size: [u32; 3],
+}
+
+impl Dispatch {
+    /// One invocation per item, e.g. per particle.
+    pub fn items(count: u32) -> Self {
+        Self { size: [count, 1, 1] }
+    }
+
+    /// One invocation per texel.
+    pub fn texels(size: wgpu::Extent3d) -> Self {
+        Self { size: [size.width, size.height, size.depth] }
+    }
+
+    /// How many workgroups of `workgroup_size` cover the whole dispatch.
+    pub fn workgroups(&self, workgroup_size: [u32; 3]) -> [u32; 3] {
+        let mut workgroups = [0; 3];
+        for i in 0 .. 3 {
+            let group = workgroup_size[i].max(1);
+            workgroups[i] = self.size[i] / group + (self.size[i] % group != 0) as u32;
+        }
+        workgroups
+    }
+}
+
+
+/// What a `ComputePass` runs, and on what.
+pub struct ComputeConfig<'p> {
+    pub shader: ShaderName,
+    /// Each bind group's resources, by binding number, in set order.
+    pub bind_groups: &'p [&'p [(u32, reflect::BoundResource<'p>)]],
+}
+
+
+/// Runs one compute shader over whatever's bound to it.
+pub struct ComputePass {
+    shader: ShaderName,
+    /// Unset while not even the fallback shader fits, and the pass is skipped.
+    built: Option<BuiltCompute>,
+}
+
+struct BuiltCompute {
+    bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: wgpu::ComputePipeline,
+    workgroup_size: [u32; 3],
+    /// Whether the shader is the do-nothing fallback, which isn't worth
+    /// dispatching. With its workgroups of one, a big dispatch could even
+    /// go past what the device allows.
+    runs_fallback: bool,
+}
+
+impl ComputePass {
+    /// Whether the pass needs refreshing to pick up `changed` assets.
+    pub fn depends_on(&self, changed: &ChangedAssets) -> bool {
+        changed.shaders.contains(&self.shader)
+    }
+
+    /// `None` while the pass is skipped.
+    #[inline]
+    pub fn workgroup_size(&self) -> Option<[u32; 3]> {
+        self.built.as_ref().map(|built| built.workgroup_size)
+    }
+
+    fn build(device: &wgpu::Device, shaders: &ShaderCache, config: ComputeConfig) -> Self {
+        let set_count = config.bind_groups.len() as u32;
+
+        let built = shaders.build_compute("Compute pass", config.shader, |module| {
+            let layout = reflect::PipelineLayout::new(device, "Compute pass", &[&module.interface], set_count)?;
+
+            let bind_groups = config.bind_groups.iter()
+                .enumerate()
+                .map(|(set, resources)| layout.bind_group(device, set as u32, "Compute pass", resources))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &layout.layout,
+                compute_stage: module.descriptor(),
+            });
+
+            Ok(BuiltCompute {
+                bind_groups,
+                pipeline,
+                workgroup_size: module.interface.workgroup_size,
+                runs_fallback: module.is_fallback,
+            })
+        });
+
+        // Any error is already shown with the rest of the shaders'.
+        ComputePass {
+            shader: config.shader,
+            built: built.ok(),
+        }
+    }
+
+    fn dispatch(&self, device: &wgpu::Device, queue: &wgpu::Queue, dispatch: Dispatch) {
+        let built = match &self.built {
+            Some(built) if !built.runs_fallback => built,
+            _ => return,
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Compute pass"),
+        });
+
+        let [x, y, z] = dispatch.workgroups(built.workgroup_size);
+
+        let mut pass = encoder.begin_compute_pass();
+        pass.set_pipeline(&built.pipeline);
+        for (set, bind_group) in built.bind_groups.iter().enumerate() {
+            pass.set_bind_group(set as u32, bind_group, &[]);
+        }
+        pass.dispatch(x, y, z);
+
+        drop(pass); // end borrow
+
+        queue.submit(std::iter::once(
+            encoder.finish()
+        ));
+    }
+}
+
+impl<'p> Pass<'p> for ComputePass {
+
+    type Input = With<&'p Core>;
+    type Output = ();
+
+    type Config = ComputeConfig<'p>;
+    type Params = Dispatch;
+
+    fn construct(config: Self::Config, core: InputDesc<'p, Self>) -> (Self, OutputDesc<'p, Self>) {
+        (ComputePass::build(core.device, &core.shaders, config), ())
+    }
+
+    fn perform(self: &'p mut Self, dispatch: Dispatch, core: InputHandle<'p, Self>) -> OutputHandle<'p, Self> {
+        self.dispatch(core.device, core.queue, dispatch);
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::reflect::asm::{self, decoration, storage_class, Assembler};
+
+    /// `values[i] = i * 2` for each invocation `i`, in workgroups of 64,
+    /// over `layout(set = 0, binding = 0) buffer Data { uint values[]; }`.
+    fn doubling_shader() -> Vec<u32> {
+        let mut a = Assembler::new();
+        let [void, void_fn, uint, uvec3] = [a.id(), a.id(), a.id(), a.id()];
+        let [input_ptr, global_id] = [a.id(), a.id()];
+        let [values, data, data_ptr, data_var] = [a.id(), a.id(), a.id(), a.id()];
+        let [zero, two, uint_ptr] = [a.id(), a.id(), a.id()];
+        let [main, label, id_vec, id, doubled, element] = [a.id(), a.id(), a.id(), a.id(), a.id(), a.id()];
+
+        a.entry_point(asm::model::GL_COMPUTE, main, &[global_id]);
+        a.inst(asm::EXECUTION_MODE, &[main, asm::execution_mode::LOCAL_SIZE, 64, 1, 1]);
+
+        a.inst(asm::DECORATE, &[global_id, decoration::BUILT_IN, asm::built_in::GLOBAL_INVOCATION_ID]);
+        a.inst(asm::DECORATE, &[values, decoration::ARRAY_STRIDE, 4]);
+        a.inst(asm::DECORATE, &[data, decoration::BUFFER_BLOCK]);
+        a.inst(asm::MEMBER_DECORATE, &[data, 0, decoration::OFFSET, 0]);
+        a.inst(asm::DECORATE, &[data_var, decoration::DESCRIPTOR_SET, 0]);
+        a.inst(asm::DECORATE, &[data_var, decoration::BINDING, 0]);
+
+        a.inst(asm::TYPE_VOID, &[void]);
+        a.inst(asm::TYPE_FUNCTION, &[void_fn, void]);
+        a.inst(asm::TYPE_INT, &[uint, 32, 0]);
+        a.inst(asm::TYPE_VECTOR, &[uvec3, uint, 3]);
+        a.inst(asm::TYPE_POINTER, &[input_ptr, storage_class::INPUT, uvec3]);
+        a.inst(asm::VARIABLE, &[input_ptr, global_id, storage_class::INPUT]);
+        a.inst(asm::TYPE_RUNTIME_ARRAY, &[values, uint]);
+        a.inst(asm::TYPE_STRUCT, &[data, values]);
+        a.inst(asm::TYPE_POINTER, &[data_ptr, storage_class::UNIFORM, data]);
+        a.inst(asm::VARIABLE, &[data_ptr, data_var, storage_class::UNIFORM]);
+        a.inst(asm::CONSTANT, &[uint, zero, 0]);
+        a.inst(asm::CONSTANT, &[uint, two, 2]);
+        a.inst(asm::TYPE_POINTER, &[uint_ptr, storage_class::UNIFORM, uint]);
+
+        a.inst(asm::FUNCTION, &[void, main, 0, void_fn]);
+        a.inst(asm::LABEL, &[label]);
+        a.inst(asm::LOAD, &[uvec3, id_vec, global_id]);
+        a.inst(asm::COMPOSITE_EXTRACT, &[uint, id, id_vec, 0]);
+        a.inst(asm::IMUL, &[uint, doubled, id, two]);
+        a.inst(asm::ACCESS_CHAIN, &[uint_ptr, element, data_var, zero, id]);
+        a.inst(asm::STORE, &[element, doubled]);
+        a.inst(asm::RETURN, &[]);
+        a.inst(asm::FUNCTION_END, &[]);
+
+        a.finish()
+    }
+
+    /// A device on whatever Vulkan adapter there is, which on CI is a software one.
+    fn device() -> Option<(&'static wgpu::Device, &'static wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::VULKAN);
+        let adapter = futures::executor::block_on(instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::Default,
+                compatible_surface: None,
+            },
+            unsafe { wgpu::UnsafeExtensions::allow() },
+        ))?;
+
+        let (device, queue) = futures::executor::block_on(adapter.request_device(&Default::default(), None)).ok()?;
+        Some((Box::leak(Box::new(device)), Box::leak(Box::new(queue))))
+    }
+
+    fn read_back(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &StorageBuffer<u32>) -> Vec<u32> {
+        let size = buffer.len() * 4;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback"),
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&buffer.buffer, 0, &readback, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapped).expect("Failed to map the readback buffer.");
+
+        let values = slice.get_mapped_range()
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        readback.unmap();
+        values
+    }
+
+    #[test]
+    fn workgroups_cover_the_dispatch() {
+        assert_eq!(Dispatch::items(128).workgroups([64, 1, 1]), [2, 1, 1]);
+        assert_eq!(Dispatch::items(130).workgroups([64, 1, 1]), [3, 1, 1]);
+        assert_eq!(Dispatch::items(0).workgroups([64, 1, 1]), [0, 1, 1]);
+        assert_eq!(Dispatch { size: [5, 6, 7] }.workgroups([2, 0, 7]), [3, 6, 1]);
+    }
+
+    #[test]
+    fn workgroups_dont_overflow() {
+        assert_eq!(Dispatch::items(u32::MAX).workgroups([64, 1, 1]), [u32::MAX / 64 + 1, 1, 1]);
+        assert_eq!(Dispatch::items(u32::MAX).workgroups([1, 1, 1]), [u32::MAX, 1, 1]);
+    }
+
+    #[test]
+    fn dispatches_run() {
+        let (device, queue) = match device() {
+            Some(device) => device,
+            None => {
+                eprintln!("No Vulkan adapter, skipping.");
+                return;
+            },
+        };
+
+        let shaders = ShaderCache::new(device);
+        let name = ShaderName::new("doubling.comp");
+        shaders.insert_spirv(name, &doubling_shader()).unwrap();
+
+        let buffer = StorageBuffer::<u32>::new(device, "Doubled", 256);
+        let pass = ComputePass::build(device, &shaders, ComputeConfig {
+            shader: name,
+            bind_groups: &[&[(0, buffer.bound())]],
+        });
+
+        assert!(!pass.built.as_ref().unwrap().runs_fallback);
+        assert_eq!(pass.workgroup_size(), Some([64, 1, 1]));
+
+        // Rounded up to three whole workgroups, of which the last is partly past the dispatch.
+        pass.dispatch(device, queue, Dispatch::items(130));
+
+        let values = read_back(device, queue, &buffer);
+        for (i, &value) in values.iter().enumerate() {
+            let expected = if i < 192 { i as u32 * 2 } else { 0 };
+            assert_eq!(value, expected, "values[{}]", i);
+        }
+    }
+
+    #[test]
+    fn mismatched_shaders_fall_back() {
+        let (device, _queue) = match device() {
+            Some(device) => device,
+            None => {
+                eprintln!("No Vulkan adapter, skipping.");
+                return;
+            },
+        };
+
+        let shaders = ShaderCache::new(device);
+        let name = ShaderName::new("doubling.comp");
+        shaders.insert_spirv(name, &doubling_shader()).unwrap();
+
+        // The shader's buffer is left unbound.
+        let pass = ComputePass::build(device, &shaders, ComputeConfig {
+            shader: name,
+            bind_groups: &[&[]],
+        });
+
+        assert!(pass.built.unwrap().runs_fallback);
+        let errors = shaders.errors().list();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], crate::render::cache::shaders::ShaderError::Mismatch { .. }));
+    }
+}
@@ -1,6 +1,28 @@
 
+use std::time::{Duration, Instant};
+
 use crate::render::window;
-use crate::render::{ShaderCache, TextureCache, ModelCache};
+use crate::render::{ShaderCache, ShaderName, TextureCache, ModelCache};
+
+
+/// How often asset files are checked for changes.
+const ASSET_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Assets which were invalidated because their files changed on disk.
+#[derive(Clone, Debug, Default)]
+pub struct ChangedAssets {
+    pub shaders: Vec<ShaderName>,
+    pub textures: Vec<&'static str>,
+    /// Model files, rather than the names of the models in them.
+    pub models: Vec<&'static str>,
+}
+
+impl ChangedAssets {
+    pub fn is_empty(&self) -> bool {
+        self.shaders.is_empty() && self.textures.is_empty() && self.models.is_empty()
+    }
+}
 
 
 pub struct Core {
@@ -12,6 +34,7 @@ pub struct Core {
     pub shaders: ShaderCache,
     pub textures: TextureCache,
     pub models: ModelCache,
+    last_asset_poll: Instant,
 }
 
 
@@ -38,9 +61,13 @@ impl Core {
         let adapter_info = adapter.get_info();
         eprintln!("{:?}", adapter_info);
 
+        // WTF: Software adapters (e.g. lavapipe) don't offer binding indexing,
+        // and asking for an extension the adapter lacks fails outright.
+        let extensions = adapter.extensions() & wgpu::Extensions::BINDING_INDEXING;
+
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                extensions: wgpu::Extensions::BINDING_INDEXING,
+                extensions,
                 ..Default::default()
             },
             None, // trace_path
@@ -82,6 +109,24 @@ impl Core {
             shaders,
             textures,
             models,
+            last_asset_poll: Instant::now(),
+        }
+    }
+
+    /// Invalidates every cached asset whose files have changed on disk,
+    /// so that it gets reloaded the next time it's used.
+    ///
+    /// Cheap to call every frame; the files are only checked once per `ASSET_POLL_INTERVAL`.
+    pub fn invalidate_changed_assets(&mut self) -> ChangedAssets {
+        if self.last_asset_poll.elapsed() < ASSET_POLL_INTERVAL {
+            return ChangedAssets::default();
+        }
+        self.last_asset_poll = Instant::now();
+
+        ChangedAssets {
+            shaders: self.shaders.invalidate_changed(),
+            textures: self.textures.invalidate_changed(),
+            models: self.models.invalidate_changed(),
         }
     }
 
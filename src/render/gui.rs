@@ -1,7 +1,24 @@
 
 use imgui::*;
 
+use crate::script::ConsoleLog;
+
 pub mod imgui_wgpu;
+pub mod console;
+pub mod lua_panels;
+pub mod choices;
+pub mod profiler;
+pub mod shader_errors;
+pub mod debug_view;
+pub mod deck_builder;
+
+pub use console::LuaPrintBuffer;
+pub use lua_panels::LuaPanels;
+pub use choices::ChoicePrompts;
+pub use profiler::ProfilerWindow;
+pub use shader_errors::ShaderErrorOverlay;
+pub use debug_view::DebugViewPicker;
+pub use deck_builder::DeckBuilder;
 
 
 //
@@ -15,23 +32,51 @@ pub trait Widget {
     fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua);
 }
 
+impl<W: Widget + ?Sized> Widget for &mut W {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+        (**self).compose(ui, lua);
+    }
+}
+
+impl<W: Widget> Widget for Option<W> {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+        if let Some(widget) = self {
+            widget.compose(ui, lua);
+        }
+    }
+}
+
+impl<A: Widget, B: Widget> Widget for (A, B) {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+        self.0.compose(ui, lua);
+        self.1.compose(ui, lua);
+    }
+}
+
 pub struct GuiComponentState {
     demo: ImguiDemoWindow,
     lua_print: LuaPrintBuffer,
+    lua_panels: LuaPanels,
+    profiler: ProfilerWindow,
+    debug_view: DebugViewPicker,
+    deck_builder: DeckBuilder,
 }
 
 impl GuiComponentState {
-    pub fn new() -> Self {
+    pub fn new(console: ConsoleLog) -> Self {
         GuiComponentState {
             demo: ImguiDemoWindow { window_open: true },
-            lua_print: LuaPrintBuffer::new(im_str!("Hello from lua")),
+            lua_print: LuaPrintBuffer::new(im_str!("Hello from lua"), console),
+            lua_panels: LuaPanels,
+            profiler: ProfilerWindow::new(),
+            debug_view: DebugViewPicker::new(),
+            deck_builder: DeckBuilder::new(),
         }
     }
-}
 
-impl Default for GuiComponentState {
-    fn default() -> Self {
-        Self::new()
+    /// What the main pass should be showing.
+    pub fn debug_view(&self) -> crate::render::DebugView {
+        self.debug_view.picked
     }
 }
 
@@ -39,6 +84,10 @@ impl Widget for GuiComponentState {
     fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
         self.demo.compose(ui, lua);
         self.lua_print.compose(ui, lua);
+        self.lua_panels.compose(ui, lua);
+        self.profiler.compose(ui, lua);
+        self.debug_view.compose(ui, lua);
+        self.deck_builder.compose(ui, lua);
     }
 }
 
@@ -59,105 +108,3 @@ impl Widget for ImguiDemoWindow {
         ui.show_demo_window(&mut self.window_open);
     }
 }
-
-
-pub struct LuaPrintBuffer {
-    window_name: imgui::ImString,
-    printed_strings: Vec<String>,
-    console_buffer: imgui::ImString,
-}
-
-impl LuaPrintBuffer {
-    pub fn new(name: impl Into<imgui::ImString>) -> Self {
-        Self {
-            window_name: name.into(),
-            printed_strings: Vec::new(),
-            console_buffer: imgui::ImString::default(),
-        }
-    }
-
-    fn exec_lua_buffer(&mut self, lua: &rlua::Lua) {
-
-        // WTF: This way rust doesn't infer too strict
-        // a lifetime for the following closures.
-        let Self {
-            ref console_buffer,
-            ref mut printed_strings,
-            ..
-        } = self;
-
-        match lua.context(|ctx: rlua::Context| {
-            let chunk = ctx.load(console_buffer.to_str());
-            let globals = ctx.globals();
-            ctx.scope(|scope| {
-                let print_override = scope.create_function_mut(
-                    |ctx, args: rlua::MultiValue| {
-                        let mut str_args = Vec::with_capacity(args.len());
-                        for arg_val in args {
-                            let arg_str = ctx.coerce_string(arg_val)?;
-                            let arg_str = arg_str.unwrap_or(ctx.create_string("nil")?);
-                            str_args.push(arg_str.to_str()?.to_owned());
-                        }
-                        printed_strings.push(str_args.join("\t"));
-                        Ok(())
-                    }
-                )?;
-                globals.set("print", print_override)?;
-                let chunk = chunk.set_environment(globals)?;
-                chunk.exec()
-            })
-        }) {
-            Ok(()) => (),
-            Err(e) => eprintln!("{}", e),
-        }
-    }
-}
-
-
-impl Widget for LuaPrintBuffer {
-    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
-        use imgui::*;
-
-        let lua_window = Window::new(&self.window_name)
-            .size([640.0, 480.0], Condition::FirstUseEver)
-            .begin(&ui); 
-        
-        if let Some(lua_window) = lua_window {
-            
-            ui.text(im_str!("should be lua, eventually."));
-            ui.input_text_multiline(
-                im_str!("Lua Console"),
-                &mut self.console_buffer, [640.0, 400.0]
-            )
-            .allow_tab_input(true)
-            .resize_buffer(true)
-            .build();
-
-            if ui.button(im_str!("Execute"), [100.0, 20.0]) {
-                self.exec_lua_buffer(lua);
-            }
-
-            lua_window.end(&ui);
-        }
-
-
-        let out_window = Window::new(im_str!("Console output"))
-            .size([200.0, 200.0], Condition::FirstUseEver)
-            .begin(&ui);
-
-        if let Some(out_window) = out_window {
-            
-            if ui.button(im_str!("Clear"), [100.0, 20.0]) {
-                self.printed_strings.clear();
-            }
-
-            ui.separator();
-
-            for output in &self.printed_strings {
-                ui.text(output);
-            }
-
-            out_window.end(&ui);
-        }
-    }
-}
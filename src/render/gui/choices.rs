@@ -0,0 +1,114 @@
+
+use std::collections::HashMap;
+
+use imgui::*;
+
+use crate::render::gui::Widget;
+use crate::script::effects::Effects;
+
+
+#[derive(Default)]
+struct Answer {
+    picked: Vec<bool>,
+    rejected: Option<String>,
+}
+
+
+/// Lets whoever is at the keyboard answer the choices lua effects are waiting on,
+/// until there's proper UI for it.
+///
+/// Only the choices of the seat holding the device are shown, since any
+/// other player's may be private to them.
+pub struct ChoicePrompts {
+    effects: Effects,
+    answers: HashMap<u64, Answer>,
+    /// The seat at the keyboard, if it's a local one.
+    viewer: Option<usize>,
+}
+
+
+impl ChoicePrompts {
+    pub fn new(effects: Effects) -> Self {
+        Self {
+            effects,
+            answers: HashMap::new(),
+            viewer: None,
+        }
+    }
+
+    /// Shows `seat`'s choices from now on, or nobody's with `None`, e.g.
+    /// while the active seat is played from another machine.
+    pub fn set_viewer(&mut self, seat: Option<usize>) {
+        self.viewer = seat;
+    }
+}
+
+
+impl Widget for ChoicePrompts {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+
+        let pending = self.effects.pending();
+        self.answers.retain(|id, _| pending.iter().any(|(pending, _)| pending == id));
+
+        let viewer = match self.viewer {
+            Some(viewer) => viewer,
+            None => return,
+        };
+
+        // A choice without a player is for whoever's turn it is.
+        let visible = pending.into_iter()
+            .filter(|(_, request)| request.player.unwrap_or(viewer) == viewer);
+
+        for (id, request) in visible {
+            let answer = self.answers.entry(id).or_default();
+            answer.picked.resize(request.options.len(), false);
+
+            let window = Window::new(&im_str!("{}###choice{}", request.prompt, id))
+                .size([300.0, 200.0], Condition::FirstUseEver)
+                .begin(&ui);
+
+            if let Some(window) = window {
+                match (request.player, request.reaction) {
+                    (Some(player), true) => ui.text(format!("Player {} may react", player + 1)),
+                    (Some(player), false) => ui.text(format!("For player {}", player + 1)),
+                    (None, true) => ui.text("Reaction"),
+                    (None, false) => (),
+                }
+
+                if request.min == request.max {
+                    ui.text_disabled(format!("Choose {}", request.min));
+                } else {
+                    ui.text_disabled(format!("Choose {} to {}", request.min, request.max));
+                }
+
+                for (i, (option, picked)) in request.options.iter().zip(&mut answer.picked).enumerate() {
+                    let option_id = ui.push_id(i as i32);
+                    ui.checkbox(&ImString::new(option.as_str()), picked);
+                    option_id.pop(&ui);
+                }
+
+                ui.separator();
+
+                let confirmed = ui.button(im_str!("Confirm"), [100.0, 20.0]);
+
+                if let Some(rejected) = &answer.rejected {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], rejected);
+                }
+
+                window.end(&ui);
+
+                if confirmed {
+                    let picks = answer.picked.iter()
+                        .enumerate()
+                        .filter(|(_, picked)| **picked)
+                        .map(|(i, _)| i)
+                        .collect::<Vec<_>>();
+
+                    if let Err(e) = self.effects.answer(lua, id, &picks) {
+                        answer.rejected = Some(e.to_string());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+
+use imgui::*;
+use std::path::{Path, PathBuf};
+
+use crate::render::gui::Widget;
+use crate::script::{self, sandbox, ConsoleLog, LineKind};
+
+
+const HISTORY_FILE: &str = "lua_history.txt";
+const MAX_HISTORY: usize = 500;
+
+
+/// An interactive lua prompt, with its scrollback above it.
+pub struct LuaPrintBuffer {
+    window_name: ImString,
+    log: ConsoleLog,
+    shown_lines: usize,
+
+    input: ImString,
+    // Lines of a chunk which lua has reported as incomplete so far.
+    pending: Vec<String>,
+
+    history: Vec<String>,
+    history_path: PathBuf,
+    // Where we are in `history` while browsing it with the arrow keys.
+    history_pos: Option<usize>,
+
+    completions: Vec<String>,
+
+    // WTF: imgui ignores changes made to the buffer of an active text box,
+    // so whenever we rewrite the input ourselves we give the box a fresh id
+    // and focus it again, which makes imgui reload the buffer.
+    input_generation: i32,
+    input_was_active: bool,
+    reclaim_focus: bool,
+}
+
+
+impl LuaPrintBuffer {
+    /// A console showing `log`, which should be the one lua's `print` was
+    /// installed with (see `script::install_print`).
+    pub fn new(name: impl Into<ImString>, log: ConsoleLog) -> Self {
+        let history_path = PathBuf::from(HISTORY_FILE);
+        let history = load_history(&history_path);
+
+        Self {
+            window_name: name.into(),
+            log,
+            shown_lines: 0,
+
+            input: ImString::default(),
+            pending: Vec::new(),
+
+            history,
+            history_path,
+            history_pos: None,
+
+            completions: Vec::new(),
+
+            input_generation: 0,
+            input_was_active: false,
+            reclaim_focus: false,
+        }
+    }
+
+    pub fn log(&self) -> &ConsoleLog {
+        &self.log
+    }
+
+    fn replace_input(&mut self, text: &str) {
+        self.input = ImString::new(text);
+        self.input_generation += 1;
+        self.reclaim_focus = true;
+    }
+
+    fn exec_lua_buffer(&mut self, lua: &rlua::Lua) {
+        let line = self.input.to_str().to_owned();
+        self.input.clear();
+        self.completions.clear();
+        self.history_pos = None;
+
+        let prompt = if self.pending.is_empty() { "> " } else { ">> " };
+        self.log.push(LineKind::Input, format!("{}{}", prompt, line));
+
+        self.pending.push(line);
+        let source = self.pending.join("\n");
+
+        let log = &self.log;
+        let complete = sandbox::run_limited(lua, sandbox::Limits::CONSOLE, |ctx| -> rlua::Result<bool> {
+            // Try the input as an expression first, so that `1 + 1` echoes `2`.
+            let function = match ctx.load(&format!("return {}", source)).set_name("=console")?.into_function() {
+                Ok(function) => function,
+                Err(_) => match ctx.load(&source).set_name("=console")?.into_function() {
+                    Ok(function) => function,
+                    Err(rlua::Error::SyntaxError { incomplete_input: true, .. }) => return Ok(false),
+                    Err(e) => return Err(e),
+                },
+            };
+
+            let values: rlua::MultiValue = function.call(())?;
+            if !values.is_empty() {
+                log.push(LineKind::Result, script::display_values(ctx, values)?);
+            }
+
+            Ok(true)
+        });
+
+        match complete {
+            Ok(false) => return, // wait for the rest of the chunk
+            Ok(true) => (),
+            Err(e) => self.log.push(LineKind::Error, script::describe_error(&e)),
+        }
+
+        self.pending.clear();
+        self.push_history(&source);
+    }
+
+    /// Remembers each line of `chunk` as an entry of its own, so that
+    /// browsing back through a multi-line chunk re-enters it line by line.
+    fn push_history(&mut self, chunk: &str) {
+        let mut changed = false;
+
+        for line in chunk.lines() {
+            if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+                continue;
+            }
+            self.history.push(line.to_owned());
+            changed = true;
+        }
+
+        if !changed {
+            return;
+        }
+
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(.. self.history.len() - MAX_HISTORY);
+        }
+        save_history(&self.history_path, &self.history);
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        self.history_pos = match (self.history_pos, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+        };
+
+        let text = match self.history_pos {
+            Some(i) => self.history[i].clone(),
+            None => String::new(),
+        };
+
+        self.replace_input(&text);
+    }
+
+    /// Completes the dotted name at the end of the input against the
+    /// fields of the global table (and of tables reachable from it).
+    fn complete(&mut self, lua: &rlua::Lua) {
+        let input = self.input.to_str().to_owned();
+
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == ':';
+        let start = input.char_indices()
+            .rev()
+            .find(|&(_, c)| !is_name_char(c))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+
+        let path = &input[start..];
+        let prefix_start = path.rfind(|c: char| c == '.' || c == ':').map(|i| i + 1).unwrap_or(0);
+        let (parent, prefix) = path.split_at(prefix_start);
+
+        // Looking fields up can run `__index` metamethods, which are
+        // as likely to loop forever as any other lua.
+        let mut candidates = sandbox::run_limited(lua, sandbox::Limits::default(), |ctx| -> rlua::Result<Vec<String>> {
+            let mut table = ctx.globals();
+            for part in parent.split(|c: char| c == '.' || c == ':').filter(|p| !p.is_empty()) {
+                match table.get::<_, rlua::Value>(part)? {
+                    rlua::Value::Table(t) => table = t,
+                    _ => return Ok(Vec::new()),
+                }
+            }
+            table_fields(table, prefix)
+        })
+        .unwrap_or_default();
+
+        candidates.sort();
+        candidates.dedup();
+
+        let common = match candidates.first() {
+            None => return,
+            Some(first) => candidates.iter().fold(first.as_str(), |common, name| {
+                let len = common.char_indices()
+                    .zip(name.chars())
+                    .find(|&((_, a), b)| a != b)
+                    .map(|((i, _), _)| i)
+                    .unwrap_or_else(|| common.len().min(name.len()));
+                &common[..len]
+            }).to_owned(),
+        };
+
+        if common.len() > prefix.len() {
+            self.replace_input(&format!("{}{}{}", &input[..start], parent, common));
+        }
+
+        self.completions = if candidates.len() > 1 { candidates } else { Vec::new() };
+    }
+}
+
+
+/// Names of the string-keyed fields of `table`, and of the tables
+/// it inherits from through `__index`, which start with `prefix`.
+fn table_fields(table: rlua::Table, prefix: &str) -> rlua::Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut table = Some(table);
+
+    // Don't follow runaway (or cyclic) `__index` chains forever.
+    for _ in 0..8 {
+        let current = match table.take() {
+            Some(t) => t,
+            None => break,
+        };
+
+        for pair in current.clone().pairs::<rlua::Value, rlua::Value>() {
+            if let (rlua::Value::String(key), _) = pair? {
+                if let Ok(key) = key.to_str() {
+                    if key.starts_with(prefix) {
+                        names.push(key.to_owned());
+                    }
+                }
+            }
+        }
+
+        if let Some(meta) = current.get_metatable() {
+            if let rlua::Value::Table(index) = meta.raw_get::<_, rlua::Value>("__index")? {
+                table = Some(index);
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+
+fn escape_history(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_history(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => { entry.push('\n'); chars.next(); },
+            ('\\', Some('\\')) => { entry.push('\\'); chars.next(); },
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+fn load_history(path: &Path) -> Vec<String> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+
+    // Older files kept multi-line chunks as one escaped entry.
+    let mut history: Vec<String> = content.lines()
+        .map(unescape_history)
+        .flat_map(|entry| entry.lines().map(str::to_owned).collect::<Vec<_>>())
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    if history.len() > MAX_HISTORY {
+        history.drain(..history.len() - MAX_HISTORY);
+    }
+    history
+}
+
+/// Rewrites the history file with just `history`, so it never grows past `MAX_HISTORY`.
+fn save_history(path: &Path, history: &[String]) {
+    let mut content = String::new();
+    for entry in history {
+        content += &escape_history(entry);
+        content.push('\n');
+    }
+
+    if let Err(e) = std::fs::write(path, content) {
+        eprintln!("Failed to save console history: {}", e);
+    }
+}
+
+
+impl Widget for LuaPrintBuffer {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+
+        let lua_window = Window::new(&self.window_name)
+            .size([640.0, 480.0], Condition::FirstUseEver)
+            .begin(&ui);
+
+        if let Some(lua_window) = lua_window {
+
+            if ui.button(im_str!("Clear"), [100.0, 20.0]) {
+                self.log.clear();
+            }
+
+            ui.separator();
+
+            let log = &self.log;
+            let shown_lines = &mut self.shown_lines;
+
+            ChildWindow::new(im_str!("Scrollback"))
+                .size([0.0, -50.0])
+                .build(&ui, || {
+                    let lines = log.lines();
+                    for line in lines.iter() {
+                        match line.kind {
+                            LineKind::Input => ui.text_disabled(&line.text),
+                            LineKind::Output => ui.text(&line.text),
+                            LineKind::Result => ui.text_colored([0.6, 0.9, 1.0, 1.0], &line.text),
+                            LineKind::Error => ui.text_colored([1.0, 0.3, 0.3, 1.0], &line.text),
+                        }
+                    }
+                    if lines.len() != *shown_lines {
+                        *shown_lines = lines.len();
+                        ui.set_scroll_here_y();
+                    }
+                });
+
+            if self.reclaim_focus {
+                ui.set_keyboard_focus_here(FocusedWidget::Next);
+                self.reclaim_focus = false;
+            }
+
+            let prompt = if self.pending.is_empty() {
+                im_str!(">###lua_input")
+            } else {
+                im_str!(">>###lua_input")
+            };
+
+            let input_id = ui.push_id(self.input_generation);
+            let entered = ui.input_text(prompt, &mut self.input)
+                .enter_returns_true(true)
+                .resize_buffer(true)
+                .build();
+            let active = ui.is_item_active();
+            input_id.pop(&ui);
+
+            if active || self.input_was_active {
+                if ui.is_key_pressed(ui.key_index(Key::UpArrow)) {
+                    self.browse_history(true);
+                } else if ui.is_key_pressed(ui.key_index(Key::DownArrow)) {
+                    self.browse_history(false);
+                } else if ui.is_key_pressed(ui.key_index(Key::Tab)) {
+                    self.complete(lua);
+                }
+            }
+            self.input_was_active = active;
+
+            if entered {
+                self.exec_lua_buffer(lua);
+                self.reclaim_focus = true;
+            }
+
+            if !self.completions.is_empty() {
+                ui.text_disabled(self.completions.join("  "));
+            }
+
+            lua_window.end(&ui);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+
+use imgui::*;
+
+use crate::render::DebugView;
+use crate::render::gui::Widget;
+
+
+/// Picks what the main pass shows. The main loop rebuilds the pass
+/// whenever the pick differs from what it's showing.
+pub struct DebugViewPicker {
+    pub picked: DebugView,
+}
+
+impl DebugViewPicker {
+    pub fn new() -> Self {
+        Self { picked: DebugView::default() }
+    }
+}
+
+impl Default for DebugViewPicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl Widget for DebugViewPicker {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+
+        let window = Window::new(im_str!("Debug view"))
+            .always_auto_resize(true)
+            .begin(&ui);
+
+        if let Some(window) = window {
+            for view in DebugView::ALL.iter() {
+                ui.radio_button(&ImString::new(view.label()), &mut self.picked, *view);
+            }
+
+            window.end(&ui);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+
+use imgui::*;
+
+use crate::collection::{self, Cards, Collection, DeckRules};
+use crate::render::gui::Widget;
+
+
+/// Builds decks from the player's collection, checking them against the
+/// deck rules as they change. Every change is saved straight to disk.
+pub struct DeckBuilder {
+    collection: Collection,
+    rules: DeckRules,
+    // Left unset if the collection on disk couldn't be read, so that it
+    // isn't overwritten with an empty one before someone can fix it.
+    writable: bool,
+
+    // The deck being edited.
+    name: ImString,
+    cards: Cards,
+
+    // A card to add to the collection by hand, there being no other way
+    // to come by cards yet.
+    new_card: ImString,
+    new_count: i32,
+
+    error: Option<String>,
+}
+
+impl DeckBuilder {
+    /// Opens the collection stored at `collection::PATH`.
+    pub fn new() -> Self {
+        let (collection, error) = match Collection::load(collection::PATH) {
+            Ok(collection) => (collection, None),
+            Err(e) => (Collection::default(), Some(format!("Failed to load {}: {}", collection::PATH, e))),
+        };
+
+        Self {
+            writable: error.is_none(),
+            collection,
+            rules: DeckRules::default(),
+
+            name: ImString::default(),
+            cards: Cards::new(),
+
+            new_card: ImString::default(),
+            new_count: 1,
+
+            error,
+        }
+    }
+
+    fn open_deck(&mut self, name: &str) {
+        self.name = ImString::new(name);
+        self.cards = self.collection.deck(name).cloned().unwrap_or_default();
+    }
+
+    fn save(&mut self) {
+        if self.writable {
+            self.error = self.collection.save(collection::PATH).err()
+                .map(|e| format!("Failed to save {}: {}", collection::PATH, e));
+        }
+    }
+
+    fn compose_decks(&mut self, ui: &imgui::Ui) {
+        let decks = self.collection.decks().map(str::to_owned).collect::<Vec<_>>();
+        for deck in &decks {
+            let open = deck == self.name.to_str();
+            if Selectable::new(&ImString::new(deck.as_str())).selected(open).build(&ui) {
+                self.open_deck(deck);
+            }
+        }
+
+        ui.input_text(im_str!("Deck name"), &mut self.name)
+            .resize_buffer(true)
+            .build();
+
+        if ui.button(im_str!("Save deck"), [100.0, 20.0]) {
+            match self.collection.save_deck(self.name.to_str(), self.cards.clone()) {
+                Ok(()) => self.save(),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Delete deck"), [100.0, 20.0]) && self.collection.delete_deck(self.name.to_str()) {
+            self.save();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("New deck"), [100.0, 20.0]) {
+            self.name.clear();
+            self.cards.clear();
+        }
+    }
+
+    /// Every owned card, with how many of it the deck has.
+    fn compose_cards(&mut self, ui: &imgui::Ui) {
+        for (i, (card, &owned)) in self.collection.cards().iter().enumerate() {
+            let id = ui.push_id(i as i32);
+
+            let count = self.cards.get(card).copied().unwrap_or(0);
+            if ui.button(im_str!("-"), [20.0, 20.0]) && count > 0 {
+                if count == 1 {
+                    self.cards.remove(card);
+                } else {
+                    self.cards.insert(card.clone(), count - 1);
+                }
+            }
+            ui.same_line(0.0);
+            if ui.button(im_str!("+"), [20.0, 20.0]) {
+                self.cards.insert(card.clone(), count.saturating_add(1));
+            }
+            ui.same_line(0.0);
+            ui.text(format!("{} / {}  {}", count, owned, card));
+
+            id.pop(&ui);
+        }
+
+        // Cards the deck has but the collection doesn't, e.g. after they were removed.
+        for (card, count) in self.cards.iter().filter(|(card, _)| self.collection.owned(card) == 0) {
+            ui.text_disabled(format!("{} / 0  {}", count, card));
+        }
+    }
+
+    fn compose_problems(&self, ui: &imgui::Ui) {
+        let problems = self.collection.check_deck(&self.cards, self.rules);
+        if problems.is_empty() {
+            ui.text_colored([0.4, 1.0, 0.4, 1.0], "Ready to play.");
+        }
+        for problem in problems {
+            ui.text_colored([1.0, 0.6, 0.2, 1.0], problem.to_string());
+        }
+    }
+
+    fn compose_add_card(&mut self, ui: &imgui::Ui) {
+        ui.input_text(im_str!("Card"), &mut self.new_card)
+            .resize_buffer(true)
+            .build();
+        ui.input_int(im_str!("Count"), &mut self.new_count).build();
+
+        if ui.button(im_str!("Add to collection"), [150.0, 20.0]) {
+            let count = self.new_count.max(0) as u32;
+            match self.collection.add(self.new_card.to_str(), count) {
+                Ok(()) => self.save(),
+                Err(e) => self.error = Some(e.to_string()),
+            }
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Remove"), [100.0, 20.0]) {
+            let count = self.new_count.max(0) as u32;
+            if self.collection.remove(self.new_card.to_str(), count) > 0 {
+                self.save();
+            }
+        }
+    }
+}
+
+impl Default for DeckBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl Widget for DeckBuilder {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+
+        let window = Window::new(im_str!("Deck builder"))
+            .size([360.0, 480.0], Condition::FirstUseEver)
+            .begin(&ui);
+
+        if let Some(window) = window {
+            self.compose_decks(ui);
+            ui.separator();
+            self.compose_cards(ui);
+            ui.separator();
+            self.compose_problems(ui);
+            ui.separator();
+            self.compose_add_card(ui);
+
+            if let Some(error) = &self.error {
+                ui.separator();
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+            }
+
+            window.end(&ui);
+        }
+    }
+}
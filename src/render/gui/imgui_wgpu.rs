@@ -90,8 +90,8 @@ impl<'p> render::Pass<'p> for ImguiPass {
         ) = input;
 
         // Load shaders.
-        let vs_module = core.shaders.load("imgui.vert");
-        let fs_module = core.shaders.load("imgui.frag");
+        let [vs_name, fs_name] = ImguiPass::SHADERS;
+        let (vs_module, fs_module) = core.shaders.load_pipeline(vs_name, fs_name);
 
         // Create the uniform matrix buffer.
         let size = 64;
@@ -250,6 +250,16 @@ impl<'p> render::Pass<'p> for ImguiPass {
 
 impl ImguiPass {
 
+    pub const SHADERS: [render::ShaderName; 2] = [
+        render::ShaderName::new("imgui.vert"),
+        render::ShaderName::new("imgui.frag"),
+    ];
+
+    /// Whether the pass needs refreshing to pick up `changed` assets.
+    pub fn depends_on(changed: &render::ChangedAssets) -> bool {
+        changed.shaders.iter().any(|name| ImguiPass::SHADERS.contains(name))
+    }
+
     /// Render the current imgui frame.
     pub fn render<'r>(
         &'r mut self,
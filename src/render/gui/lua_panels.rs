@@ -0,0 +1,203 @@
+
+use imgui::*;
+
+use crate::render::gui::Widget;
+use crate::script::{self, sandbox};
+
+
+const PANELS_REGISTRY_KEY: &str = "tbs_gui_panels";
+
+/// The most columns imgui can lay out at once.
+const MAX_TABLE_COLUMNS: usize = 64;
+
+
+/// Debug windows defined from lua with `gui.panel(title, draw)`.
+///
+/// Each frame, `draw` is called with a `ui` table of imgui functions
+/// which are only valid for the duration of that call:
+///
+/// ```lua
+/// gui.panel("Life totals", function(ui)
+///     ui.text("Player 1: " .. life[1])
+///     if ui.button("+1") then life[1] = life[1] + 1 end
+///     scale = ui.slider("Scale", scale, 0, 10)
+///     ui.table({ "Card", "Cost" }, { { "Goblin", 1 }, { "Dragon", 7 } })
+/// end)
+/// ```
+///
+/// Adding a panel with the title of one already there replaces it, so a
+/// reloaded script doesn't double up its panels.
+pub struct LuaPanels;
+
+
+impl LuaPanels {
+
+    /// Installs the global `gui` table, through which scripts add and remove panels.
+    pub fn install(lua: &rlua::Lua) -> rlua::Result<()> {
+        lua.context(|ctx| -> rlua::Result<()> {
+            ctx.set_named_registry_value(PANELS_REGISTRY_KEY, ctx.create_table()?)?;
+
+            let gui = ctx.create_table()?;
+
+            gui.set("panel", ctx.create_function(|ctx, (title, draw): (String, rlua::Function)| {
+                check_title(&title)?;
+                let panels: rlua::Table = ctx.named_registry_value(PANELS_REGISTRY_KEY)?;
+                panels.set(title, draw)
+            })?)?;
+
+            gui.set("remove_panel", ctx.create_function(|ctx, title: String| {
+                let panels: rlua::Table = ctx.named_registry_value(PANELS_REGISTRY_KEY)?;
+                panels.set(title, rlua::Nil)
+            })?)?;
+
+            ctx.globals().set("gui", gui)
+        })
+    }
+}
+
+
+/// Titles double as imgui window ids, which can't be empty, and which
+/// `##` would let two differently titled panels share.
+fn check_title(title: &str) -> rlua::Result<()> {
+    if title.is_empty() || title.contains("##") || title.contains('\0') {
+        return Err(rlua::Error::RuntimeError(format!(
+            "invalid panel title {:?}: titles can't be empty, or contain '##' or NUL", title
+        )));
+    }
+    Ok(())
+}
+
+fn panel_titles(lua: &rlua::Lua) -> rlua::Result<Vec<String>> {
+    lua.context(|ctx| {
+        let panels: rlua::Table = ctx.named_registry_value(PANELS_REGISTRY_KEY)?;
+        let mut titles = Vec::new();
+        for pair in panels.pairs::<String, rlua::Value>() {
+            titles.push(pair?.0);
+        }
+        titles.sort();
+        Ok(titles)
+    })
+}
+
+
+fn draw_panel(ui: &imgui::Ui, lua: &rlua::Lua, title: &str) -> rlua::Result<()> {
+    sandbox::run_limited(lua, sandbox::Limits::default(), |ctx| {
+        let panels: rlua::Table = ctx.named_registry_value(PANELS_REGISTRY_KEY)?;
+
+        // An earlier panel may have removed this one during this frame.
+        let draw = match panels.get::<_, rlua::Value>(title)? {
+            rlua::Value::Function(draw) => draw,
+            _ => return Ok(()),
+        };
+
+        ctx.scope(|scope| {
+            let ui_table = ctx.create_table()?;
+
+            ui_table.set("text", scope.create_function(|ctx, args: rlua::MultiValue| {
+                ui.text(script::display_values(ctx, args)?);
+                Ok(())
+            })?)?;
+
+            ui_table.set("button", scope.create_function(|_, label: String| {
+                Ok(ui.button(&ImString::new(label), [0.0, 0.0]))
+            })?)?;
+
+            ui_table.set("checkbox", scope.create_function(|_, (label, mut value): (String, bool)| {
+                ui.checkbox(&ImString::new(label), &mut value);
+                Ok(value)
+            })?)?;
+
+            ui_table.set("slider", scope.create_function(
+                |_, (label, mut value, min, max): (String, f32, f32, f32)| {
+                    Slider::new(&ImString::new(label), min..=max).build(ui, &mut value);
+                    Ok(value)
+                }
+            )?)?;
+
+            ui_table.set("slider_int", scope.create_function(
+                |_, (label, mut value, min, max): (String, i32, i32, i32)| {
+                    Slider::new(&ImString::new(label), min..=max).build(ui, &mut value);
+                    Ok(value)
+                }
+            )?)?;
+
+            ui_table.set("separator", scope.create_function(|_, ()| {
+                ui.separator();
+                Ok(())
+            })?)?;
+
+            ui_table.set("same_line", scope.create_function(|_, ()| {
+                ui.same_line(0.0);
+                Ok(())
+            })?)?;
+
+            ui_table.set("table", scope.create_function(
+                |ctx, (headers, rows): (Vec<String>, Vec<Vec<rlua::Value>>)| {
+                    if headers.is_empty() || headers.len() > MAX_TABLE_COLUMNS {
+                        return Err(rlua::Error::RuntimeError(format!(
+                            "ui.table needs between 1 and {} headers, not {}", MAX_TABLE_COLUMNS, headers.len()
+                        )));
+                    }
+
+                    ui.columns(headers.len() as i32, im_str!("lua_table"), true);
+                    ui.separator();
+
+                    for header in &headers {
+                        ui.text(header);
+                        ui.next_column();
+                    }
+
+                    ui.separator();
+
+                    // WTF: Cells can run lua (`__tostring`), which can fail, and the
+                    // columns have to be put back either way, or the rest of the
+                    // window ends up squeezed into the table's first column.
+                    let drawn = rows.into_iter().try_for_each(|row| {
+                        for cell in row.into_iter().chain(std::iter::repeat(rlua::Nil)).take(headers.len()) {
+                            let cell = script::display_values(ctx, rlua::MultiValue::from_vec(vec![cell]))?;
+                            ui.text(cell);
+                            ui.next_column();
+                        }
+                        Ok(())
+                    });
+
+                    ui.columns(1, im_str!("lua_table"), false);
+                    ui.separator();
+                    drawn
+                }
+            )?)?;
+
+            draw.call::<_, ()>(ui_table)
+        })
+    })
+}
+
+
+impl Widget for LuaPanels {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+
+        let titles = match panel_titles(lua) {
+            Ok(titles) => titles,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            },
+        };
+
+        for title in titles {
+            // The id suffix keeps panels apart from the game's own windows.
+            let window = Window::new(&ImString::new(format!("{}##lua_panel", title)))
+                .size([300.0, 200.0], Condition::FirstUseEver)
+                .begin(&ui);
+
+            if let Some(window) = window {
+                // Keep a broken panel on screen, showing what broke it.
+                if let Err(e) = draw_panel(ui, lua, &title) {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], script::describe_error(&e));
+                }
+
+                window.end(&ui);
+            }
+        }
+    }
+}
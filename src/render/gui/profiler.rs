@@ -0,0 +1,142 @@
+
+use imgui::*;
+
+use crate::render::gui::Widget;
+use crate::script::profiler::{Cost, Profiler};
+
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SortBy {
+    SelfTime,
+    TotalTime,
+    Instructions,
+    Calls,
+}
+
+impl SortBy {
+    fn key(self, cost: &Cost) -> u128 {
+        match self {
+            SortBy::SelfTime => cost.self_time.as_nanos(),
+            SortBy::TotalTime => cost.total_time.as_nanos(),
+            SortBy::Instructions => cost.instructions as u128,
+            SortBy::Calls => cost.calls as u128,
+        }
+    }
+}
+
+
+/// Shows what lua has been spending its time on, most expensive first.
+pub struct ProfilerWindow {
+    profiler: Profiler,
+    by_script: bool,
+    sort_by: SortBy,
+}
+
+impl ProfilerWindow {
+    pub fn new() -> Self {
+        Self {
+            profiler: Profiler::new(),
+            by_script: false,
+            sort_by: SortBy::SelfTime,
+        }
+    }
+}
+
+impl Default for ProfilerWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+fn millis(duration: std::time::Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+
+impl Widget for ProfilerWindow {
+    fn compose(&mut self, ui: &imgui::Ui, lua: &rlua::Lua) {
+
+        let window = Window::new(im_str!("Lua profiler"))
+            .size([640.0, 320.0], Condition::FirstUseEver)
+            .begin(&ui);
+
+        if let Some(window) = window {
+
+            if self.profiler.is_recording() {
+                if ui.button(im_str!("Stop"), [100.0, 20.0]) {
+                    if let Err(e) = self.profiler.stop(lua) {
+                        eprintln!("{}", e);
+                    }
+                }
+            } else if ui.button(im_str!("Record"), [100.0, 20.0]) {
+                if let Err(e) = self.profiler.start(lua) {
+                    eprintln!("{}", e);
+                }
+            }
+
+            ui.same_line(0.0);
+            if ui.button(im_str!("Reset"), [100.0, 20.0]) {
+                self.profiler.reset();
+            }
+
+            ui.same_line(0.0);
+            ui.checkbox(im_str!("By script"), &mut self.by_script);
+
+            ui.radio_button(im_str!("Self time"), &mut self.sort_by, SortBy::SelfTime);
+            ui.same_line(0.0);
+            ui.radio_button(im_str!("Total time"), &mut self.sort_by, SortBy::TotalTime);
+            ui.same_line(0.0);
+            ui.radio_button(im_str!("Instructions"), &mut self.sort_by, SortBy::Instructions);
+            ui.same_line(0.0);
+            ui.radio_button(im_str!("Calls"), &mut self.sort_by, SortBy::Calls);
+
+            let mut rows = {
+                let profile = self.profiler.profile();
+                if self.by_script {
+                    profile.scripts().into_iter()
+                        .map(|(script, cost)| (script.to_owned(), cost))
+                        .collect::<Vec<_>>()
+                } else {
+                    profile.functions()
+                        .map(|(function, cost)| (function.to_string(), *cost))
+                        .collect::<Vec<_>>()
+                }
+            };
+
+            let first_header = if self.by_script { "Script" } else { "Function" };
+            let sort_by = self.sort_by;
+            rows.sort_by_key(|(_, cost)| std::cmp::Reverse(sort_by.key(cost)));
+
+            ui.separator();
+
+            ChildWindow::new(im_str!("Costs")).build(&ui, || {
+                ui.columns(5, im_str!("lua_profile"), true);
+
+                for header in &[first_header, "Calls", "Self ms", "Total ms", "Instructions"] {
+                    ui.text(header);
+                    ui.next_column();
+                }
+
+                ui.separator();
+
+                for (name, cost) in &rows {
+                    ui.text(name);
+                    ui.next_column();
+                    ui.text(cost.calls.to_string());
+                    ui.next_column();
+                    ui.text(millis(cost.self_time));
+                    ui.next_column();
+                    ui.text(millis(cost.total_time));
+                    ui.next_column();
+                    ui.text(cost.instructions.to_string());
+                    ui.next_column();
+                }
+
+                ui.columns(1, im_str!("lua_profile"), false);
+            });
+
+            window.end(&ui);
+        }
+    }
+}
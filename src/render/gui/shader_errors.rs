@@ -0,0 +1,69 @@
+
+use imgui::*;
+
+use crate::render::cache::AssetErrors;
+use crate::render::cache::shaders::{ShaderError, ShaderErrors};
+use crate::render::gui::Widget;
+
+
+/// Lists the shaders which currently fail to compile, and any other
+/// assets which fail to load, over the top of everything else, until
+/// they're fixed.
+pub struct ShaderErrorOverlay {
+    errors: ShaderErrors,
+    assets: Vec<AssetErrors>,
+}
+
+impl ShaderErrorOverlay {
+    pub fn new(errors: ShaderErrors, assets: Vec<AssetErrors>) -> Self {
+        Self { errors, assets }
+    }
+}
+
+
+impl Widget for ShaderErrorOverlay {
+    fn compose(&mut self, ui: &imgui::Ui, _lua: &rlua::Lua) {
+
+        let errors = self.errors.list();
+        let assets = self.assets.iter()
+            .flat_map(AssetErrors::list)
+            .collect::<Vec<_>>();
+        if errors.is_empty() && assets.is_empty() {
+            return;
+        }
+
+        let overlay = Window::new(im_str!("Asset errors"))
+            .position([10.0, 10.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .bg_alpha(0.85)
+            .begin(&ui);
+
+        if let Some(overlay) = overlay {
+            for error in errors {
+                ui.text(error.name().to_string());
+
+                let details = match &error {
+                    ShaderError::Compile { diagnostics, .. } => diagnostics.trim_end().to_owned(),
+                    error => error.to_string(),
+                };
+
+                // One line per diagnostic, each already led by `file:line:`.
+                for line in details.lines() {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], line);
+                }
+
+                ui.separator();
+            }
+
+            for (asset, error) in &assets {
+                ui.text(asset);
+                ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
+                ui.separator();
+            }
+
+            ui.text_disabled("Using the last working (or fallback) assets until these are fixed.");
+
+            overlay.end(&ui);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Types for structs the GPU reads, laid out the way GLSL blocks are.
+//!
+//! Under both std140 (uniform blocks) and std430 (storage blocks), a `vec3`
+//! is aligned to 16 bytes, unlike `glm::Vec3`. The padded types here line a
+//! `#[repr(C)]` struct up with the block it stands for, as long as its fields
+//! are in the same order. Two things they don't cover, which the check
+//! against reflection (see `Block`) catches instead:
+//! - GLSL packs a scalar into the 4 bytes after a `vec3`, where `Vec3` has its padding.
+//! - std140 arrays round each element up to 16 bytes; use `Vec4`s, or std430.
+
+use nalgebra_glm as glm;
+use crate::util::bytes;
+
+
+macro_rules! padded {
+    ($(#[$meta:meta])* $name:ident($inner:ty $(, $pad:ty)?), align($align:literal)) => {
+        $(#[$meta])*
+        #[repr(C, align($align))]
+        #[derive(Copy, Clone, Debug, Default, PartialEq)]
+        pub struct $name(pub $inner $(, $pad)?);
+
+        // Any padding is a field of its own, so every byte is initialized.
+        const _: [(); std::mem::size_of::<$name>()] = [(); std::mem::size_of::<$inner>() $(+ std::mem::size_of::<$pad>())?];
+
+        unsafe impl bytes::IntoBytes for $name {}
+
+        impl From<$inner> for $name {
+            #[inline]
+            fn from(inner: $inner) -> Self {
+                $name(inner $(, <$pad>::default())?)
+            }
+        }
+
+        impl std::ops::Deref for $name {
+            type Target = $inner;
+            #[inline]
+            fn deref(&self) -> &$inner {
+                &self.0
+            }
+        }
+
+        impl std::ops::DerefMut for $name {
+            #[inline]
+            fn deref_mut(&mut self) -> &mut $inner {
+                &mut self.0
+            }
+        }
+    }
+}
+
+padded!(
+    /// A `vec2`.
+    Vec2(glm::Vec2), align(8)
+);
+
+padded!(
+    /// A `vec3`, which takes up 16 bytes.
+    Vec3(glm::Vec3, f32), align(16)
+);
+
+padded!(
+    /// A `vec4`.
+    Vec4(glm::Vec4), align(16)
+);
+
+padded!(
+    /// A `mat4`.
+    Mat4(glm::Mat4), align(16)
+);
+
+/// A `mat3`, whose columns are each padded out like a `vec3`.
+#[repr(C, align(16))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Mat3(pub [Vec3; 3]);
+
+const _: [(); std::mem::size_of::<Mat3>()] = [(); 3 * std::mem::size_of::<Vec3>()];
+
+unsafe impl bytes::IntoBytes for Mat3 {}
+
+impl From<glm::Mat3> for Mat3 {
+    fn from(m: glm::Mat3) -> Self {
+        Mat3([
+            m.column(0).into_owned().into(),
+            m.column(1).into_owned().into(),
+            m.column(2).into_owned().into(),
+        ])
+    }
+}
+
+
+/// Where one of a block's fields is, in bytes from its start.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// `None` if the field stands for the whole block, and so can be
+    /// called anything in the shaders.
+    pub name: Option<&'static str>,
+    pub offset: u64,
+}
+
+/// A struct standing in for a GLSL block, field for field and in the same order.
+///
+/// Implement it with `crate::block!`, which finds each field's offset. When the
+/// struct is bound to a pipeline, those offsets are checked against the
+/// offsets of the block's members, as reflected from the shaders.
+///
+/// Unsafe to implement, since `fields` has to tell the truth.
+pub unsafe trait Block: bytes::IntoBytes {
+    fn fields() -> Vec<Field>;
+}
+
+/// Implements `Block` for a `#[repr(C)]` struct, given its fields in order.
+///
+/// `fields` panics if the struct has padding between or after its fields,
+/// since those bytes would be uninitialized. Pad with explicit fields instead.
+#[macro_export]
+macro_rules! block {
+    ($t:ty { $($field:ident),* $(,)? }) => {
+        unsafe impl $crate::util::bytes::IntoBytes for $t {}
+
+        unsafe impl $crate::render::layout::Block for $t {
+            fn fields() -> Vec<$crate::render::layout::Field> {
+                let block = std::mem::MaybeUninit::<$t>::uninit();
+                let base = block.as_ptr();
+                let mut size = 0;
+                let fields = vec![$({
+                    // WTF: Only ever takes addresses, never references or
+                    // reads the uninitialized fields.
+                    let field = unsafe { std::ptr::addr_of!((*base).$field) };
+                    size += $crate::render::layout::size_of_pointee(field);
+                    $crate::render::layout::Field {
+                        name: Some(stringify!($field)),
+                        offset: (field as usize - base as usize) as u64,
+                    }
+                },)*];
+                assert_eq!(size, std::mem::size_of::<$t>(),
+                    "`{}` has padding between or after its fields", stringify!($t));
+                fields
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn size_of_pointee<T>(_: *const T) -> usize {
+    std::mem::size_of::<T>()
+}
+
+// A block with a lone `mat4` in it, as for projection matrices.
+unsafe impl Block for glm::Mat4 {
+    fn fields() -> Vec<Field> {
+        vec![Field { name: None, offset: 0 }]
+    }
+}
+
+
+/// Something which can be uploaded as a `Block`, like a camera, whose
+/// shaders only see part of it.
+pub trait AsBlock {
+    type Block: Block;
+    fn as_block(&self) -> Self::Block;
+}
+
+impl AsBlock for glm::Mat4 {
+    type Block = glm::Mat4;
+
+    #[inline]
+    fn as_block(&self) -> glm::Mat4 {
+        *self
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_types_are_the_size_of_glsls() {
+        assert_eq!(std::mem::size_of::<Vec2>(), 8);
+        assert_eq!(std::mem::size_of::<Vec3>(), 16);
+        assert_eq!(std::mem::size_of::<Vec4>(), 16);
+        assert_eq!(std::mem::size_of::<Mat3>(), 48);
+        assert_eq!(std::mem::size_of::<Mat4>(), 64);
+
+        let m = Mat3::from(glm::mat3(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0));
+        assert_eq!(*m.0[1], glm::vec3(2.0, 5.0, 8.0));
+        assert_eq!(m.0[1].1, 0.0);
+    }
+
+    // Only its layout matters.
+    #[allow(dead_code)]
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct Packed {
+        a: f32,
+        b: Vec3,
+    }
+
+    crate::block!(Packed { a, b });
+
+    #[test]
+    #[should_panic(expected = "`Packed` has padding")]
+    fn blocks_with_padding_are_refused() {
+        Packed::fields();
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::render::{self, reflect, gui::imgui_wgpu::ImguiPass};
+use crate::render::cache::shaders::{self, ShaderKind, ShaderName, ShaderOptions, PRECOMPILED_DIR, SHADER_DIR};
+use crate::util::bytes;
+
+
+/// Every permutation the game asks for, beyond each file's plain version.
+fn permutations() -> impl Iterator<Item = ShaderName> {
+    render::BasicPass::all_shaders()
+        .chain(render::PostPass::SHADERS.iter().copied())
+        .chain(ImguiPass::SHADERS.iter().copied())
+}
+
+fn shader_files(dir: &Path) -> std::io::Result<Vec<ShaderName>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if ShaderKind::of(&file_name).is_some() {
+            // WTF: Shader names are `'static`, since the game only ever
+            // has a handful. This runs once and exits, so leaking is fine.
+            names.push(ShaderName::new(Box::leak(file_name.into_boxed_str())));
+        }
+    }
+    Ok(names)
+}
+
+fn check_wgsl(name: ShaderName) -> Result<(), shaders::ShaderError> {
+    let path = Path::new(SHADER_DIR).join(name.file);
+    let source = std::fs::read_to_string(&path)
+        .map_err(|e| shaders::ShaderError::Load { name, reason: format!("{}: {}", path.display(), e) })?;
+
+    let spirv = shaders::translate_wgsl(name, &source)?;
+    reflect::reflect(&spirv)
+        .map_err(|error| shaders::ShaderError::Interface { name, error })?;
+    Ok(())
+}
+
+fn write_spirv(path: &Path, spirv: &[u32]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, bytes::of_slice(spirv))
+}
+
+
+/// Compiles every shader in `assets/shaders`, and every permutation the
+/// game uses, into `PRECOMPILED_DIR`, for builds without shaderc to load.
+/// WGSL shaders are only checked, since every build loads them from source.
+/// Also regenerates the fallback shaders in `BUILTIN_DIR`.
+///
+/// Reports every error and warning, rather than stopping at the first.
+/// Returns the process exit code.
+pub fn run_cli(args: impl Iterator<Item = String>) -> i32 {
+    let mut options = ShaderOptions::default();
+    for arg in args {
+        match arg.as_str() {
+            "--debug" => options = ShaderOptions::debug(),
+            "--release" => options = ShaderOptions::release(),
+            _ => {
+                eprintln!("Unknown argument: {}\nusage: compile-shaders [--debug | --release]", arg);
+                return 2;
+            },
+        }
+    }
+
+    // The point is to hear about every problem, release build or not.
+    options.suppress_warnings = false;
+
+    println!("Compiling with {:?}", options);
+
+    let files = match shader_files(Path::new(SHADER_DIR)) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}: {}", SHADER_DIR, e);
+            return 2;
+        },
+    };
+
+    let names = files.into_iter().chain(permutations()).collect::<BTreeSet<_>>();
+
+    let mut compiler = shaderc::Compiler::new().expect("Failed to initialize glsl compiler.");
+
+    let mut compiled = 0;
+    let mut failed = 0;
+    let mut warnings = 0;
+
+    for name in names {
+        // WGSL is loaded from source even without shaderc, so just check it.
+        if name.is_wgsl() {
+            match check_wgsl(name) {
+                Ok(()) => println!("{} ... ok, loaded from source", name),
+                Err(e) => {
+                    eprintln!("{} ... FAILED\n{}\n", name, e);
+                    failed += 1;
+                },
+            }
+            continue;
+        }
+
+        let spirv = shaders::compile_uncached(&mut compiler, &options, name)
+            .and_then(|artifact| {
+                // Shaders the game can't lay out are as broken as ones which don't compile.
+                reflect::reflect(artifact.as_binary())
+                    .map_err(|error| shaders::ShaderError::Interface { name, error })?;
+                Ok(artifact)
+            });
+
+        match spirv {
+            Ok(artifact) => {
+                if artifact.get_num_warnings() != 0 {
+                    println!("{} ... ok, with warnings\n{}", name, artifact.get_warning_messages());
+                    warnings += artifact.get_num_warnings();
+                } else {
+                    println!("{} ... ok", name);
+                }
+
+                let path = name.precompiled_path();
+                if let Err(e) = write_spirv(&path, artifact.as_binary()) {
+                    eprintln!("{}: failed to write\n{}\n", path.display(), e);
+                    failed += 1;
+                    continue;
+                }
+                compiled += 1;
+            },
+            Err(e) => {
+                eprintln!("{} ... FAILED\n{}\n", name, e);
+                failed += 1;
+            },
+        }
+    }
+
+    for &kind in &ShaderKind::ALL {
+        let artifact = shaders::compile_fallback(&mut compiler, kind);
+        let path = kind.builtin_fallback_path();
+        if let Err(e) = write_spirv(&path, artifact.as_binary()) {
+            eprintln!("{}: failed to write\n{}\n", path.display(), e);
+            failed += 1;
+        }
+    }
+
+    println!("\n{} compiled into {}, {} failed, {} warnings", compiled, PRECOMPILED_DIR, failed, warnings);
+
+    if failed == 0 { 0 } else { 1 }
+}
@@ -0,0 +1,1157 @@
+
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroU64;
+
+use crate::render::cache::shaders::SPIRV_MAGIC;
+use crate::render::layout;
+
+
+// The few parts of the SPIR-V spec reflection needs.
+mod op {
+    pub const NAME: u32 = 5;
+    pub const MEMBER_NAME: u32 = 6;
+    pub const ENTRY_POINT: u32 = 15;
+    pub const EXECUTION_MODE: u32 = 16;
+    pub const TYPE_BOOL: u32 = 20;
+    pub const TYPE_INT: u32 = 21;
+    pub const TYPE_FLOAT: u32 = 22;
+    pub const TYPE_VECTOR: u32 = 23;
+    pub const TYPE_MATRIX: u32 = 24;
+    pub const TYPE_IMAGE: u32 = 25;
+    pub const TYPE_SAMPLER: u32 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u32 = 27;
+    pub const TYPE_ARRAY: u32 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u32 = 29;
+    pub const TYPE_STRUCT: u32 = 30;
+    pub const TYPE_POINTER: u32 = 32;
+    pub const CONSTANT: u32 = 43;
+    pub const VARIABLE: u32 = 59;
+    pub const DECORATE: u32 = 71;
+    pub const MEMBER_DECORATE: u32 = 72;
+}
+
+mod execution_mode {
+    pub const LOCAL_SIZE: u32 = 17;
+}
+
+mod decoration {
+    pub const BLOCK: u32 = 2;
+    pub const BUFFER_BLOCK: u32 = 3;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BUILT_IN: u32 = 11;
+    pub const NON_WRITABLE: u32 = 24;
+    pub const NON_READABLE: u32 = 25;
+    pub const LOCATION: u32 = 30;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+mod storage_class {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT: u32 = 1;
+    pub const UNIFORM: u32 = 2;
+    pub const PUSH_CONSTANT: u32 = 9;
+    pub const STORAGE_BUFFER: u32 = 12;
+}
+
+
+/// What a shader expects to find bound at some `(set, binding)`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindingKind {
+    /// `size` is how many bytes of the buffer the shader reads.
+    UniformBuffer { size: u64 },
+    /// `size` excludes any trailing runtime-sized array.
+    StorageBuffer { size: u64, readonly: bool },
+    SampledTexture {
+        dimension: wgpu::TextureViewDimension,
+        component_type: wgpu::TextureComponentType,
+        multisampled: bool,
+    },
+    StorageTexture {
+        dimension: wgpu::TextureViewDimension,
+        format: wgpu::TextureFormat,
+        readonly: bool,
+    },
+    // WTF: Whether a sampler compares depends on how it's used,
+    // which SPIR-V doesn't record on the sampler itself.
+    Sampler,
+}
+
+impl BindingKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            BindingKind::UniformBuffer { .. } => "a uniform buffer",
+            BindingKind::StorageBuffer { .. } => "a storage buffer",
+            BindingKind::SampledTexture { .. } => "a sampled texture",
+            BindingKind::StorageTexture { .. } => "a storage texture",
+            BindingKind::Sampler => "a sampler",
+        }
+    }
+
+    fn binding_type(&self) -> wgpu::BindingType {
+        match *self {
+            BindingKind::UniformBuffer { size } => wgpu::BindingType::UniformBuffer {
+                dynamic: false,
+                min_binding_size: NonZeroU64::new(size),
+            },
+            BindingKind::StorageBuffer { size, readonly } => wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                min_binding_size: NonZeroU64::new(size),
+                readonly,
+            },
+            BindingKind::SampledTexture { dimension, component_type, multisampled } =>
+                wgpu::BindingType::SampledTexture { dimension, component_type, multisampled },
+            BindingKind::StorageTexture { dimension, format, readonly } =>
+                wgpu::BindingType::StorageTexture { dimension, format, readonly },
+            BindingKind::Sampler => wgpu::BindingType::Sampler { comparison: false },
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockMember {
+    /// `None` if the shader was compiled without member names.
+    pub name: Option<String>,
+    pub offset: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// The variable's name, or for an anonymous block, the block's.
+    pub name: String,
+    pub kind: BindingKind,
+    /// For buffers, the block's members, in order. Empty otherwise.
+    pub members: Vec<BlockMember>,
+}
+
+#[derive(Clone, Debug)]
+pub struct VertexInput {
+    pub location: u32,
+    pub name: String,
+    pub format: wgpu::VertexFormat,
+}
+
+/// Everything a compiled shader needs from the pipeline it's used in.
+#[derive(Clone, Debug)]
+pub struct ShaderInterface {
+    pub stage: wgpu::ShaderStage,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Only vertex shaders have any.
+    pub vertex_inputs: Vec<VertexInput>,
+    /// How many invocations make up one workgroup, for compute shaders.
+    pub workgroup_size: [u32; 3],
+}
+
+
+/// Why a shader's interface couldn't be reflected, or doesn't match what the pipeline provides.
+#[derive(Clone, Debug)]
+pub enum InterfaceError {
+    Malformed(&'static str),
+    Unsupported { name: String, reason: &'static str },
+    /// Two stages declare the same binding differently.
+    Conflict { set: u32, binding: u32, name: String },
+    SetOutOfRange { set: u32, name: String },
+    MissingResource { set: u32, binding: u32, name: String },
+    WrongResource { set: u32, binding: u32, name: String, expected: &'static str },
+    BufferTooSmall { set: u32, binding: u32, name: String, needed: u64, provided: u64 },
+    /// A storage texture isn't the format or dimension the shader declares.
+    WrongStorageTexture {
+        set: u32,
+        binding: u32,
+        name: String,
+        expected: (wgpu::TextureViewDimension, wgpu::TextureFormat),
+        provided: (wgpu::TextureViewDimension, wgpu::TextureFormat),
+    },
+    /// A block member isn't named the same as the field in its place in the Rust struct bound to it.
+    WrongField { set: u32, binding: u32, name: String, provided: &'static str },
+    /// A block member isn't where the Rust struct bound to it puts the matching field.
+    WrongOffset { set: u32, binding: u32, name: String, expected: u64, provided: Option<u64> },
+    MissingVertexInput { location: u32, name: String },
+    WrongVertexFormat { location: u32, name: String, expected: wgpu::VertexFormat, provided: wgpu::VertexFormat },
+}
+
+impl std::fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InterfaceError::Malformed(reason) =>
+                write!(f, "Malformed SPIR-V: {}", reason),
+            InterfaceError::Unsupported { name, reason } =>
+                write!(f, "`{}` is not supported: {}", name, reason),
+            InterfaceError::Conflict { set, binding, name } =>
+                write!(f, "`{}` (set {}, binding {}) is declared differently between stages", name, set, binding),
+            InterfaceError::SetOutOfRange { set, name } =>
+                write!(f, "`{}` is in set {}, but the pipeline has no bind group there", name, set),
+            InterfaceError::MissingResource { set, binding, name } =>
+                write!(f, "Nothing is bound to `{}` (set {}, binding {})", name, set, binding),
+            InterfaceError::WrongResource { set, binding, name, expected } =>
+                write!(f, "`{}` (set {}, binding {}) needs {}", name, set, binding, expected),
+            InterfaceError::BufferTooSmall { set, binding, name, needed, provided } =>
+                write!(f, "`{}` (set {}, binding {}) reads {} bytes, but its buffer only has {}",
+                    name, set, binding, needed, provided),
+            InterfaceError::WrongStorageTexture { set, binding, name, expected, provided } =>
+                write!(f, "`{}` (set {}, binding {}) is a {:?} {:?} texture, but a {:?} {:?} one is bound",
+                    name, set, binding, expected.0, expected.1, provided.0, provided.1),
+            InterfaceError::WrongField { set, binding, name, provided } =>
+                write!(f, "`{}` (set {}, binding {}) is where its buffer has `{}`",
+                    name, set, binding, provided),
+            InterfaceError::WrongOffset { set, binding, name, expected, provided: Some(offset) } =>
+                write!(f, "`{}` (set {}, binding {}) is at offset {}, but its buffer has it at {}",
+                    name, set, binding, expected, offset),
+            InterfaceError::WrongOffset { set, binding, name, expected, provided: None } =>
+                write!(f, "`{}` (set {}, binding {}) is at offset {}, past the end of its buffer's fields",
+                    name, set, binding, expected),
+            InterfaceError::MissingVertexInput { location, name } =>
+                write!(f, "No vertex buffer provides `{}` (location {})", name, location),
+            InterfaceError::WrongVertexFormat { location, name, expected, provided } =>
+                write!(f, "`{}` (location {}) is {:?}, but its vertex buffer provides {:?}",
+                    name, location, expected, provided),
+        }
+    }
+}
+
+impl std::error::Error for InterfaceError {}
+
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ScalarKind {
+    Bool,
+    Float,
+    Sint,
+    Uint,
+}
+
+enum Type {
+    Scalar { kind: ScalarKind, width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { sampled_type: u32, dim: u32, arrayed: bool, multisampled: bool, sampled: u32, format: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+    non_writable: bool,
+    non_readable: bool,
+    array_stride: Option<u32>,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    non_writable: bool,
+}
+
+
+/// The parts of a SPIR-V module which describe its interface.
+#[derive(Default)]
+struct Module {
+    stage: Option<wgpu::ShaderStage>,
+    workgroup_size: Option<[u32; 3]>,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), MemberDecorations>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// Each variable's id, pointer type and storage class.
+    variables: Vec<(u32, u32, u32)>,
+}
+
+fn literal_string(words: &[u32]) -> String {
+    let bytes = words.iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(spirv: &[u32]) -> Result<Self, InterfaceError> {
+        if spirv.len() < 5 || spirv[0] != SPIRV_MAGIC {
+            return Err(InterfaceError::Malformed("missing header"));
+        }
+
+        let mut module = Module::default();
+        let mut rest = &spirv[5..];
+
+        while let Some(&first) = rest.first() {
+            let opcode = first & 0xffff;
+            let count = (first >> 16) as usize;
+            if count == 0 || count > rest.len() {
+                return Err(InterfaceError::Malformed("truncated instruction"));
+            }
+
+            let (instruction, next) = rest.split_at(count);
+            rest = next;
+
+            module.read(opcode, &instruction[1..])
+                .ok_or(InterfaceError::Malformed("instruction is missing operands"))?;
+        }
+
+        Ok(module)
+    }
+
+    /// Fails only if the instruction is too short for its opcode.
+    fn read(&mut self, opcode: u32, operands: &[u32]) -> Option<()> {
+        let operand = |i: usize| operands.get(i).copied();
+
+        match opcode {
+            op::NAME => {
+                self.names.insert(operand(0)?, literal_string(&operands[1..]));
+            },
+            op::MEMBER_NAME => {
+                self.member_names.insert((operand(0)?, operand(1)?), literal_string(operands.get(2..)?));
+            },
+            op::ENTRY_POINT => {
+                // FIXME: Assumes one entry point per module, as glslang produces.
+                self.stage = match operand(0)? {
+                    0 => Some(wgpu::ShaderStage::VERTEX),
+                    4 => Some(wgpu::ShaderStage::FRAGMENT),
+                    5 => Some(wgpu::ShaderStage::COMPUTE),
+                    _ => None,
+                };
+            },
+            op::EXECUTION_MODE => {
+                if operand(1)? == execution_mode::LOCAL_SIZE {
+                    self.workgroup_size = Some([operand(2)?, operand(3)?, operand(4)?]);
+                }
+            },
+            op::TYPE_BOOL => {
+                self.types.insert(operand(0)?, Type::Scalar { kind: ScalarKind::Bool, width: 32 });
+            },
+            op::TYPE_INT => {
+                let kind = if operand(2)? != 0 { ScalarKind::Sint } else { ScalarKind::Uint };
+                self.types.insert(operand(0)?, Type::Scalar { kind, width: operand(1)? });
+            },
+            op::TYPE_FLOAT => {
+                self.types.insert(operand(0)?, Type::Scalar { kind: ScalarKind::Float, width: operand(1)? });
+            },
+            op::TYPE_VECTOR => {
+                self.types.insert(operand(0)?, Type::Vector { component: operand(1)?, count: operand(2)? });
+            },
+            op::TYPE_MATRIX => {
+                self.types.insert(operand(0)?, Type::Matrix { column: operand(1)?, count: operand(2)? });
+            },
+            op::TYPE_IMAGE => {
+                self.types.insert(operand(0)?, Type::Image {
+                    sampled_type: operand(1)?,
+                    dim: operand(2)?,
+                    arrayed: operand(4)? != 0,
+                    multisampled: operand(5)? != 0,
+                    sampled: operand(6)?,
+                    format: operand(7)?,
+                });
+            },
+            op::TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, Type::Sampler);
+            },
+            op::TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0)?, Type::SampledImage);
+            },
+            op::TYPE_ARRAY => {
+                self.types.insert(operand(0)?, Type::Array { element: operand(1)?, length: operand(2)? });
+            },
+            op::TYPE_RUNTIME_ARRAY => {
+                self.types.insert(operand(0)?, Type::RuntimeArray);
+            },
+            op::TYPE_STRUCT => {
+                self.types.insert(operand(0)?, Type::Struct { members: operands[1..].to_vec() });
+            },
+            op::TYPE_POINTER => {
+                self.types.insert(operand(0)?, Type::Pointer { pointee: operand(2)? });
+            },
+            op::CONSTANT => {
+                // Only 32-bit constants matter, as array lengths.
+                self.constants.insert(operand(1)?, operand(2)?);
+            },
+            op::VARIABLE => {
+                self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+            },
+            op::DECORATE => {
+                let decorations = self.decorations.entry(operand(0)?).or_default();
+                match operand(1)? {
+                    decoration::BLOCK => decorations.block = true,
+                    decoration::BUFFER_BLOCK => decorations.buffer_block = true,
+                    decoration::ARRAY_STRIDE => decorations.array_stride = Some(operand(2)?),
+                    decoration::BUILT_IN => decorations.built_in = true,
+                    decoration::NON_WRITABLE => decorations.non_writable = true,
+                    decoration::NON_READABLE => decorations.non_readable = true,
+                    decoration::LOCATION => decorations.location = Some(operand(2)?),
+                    decoration::BINDING => decorations.binding = Some(operand(2)?),
+                    decoration::DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+                    _ => (),
+                }
+            },
+            op::MEMBER_DECORATE => {
+                let decorations = self.member_decorations.entry((operand(0)?, operand(1)?)).or_default();
+                match operand(2)? {
+                    decoration::OFFSET => decorations.offset = Some(operand(3)?),
+                    decoration::MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+                    decoration::NON_WRITABLE => decorations.non_writable = true,
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+
+        Some(())
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type, InterfaceError> {
+        self.types.get(&id).ok_or(InterfaceError::Malformed("reference to an undeclared type"))
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    /// How many bytes a value of type `id` takes up in a buffer.
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> Result<u64, InterfaceError> {
+        Ok(match self.ty(id)? {
+            Type::Scalar { width, .. } => *width as u64 / 8,
+            Type::Vector { component, count } => *count as u64 * self.size(*component, None)?,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => *count as u64 * stride as u64,
+                None => *count as u64 * self.size(*column, None)?,
+            },
+            Type::Array { element, length } => {
+                let length = *self.constants.get(length)
+                    .ok_or(InterfaceError::Malformed("array length isn't a constant"))? as u64;
+                let stride = match self.decorations(id).and_then(|d| d.array_stride) {
+                    Some(stride) => stride as u64,
+                    None => self.size(*element, matrix_stride)?,
+                };
+                length * stride
+            },
+            Type::RuntimeArray => 0,
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (i, &member) in members.iter().enumerate() {
+                    let decorations = self.member_decorations.get(&(id, i as u32));
+                    let offset = decorations.and_then(|d| d.offset).unwrap_or(0) as u64;
+                    let stride = decorations.and_then(|d| d.matrix_stride);
+                    size = size.max(offset + self.size(member, stride)?);
+                }
+                size
+            },
+            _ => return Err(InterfaceError::Malformed("opaque type inside a buffer")),
+        })
+    }
+
+    fn scalar_kind(&self, id: u32) -> Option<ScalarKind> {
+        match self.types.get(&id)? {
+            Type::Scalar { kind, .. } => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// The members of a buffer's block, where they're at in the buffer.
+    fn block_members(&self, id: u32) -> Result<Vec<BlockMember>, InterfaceError> {
+        let members = match self.ty(id)? {
+            Type::Struct { members } => members.len() as u32,
+            _ => return Ok(Vec::new()),
+        };
+
+        Ok((0 .. members)
+            .map(|i| BlockMember {
+                name: self.member_names.get(&(id, i)).filter(|name| !name.is_empty()).cloned(),
+                offset: self.member_decorations.get(&(id, i)).and_then(|d| d.offset).unwrap_or(0) as u64,
+            })
+            .collect())
+    }
+
+    /// A name to show in errors: the variable's own, failing that its type's.
+    fn variable_name(&self, id: u32, pointee: u32) -> String {
+        [id, pointee].iter()
+            .filter_map(|id| self.names.get(id))
+            .find(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("<unnamed {}>", id))
+    }
+
+    fn binding_kind(&self, name: &str, var: u32, pointee: u32, class: u32) -> Result<BindingKind, InterfaceError> {
+        let unsupported = |reason| InterfaceError::Unsupported { name: name.to_owned(), reason };
+        let var_decorations = self.decorations(var);
+        let type_decorations = self.decorations(pointee);
+
+        let is_block = type_decorations.map_or(false, |d| d.block);
+        let is_buffer_block = type_decorations.map_or(false, |d| d.buffer_block);
+
+        match (class, self.ty(pointee)?) {
+            (storage_class::UNIFORM, Type::Struct { .. }) if is_block =>
+                Ok(BindingKind::UniformBuffer { size: self.size(pointee, None)? }),
+
+            (storage_class::UNIFORM, Type::Struct { members }) |
+            (storage_class::STORAGE_BUFFER, Type::Struct { members }) if is_block || is_buffer_block => {
+                let readonly = var_decorations.map_or(false, |d| d.non_writable)
+                    || (0 .. members.len() as u32).all(|i| {
+                        self.member_decorations.get(&(pointee, i)).map_or(false, |d| d.non_writable)
+                    });
+                Ok(BindingKind::StorageBuffer { size: self.size(pointee, None)?, readonly })
+            },
+
+            (storage_class::UNIFORM_CONSTANT, Type::Image { sampled_type, dim, arrayed, multisampled, sampled, format }) => {
+                let dimension = match (*dim, *arrayed) {
+                    (0, false) => wgpu::TextureViewDimension::D1,
+                    (1, false) => wgpu::TextureViewDimension::D2,
+                    (1, true) => wgpu::TextureViewDimension::D2Array,
+                    (2, false) => wgpu::TextureViewDimension::D3,
+                    (3, false) => wgpu::TextureViewDimension::Cube,
+                    (3, true) => wgpu::TextureViewDimension::CubeArray,
+                    _ => return Err(unsupported("only 1D, 2D, 3D and cube textures (and arrays of 2D and cube textures) can be bound")),
+                };
+
+                if *sampled == 2 {
+                    let format = storage_format(*format)
+                        .ok_or_else(|| unsupported("storage textures need a format qualifier wgpu supports"))?;
+                    let readonly = var_decorations.map_or(false, |d| d.non_writable);
+                    if var_decorations.map_or(false, |d| d.non_readable) && readonly {
+                        return Err(unsupported("a storage texture can't be both readonly and writeonly"));
+                    }
+                    return Ok(BindingKind::StorageTexture { dimension, format, readonly });
+                }
+
+                let component_type = match self.scalar_kind(*sampled_type) {
+                    Some(ScalarKind::Float) => wgpu::TextureComponentType::Float,
+                    Some(ScalarKind::Sint) => wgpu::TextureComponentType::Sint,
+                    Some(ScalarKind::Uint) => wgpu::TextureComponentType::Uint,
+                    _ => return Err(InterfaceError::Malformed("texture has no component type")),
+                };
+
+                Ok(BindingKind::SampledTexture { dimension, component_type, multisampled: *multisampled })
+            },
+
+            (storage_class::UNIFORM_CONSTANT, Type::Sampler) => Ok(BindingKind::Sampler),
+
+            (_, Type::SampledImage) =>
+                Err(unsupported("combined image samplers; declare a separate texture and sampler")),
+            (_, Type::Array { .. }) | (_, Type::RuntimeArray) =>
+                Err(unsupported("arrays of bindings")),
+            _ =>
+                Err(unsupported("unknown kind of binding")),
+        }
+    }
+
+    fn vertex_format(&self, name: &str, id: u32) -> Result<wgpu::VertexFormat, InterfaceError> {
+        let (kind, count) = match self.ty(id)? {
+            Type::Scalar { .. } => (self.scalar_kind(id), 1),
+            Type::Vector { component, count } => (self.scalar_kind(*component), *count),
+            _ => (None, 0),
+        };
+
+        use wgpu::VertexFormat::*;
+        Ok(match (kind, count) {
+            (Some(ScalarKind::Float), 1) => Float,
+            (Some(ScalarKind::Float), 2) => Float2,
+            (Some(ScalarKind::Float), 3) => Float3,
+            (Some(ScalarKind::Float), 4) => Float4,
+            (Some(ScalarKind::Sint), 1) => Int,
+            (Some(ScalarKind::Sint), 2) => Int2,
+            (Some(ScalarKind::Sint), 3) => Int3,
+            (Some(ScalarKind::Sint), 4) => Int4,
+            (Some(ScalarKind::Uint), 1) => Uint,
+            (Some(ScalarKind::Uint), 2) => Uint2,
+            (Some(ScalarKind::Uint), 3) => Uint3,
+            (Some(ScalarKind::Uint), 4) => Uint4,
+            _ => return Err(InterfaceError::Unsupported {
+                name: name.to_owned(),
+                reason: "vertex inputs must be scalars or vectors of 32-bit numbers",
+            }),
+        })
+    }
+}
+
+
+/// The wgpu format for a SPIR-V image format, among those wgpu can bind as storage.
+fn storage_format(format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+    Some(match format {
+        1 => Rgba32Float,
+        2 => Rgba16Float,
+        3 => R32Float,
+        4 => Rgba8Unorm,
+        5 => Rgba8Snorm,
+        6 => Rg32Float,
+        21 => Rgba32Sint,
+        22 => Rgba16Sint,
+        23 => Rgba8Sint,
+        24 => R32Sint,
+        25 => Rg32Sint,
+        30 => Rgba32Uint,
+        31 => Rgba16Uint,
+        32 => Rgba8Uint,
+        33 => R32Uint,
+        35 => Rg32Uint,
+        _ => return None,
+    })
+}
+
+
+/// Reads the interface of a compiled shader.
+pub fn reflect(spirv: &[u32]) -> Result<ShaderInterface, InterfaceError> {
+    let module = Module::parse(spirv)?;
+
+    let stage = module.stage.ok_or(InterfaceError::Malformed("no vertex, fragment or compute entry point"))?;
+
+    let mut bindings = Vec::new();
+    let mut vertex_inputs = Vec::new();
+
+    for &(var, pointer, class) in &module.variables {
+        let pointee = match module.ty(pointer)? {
+            Type::Pointer { pointee } => *pointee,
+            _ => return Err(InterfaceError::Malformed("variable isn't a pointer")),
+        };
+        let decorations = module.decorations(var);
+
+        match class {
+            storage_class::UNIFORM_CONSTANT | storage_class::UNIFORM | storage_class::STORAGE_BUFFER => {
+                let name = module.variable_name(var, pointee);
+                let (set, binding) = match decorations.map(|d| (d.set, d.binding)) {
+                    Some((Some(set), Some(binding))) => (set, binding),
+                    _ => return Err(InterfaceError::Unsupported {
+                        name,
+                        reason: "bindings need an explicit `layout(set = ..., binding = ...)`",
+                    }),
+                };
+                let kind = module.binding_kind(&name, var, pointee, class)?;
+                let members = match kind {
+                    BindingKind::UniformBuffer { .. } | BindingKind::StorageBuffer { .. } => module.block_members(pointee)?,
+                    _ => Vec::new(),
+                };
+                bindings.push(ReflectedBinding { set, binding, name, kind, members });
+            },
+
+            storage_class::PUSH_CONSTANT => return Err(InterfaceError::Unsupported {
+                name: module.variable_name(var, pointee),
+                reason: "push constants",
+            }),
+
+            storage_class::INPUT if stage == wgpu::ShaderStage::VERTEX => {
+                // e.g. `gl_VertexIndex`
+                if decorations.map_or(false, |d| d.built_in) {
+                    continue;
+                }
+
+                let name = module.variable_name(var, pointee);
+                let location = decorations.and_then(|d| d.location).ok_or_else(|| InterfaceError::Unsupported {
+                    name: name.clone(),
+                    reason: "vertex inputs need an explicit `layout(location = ...)`",
+                })?;
+                let format = module.vertex_format(&name, pointee)?;
+                vertex_inputs.push(VertexInput { location, name, format });
+            },
+
+            _ => (),
+        }
+    }
+
+    bindings.sort_by_key(|b| (b.set, b.binding));
+    vertex_inputs.sort_by_key(|input| input.location);
+
+    let workgroup_size = module.workgroup_size.unwrap_or([1, 1, 1]);
+
+    Ok(ShaderInterface { stage, bindings, vertex_inputs, workgroup_size })
+}
+
+
+/// A resource to bind, along with what's needed to check it against the shaders.
+pub enum BoundResource<'a> {
+    /// `fields`, if known, are checked against the members of the shaders' block.
+    Buffer { buffer: &'a wgpu::Buffer, size: u64, fields: Option<&'a [layout::Field]> },
+    TextureView(&'a wgpu::TextureView),
+    /// Views don't say what they're of, so storage textures bring that along to check.
+    StorageTexture {
+        view: &'a wgpu::TextureView,
+        dimension: wgpu::TextureViewDimension,
+        format: wgpu::TextureFormat,
+    },
+    Sampler(&'a wgpu::Sampler),
+}
+
+impl<'a> BoundResource<'a> {
+    fn resource(&self) -> wgpu::BindingResource<'a> {
+        match *self {
+            BoundResource::Buffer { buffer, .. } => wgpu::BindingResource::Buffer(buffer.slice(..)),
+            BoundResource::TextureView(view) => wgpu::BindingResource::TextureView(view),
+            BoundResource::StorageTexture { view, .. } => wgpu::BindingResource::TextureView(view),
+            BoundResource::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+        }
+    }
+}
+
+
+/// A pipeline layout built from the interfaces of its shaders, which
+/// checks the resources bound to it against what the shaders expect.
+pub struct PipelineLayout {
+    pub bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    pub layout: wgpu::PipelineLayout,
+    sets: Vec<BTreeMap<u32, (ReflectedBinding, wgpu::ShaderStage)>>,
+    vertex_inputs: Vec<VertexInput>,
+}
+
+impl PipelineLayout {
+    /// `set_count` is how many bind groups the pass binds, which may be
+    /// more than its shaders use (say, when they're the fallback shaders).
+    pub fn new(device: &wgpu::Device, label: &str, stages: &[&ShaderInterface], set_count: u32)
+        -> Result<Self, InterfaceError>
+    {
+        let mut sets = (0 .. set_count).map(|_| BTreeMap::new()).collect::<Vec<_>>();
+        let mut vertex_inputs = Vec::new();
+
+        for stage in stages {
+            for binding in &stage.bindings {
+                let set = sets.get_mut(binding.set as usize).ok_or_else(|| InterfaceError::SetOutOfRange {
+                    set: binding.set,
+                    name: binding.name.clone(),
+                })?;
+
+                let (existing, visibility) = set.entry(binding.binding)
+                    .or_insert_with(|| (binding.clone(), wgpu::ShaderStage::NONE));
+
+                existing.kind = merge(&existing.kind, &binding.kind).ok_or_else(|| InterfaceError::Conflict {
+                    set: binding.set,
+                    binding: binding.binding,
+                    name: binding.name.clone(),
+                })?;
+                *visibility |= stage.stage;
+
+                // As with sizes, a stage may declare fewer members.
+                if binding.members.len() > existing.members.len() {
+                    existing.members = binding.members.clone();
+                }
+            }
+
+            vertex_inputs.extend(stage.vertex_inputs.iter().cloned());
+        }
+
+        let bind_group_layouts = sets.iter()
+            .map(|set| {
+                let entries = set.values()
+                    .map(|(binding, visibility)| {
+                        wgpu::BindGroupLayoutEntry::new(binding.binding, *visibility, binding.kind.binding_type())
+                    })
+                    .collect::<Vec<_>>();
+
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(label),
+                    bindings: &entries,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+        });
+
+        Ok(Self { bind_group_layouts, layout, sets, vertex_inputs })
+    }
+
+    /// Binds `resources` (by binding number) as bind group `set`.
+    ///
+    /// Resources the shaders don't use are left out, but every one they
+    /// do use must be there, and be of the right kind.
+    pub fn bind_group(&self, device: &wgpu::Device, set: u32, label: &str, resources: &[(u32, BoundResource)])
+        -> Result<wgpu::BindGroup, InterfaceError>
+    {
+        let (layout, reflected) = match (self.bind_group_layouts.get(set as usize), self.sets.get(set as usize)) {
+            (Some(layout), Some(reflected)) => (layout, reflected),
+            _ => return Err(InterfaceError::SetOutOfRange { set, name: label.to_owned() }),
+        };
+
+        let mut bindings = Vec::new();
+
+        for (binding, _) in reflected.values() {
+            let resource = resources.iter()
+                .find(|(number, _)| *number == binding.binding)
+                .map(|(_, resource)| resource)
+                .ok_or_else(|| InterfaceError::MissingResource {
+                    set,
+                    binding: binding.binding,
+                    name: binding.name.clone(),
+                })?;
+
+            let wrong_resource = || InterfaceError::WrongResource {
+                set,
+                binding: binding.binding,
+                name: binding.name.clone(),
+                expected: binding.kind.describe(),
+            };
+
+            match (&binding.kind, resource) {
+                (BindingKind::UniformBuffer { size: needed }, BoundResource::Buffer { size, fields }) |
+                (BindingKind::StorageBuffer { size: needed, .. }, BoundResource::Buffer { size, fields }) => {
+                    if size < needed {
+                        return Err(InterfaceError::BufferTooSmall {
+                            set,
+                            binding: binding.binding,
+                            name: binding.name.clone(),
+                            needed: *needed,
+                            provided: *size,
+                        });
+                    }
+                    if let Some(fields) = fields {
+                        check_fields(set, binding, fields)?;
+                    }
+                },
+                (BindingKind::StorageTexture { dimension, format, .. }, BoundResource::StorageTexture { dimension: bound_dimension, format: bound_format, .. }) => {
+                    if (dimension, format) != (bound_dimension, bound_format) {
+                        return Err(InterfaceError::WrongStorageTexture {
+                            set,
+                            binding: binding.binding,
+                            name: binding.name.clone(),
+                            expected: (*dimension, *format),
+                            provided: (*bound_dimension, *bound_format),
+                        });
+                    }
+                },
+                (BindingKind::SampledTexture { .. }, BoundResource::TextureView(_)) |
+                (BindingKind::SampledTexture { .. }, BoundResource::StorageTexture { .. }) |
+                (BindingKind::Sampler, BoundResource::Sampler(_)) => (),
+                _ => return Err(wrong_resource()),
+            }
+
+            bindings.push(wgpu::Binding {
+                binding: binding.binding,
+                resource: resource.resource(),
+            });
+        }
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            bindings: &bindings,
+        }))
+    }
+
+    /// Checks that `buffers` provide every input the vertex shader reads,
+    /// in a format it can read them as.
+    pub fn check_vertex_buffers(&self, buffers: &[wgpu::VertexBufferDescriptor]) -> Result<(), InterfaceError> {
+        for input in &self.vertex_inputs {
+            let provided = buffers.iter()
+                .flat_map(|buffer| buffer.attributes.iter())
+                .find(|attribute| attribute.shader_location == input.location)
+                .ok_or_else(|| InterfaceError::MissingVertexInput {
+                    location: input.location,
+                    name: input.name.clone(),
+                })?;
+
+            // Missing components are filled in, so only the kind of number has to agree.
+            if vertex_scalar_kind(provided.format) != vertex_scalar_kind(input.format) {
+                return Err(InterfaceError::WrongVertexFormat {
+                    location: input.location,
+                    name: input.name.clone(),
+                    expected: input.format,
+                    provided: provided.format,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Checks that each of the block's members is the field in the same place,
+/// by name where both have one, and is where that field is.
+fn check_fields(set: u32, binding: &ReflectedBinding, fields: &[layout::Field]) -> Result<(), InterfaceError> {
+    for (i, member) in binding.members.iter().enumerate() {
+        let name = || match &member.name {
+            Some(member) => format!("{}.{}", binding.name, member),
+            None => format!("{}.<member {}>", binding.name, i),
+        };
+        let field = fields.get(i);
+
+        if let (Some(member), Some(provided)) = (&member.name, field.and_then(|field| field.name)) {
+            if member != provided {
+                return Err(InterfaceError::WrongField { set, binding: binding.binding, name: name(), provided });
+            }
+        }
+
+        if field.map(|field| field.offset) != Some(member.offset) {
+            return Err(InterfaceError::WrongOffset {
+                set,
+                binding: binding.binding,
+                name: name(),
+                expected: member.offset,
+                provided: field.map(|field| field.offset),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The one binding two stages' declarations amount to, if they agree.
+fn merge(a: &BindingKind, b: &BindingKind) -> Option<BindingKind> {
+    // A stage may declare fewer members of the same block, so only the larger size matters.
+    match (a, b) {
+        (BindingKind::UniformBuffer { size: a }, BindingKind::UniformBuffer { size: b }) =>
+            Some(BindingKind::UniformBuffer { size: *a.max(b) }),
+        (BindingKind::StorageBuffer { size: a, readonly: ra }, BindingKind::StorageBuffer { size: b, readonly: rb }) =>
+            Some(BindingKind::StorageBuffer { size: *a.max(b), readonly: *ra && *rb }),
+        (a, b) if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+/// What a vertex attribute's components read as in the shader.
+fn vertex_scalar_kind(format: wgpu::VertexFormat) -> ScalarKind {
+    use wgpu::VertexFormat::*;
+    match format {
+        Uchar2 | Uchar4 | Ushort2 | Ushort4 | Uint | Uint2 | Uint3 | Uint4 => ScalarKind::Uint,
+        Char2 | Char4 | Short2 | Short4 | Int | Int2 | Int3 | Int4 => ScalarKind::Sint,
+        _ => ScalarKind::Float,
+    }
+}
+
+
+/// Hand-assembles SPIR-V, for tests to reflect and run.
+#[cfg(test)]
+pub(crate) mod asm {
+    pub use super::op::*;
+
+    pub mod decoration {
+        pub use super::super::decoration::*;
+    }
+
+    pub mod execution_mode {
+        pub use super::super::execution_mode::*;
+    }
+
+    pub mod storage_class {
+        pub use super::super::storage_class::*;
+        pub const OUTPUT: u32 = 3;
+    }
+
+    pub const CAPABILITY: u32 = 17;
+    pub const MEMORY_MODEL: u32 = 14;
+    pub const TYPE_VOID: u32 = 19;
+    pub const TYPE_FUNCTION: u32 = 33;
+    pub const FUNCTION: u32 = 54;
+    pub const LABEL: u32 = 248;
+    pub const LOAD: u32 = 61;
+    pub const STORE: u32 = 62;
+    pub const ACCESS_CHAIN: u32 = 65;
+    pub const COMPOSITE_EXTRACT: u32 = 81;
+    pub const IMUL: u32 = 132;
+    pub const RETURN: u32 = 253;
+    pub const FUNCTION_END: u32 = 56;
+
+    pub mod model {
+        pub const VERTEX: u32 = 0;
+        pub const FRAGMENT: u32 = 4;
+        pub const GL_COMPUTE: u32 = 5;
+    }
+
+    pub mod built_in {
+        pub const POSITION: u32 = 0;
+        pub const GLOBAL_INVOCATION_ID: u32 = 28;
+    }
+
+    /// A literal string operand.
+    pub fn string(text: &str) -> Vec<u32> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+        bytes.chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    pub struct Assembler {
+        words: Vec<u32>,
+        next_id: u32,
+    }
+
+    impl Assembler {
+        /// A module using the `Shader` capability, as every shader does.
+        pub fn new() -> Self {
+            let mut asm = Self {
+                words: vec![super::SPIRV_MAGIC, 0x0001_0000, 0, 0, 0],
+                next_id: 1,
+            };
+            asm.inst(CAPABILITY, &[1]);
+            asm.inst(MEMORY_MODEL, &[0, 1]); // Logical, GLSL450
+            asm
+        }
+
+        pub fn id(&mut self) -> u32 {
+            self.next_id += 1;
+            self.next_id - 1
+        }
+
+        pub fn inst(&mut self, opcode: u32, operands: &[u32]) {
+            self.words.push(((operands.len() as u32 + 1) << 16) | opcode);
+            self.words.extend_from_slice(operands);
+        }
+
+        /// `OpEntryPoint` for `main`, as `function`.
+        pub fn entry_point(&mut self, model: u32, function: u32, interface: &[u32]) {
+            let operands = [&[model, function][..], &string("main"), interface].concat();
+            self.inst(ENTRY_POINT, &operands);
+        }
+
+        pub fn name(&mut self, id: u32, name: &str) {
+            self.inst(NAME, &[&[id][..], &string(name)].concat());
+        }
+
+        pub fn member_name(&mut self, id: u32, member: u32, name: &str) {
+            self.inst(MEMBER_NAME, &[&[id, member][..], &string(name)].concat());
+        }
+
+        pub fn finish(mut self) -> Vec<u32> {
+            self.words[3] = self.next_id;
+            self.words
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::asm::{self, decoration, storage_class, Assembler};
+    use crate::render::layout::{Block, Field};
+
+    /// A fragment shader declaring
+    /// `layout(set = 0, binding = 0) uniform Camera { mat4 view; vec3 pos; vec3 dir; vec3 top; } camera;`,
+    /// with or without its members' names.
+    fn camera_shader(member_names: bool) -> Vec<u32> {
+        let mut a = Assembler::new();
+        let [main, float, vec3, vec4, mat4] = [a.id(), a.id(), a.id(), a.id(), a.id()];
+        let [camera, camera_ptr, camera_var] = [a.id(), a.id(), a.id()];
+
+        a.entry_point(asm::model::FRAGMENT, main, &[]);
+        a.name(camera, "Camera");
+        a.name(camera_var, "camera");
+        if member_names {
+            for (i, name) in ["view", "pos", "dir", "top"].iter().enumerate() {
+                a.member_name(camera, i as u32, name);
+            }
+        }
+
+        a.inst(asm::DECORATE, &[camera, decoration::BLOCK]);
+        a.inst(asm::MEMBER_DECORATE, &[camera, 0, decoration::OFFSET, 0]);
+        a.inst(asm::MEMBER_DECORATE, &[camera, 0, decoration::MATRIX_STRIDE, 16]);
+        a.inst(asm::MEMBER_DECORATE, &[camera, 1, decoration::OFFSET, 64]);
+        a.inst(asm::MEMBER_DECORATE, &[camera, 2, decoration::OFFSET, 80]);
+        a.inst(asm::MEMBER_DECORATE, &[camera, 3, decoration::OFFSET, 96]);
+        a.inst(asm::DECORATE, &[camera_var, decoration::DESCRIPTOR_SET, 0]);
+        a.inst(asm::DECORATE, &[camera_var, decoration::BINDING, 0]);
+
+        a.inst(asm::TYPE_FLOAT, &[float, 32]);
+        a.inst(asm::TYPE_VECTOR, &[vec3, float, 3]);
+        a.inst(asm::TYPE_VECTOR, &[vec4, float, 4]);
+        a.inst(asm::TYPE_MATRIX, &[mat4, vec4, 4]);
+        a.inst(asm::TYPE_STRUCT, &[camera, mat4, vec3, vec3, vec3]);
+        a.inst(asm::TYPE_POINTER, &[camera_ptr, storage_class::UNIFORM, camera]);
+        a.inst(asm::VARIABLE, &[camera_ptr, camera_var, storage_class::UNIFORM]);
+
+        a.finish()
+    }
+
+    fn camera_binding(member_names: bool) -> ReflectedBinding {
+        let mut interface = reflect(&camera_shader(member_names)).unwrap();
+        assert_eq!(interface.bindings.len(), 1);
+        interface.bindings.remove(0)
+    }
+
+    #[test]
+    fn reflects_blocks() {
+        let interface = reflect(&camera_shader(true)).unwrap();
+        assert_eq!(interface.stage, wgpu::ShaderStage::FRAGMENT);
+        assert!(interface.vertex_inputs.is_empty());
+
+        let binding = &interface.bindings[0];
+        assert_eq!((binding.set, binding.binding), (0, 0));
+        assert_eq!(binding.name, "camera");
+        // The last `vec3` ends 12 bytes in.
+        assert_eq!(binding.kind, BindingKind::UniformBuffer { size: 108 });
+
+        let members = binding.members.iter()
+            .map(|member| (member.name.as_deref(), member.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(members, [(Some("view"), 0), (Some("pos"), 64), (Some("dir"), 80), (Some("top"), 96)]);
+    }
+
+    #[test]
+    fn unnamed_members_have_no_name() {
+        let binding = camera_binding(false);
+        assert!(binding.members.iter().all(|member| member.name.is_none()));
+    }
+
+    #[test]
+    fn merges_declarations() {
+        let uniform = |size| BindingKind::UniformBuffer { size };
+        let storage = |size, readonly| BindingKind::StorageBuffer { size, readonly };
+
+        assert_eq!(merge(&uniform(64), &uniform(108)), Some(uniform(108)));
+        assert_eq!(merge(&storage(16, true), &storage(8, true)), Some(storage(16, true)));
+        assert_eq!(merge(&storage(16, true), &storage(16, false)), Some(storage(16, false)));
+        assert_eq!(merge(&BindingKind::Sampler, &BindingKind::Sampler), Some(BindingKind::Sampler));
+
+        assert_eq!(merge(&uniform(16), &storage(16, true)), None);
+        assert_eq!(merge(&uniform(16), &BindingKind::Sampler), None);
+
+        let texture = |dimension| BindingKind::SampledTexture {
+            dimension,
+            component_type: wgpu::TextureComponentType::Float,
+            multisampled: false,
+        };
+        assert_eq!(merge(&texture(wgpu::TextureViewDimension::D2), &texture(wgpu::TextureViewDimension::D3)), None);
+    }
+
+    #[test]
+    fn checks_fields_by_name() {
+        let fields = crate::render::camera::CameraBlock::fields();
+        check_fields(0, &camera_binding(true), &fields).unwrap();
+
+        // Same offsets, but not the same fields.
+        let mut swapped = fields.clone();
+        swapped[1].name = Some("dir");
+        swapped[2].name = Some("pos");
+        match check_fields(0, &camera_binding(true), &swapped) {
+            Err(InterfaceError::WrongField { name, provided: "dir", .. }) => assert_eq!(name, "camera.pos"),
+            other => panic!("expected a wrong field, got {:?}", other),
+        }
+
+        // Without names, only the offsets can be checked.
+        check_fields(0, &camera_binding(false), &swapped).unwrap();
+        let unnamed = fields.iter().map(|field| Field { name: None, ..*field }).collect::<Vec<_>>();
+        check_fields(0, &camera_binding(true), &unnamed).unwrap();
+    }
+
+    #[test]
+    fn checks_fields_by_offset() {
+        let mut fields = crate::render::camera::CameraBlock::fields();
+        fields[3].offset = 92;
+        match check_fields(0, &camera_binding(false), &fields) {
+            Err(InterfaceError::WrongOffset { name, expected: 96, provided: Some(92), .. }) =>
+                assert_eq!(name, "camera.<member 3>"),
+            other => panic!("expected a wrong offset, got {:?}", other),
+        }
+
+        fields.pop();
+        match check_fields(0, &camera_binding(true), &fields) {
+            Err(InterfaceError::WrongOffset { name, expected: 96, provided: None, .. }) =>
+                assert_eq!(name, "camera.top"),
+            other => panic!("expected a missing field, got {:?}", other),
+        }
+    }
+}
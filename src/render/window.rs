@@ -8,6 +8,8 @@ use winit::{
 
 use imgui_winit_support::*;
 
+use crate::script::{self, ConsoleLog};
+
 
 
 pub struct WindowState {
@@ -16,6 +18,8 @@ pub struct WindowState {
 
     pub imgui: imgui::Context,
     pub lua: rlua::Lua,
+    /// Where lua's `print` and script errors go, from the moment `lua` exists.
+    pub console: ConsoleLog,
 }
 
 
@@ -33,7 +37,11 @@ impl WindowState {
         
         window.set_resizable(true);
 
-        let lua = rlua::Lua::new();
+        let lua = script::sandbox::new_state()
+            .expect("Failed to initialize lua state.");
+        let console = ConsoleLog::new();
+        lua.context(|ctx| script::install_print(ctx, &console))
+            .expect("Failed to install lua print.");
         let mut imgui = imgui::Context::create();
 
         let mut platform = WinitPlatform::init(&mut imgui);
@@ -53,6 +61,7 @@ impl WindowState {
             platform,
             imgui,
             lua,
+            console,
         }
     }
 
@@ -0,0 +1,119 @@
+
+pub mod sandbox;
+pub mod loader;
+pub mod view;
+pub mod events;
+pub mod harness;
+pub mod effects;
+pub mod profiler;
+
+use std::sync::Arc;
+use parking_lot::{Mutex, MutexGuard};
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    /// Echo of what was typed into the console.
+    Input,
+    /// Anything passed to `print`.
+    Output,
+    /// The value of an expression entered into the console.
+    Result,
+    /// A lua error, from the console or from any script running in game.
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConsoleLine {
+    pub kind: LineKind,
+    pub text: String,
+}
+
+
+/// Scrollback shared between the console widget and any lua code which prints.
+///
+/// Cloning this only clones the handle; every clone pushes to the same log.
+#[derive(Clone, Default)]
+pub struct ConsoleLog(Arc<Mutex<Vec<ConsoleLine>>>);
+
+impl rlua::UserData for ConsoleLog {}
+
+impl ConsoleLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, kind: LineKind, text: impl Into<String>) {
+        self.0.lock().push(ConsoleLine {
+            kind,
+            text: text.into(),
+        });
+    }
+
+    pub fn lines(&self) -> MutexGuard<Vec<ConsoleLine>> {
+        self.0.lock()
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().clear();
+    }
+}
+
+
+/// Converts each value with lua's own `tostring`, the same way `print` would.
+pub fn display_values(ctx: rlua::Context, values: rlua::MultiValue) -> rlua::Result<String> {
+    let tostring: rlua::Function = ctx.globals().get("tostring")?;
+    let mut strs = Vec::with_capacity(values.len());
+    for value in values {
+        let s: rlua::String = tostring.call(value)?;
+        strs.push(s.to_str()?.to_owned());
+    }
+    Ok(strs.join("\t"))
+}
+
+
+const CONSOLE_LOG_REGISTRY_KEY: &str = "tbs_console_log";
+
+
+/// Replaces the global `print` so that its output lands in `log` instead of stdout.
+///
+/// `log` also becomes where `report_error` sends errors.
+pub fn install_print(ctx: rlua::Context, log: &ConsoleLog) -> rlua::Result<()> {
+    ctx.set_named_registry_value(CONSOLE_LOG_REGISTRY_KEY, log.clone())?;
+
+    let log = log.clone();
+    let print = ctx.create_function(move |ctx, args: rlua::MultiValue| {
+        log.push(LineKind::Output, display_values(ctx, args)?);
+        Ok(())
+    })?;
+    ctx.globals().set("print", print)
+}
+
+
+/// Describes `error` in full: the chunk name and line it was raised at,
+/// and the lua stack traceback(s) leading up to it.
+pub fn describe_error(error: &rlua::Error) -> String {
+    match error {
+        // WTF: rlua leaves the traceback out when displaying callback errors.
+        rlua::Error::CallbackError { traceback, cause } =>
+            format!("{}\n{}", describe_error(cause), traceback),
+        error => error.to_string(),
+    }
+}
+
+
+/// Shows `message` as an error in the console, if one has been set up with
+/// `install_print`, as well as on stderr.
+pub fn report_error(lua: &rlua::Lua, message: &str) {
+    eprintln!("{}", message);
+
+    let log = lua.context(|ctx| -> rlua::Result<ConsoleLog> {
+        let log: rlua::AnyUserData = ctx.named_registry_value(CONSOLE_LOG_REGISTRY_KEY)?;
+        let log = log.borrow::<ConsoleLog>()?;
+        Ok(log.clone())
+    });
+
+    if let Ok(log) = log {
+        log.push(LineKind::Error, message);
+    }
+}
@@ -0,0 +1,448 @@
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::script::{self, sandbox};
+
+
+/// A decision an effect is waiting on, as passed to `effects.choose`.
+#[derive(Clone, Debug)]
+pub struct ChoiceRequest {
+    pub prompt: String,
+    /// How each option reads to a player.
+    pub options: Vec<String>,
+    pub min: usize,
+    pub max: usize,
+    /// Which seat has to answer, if it isn't whoever's turn it is.
+    pub player: Option<usize>,
+    /// Reactions are answered against their own short timer, and
+    /// pass (choosing nothing) if it runs out.
+    pub reaction: bool,
+}
+
+/// Why an answer to a choice was turned down.
+#[derive(Debug)]
+pub enum ChoiceError {
+    /// No effect is waiting on a choice with that id.
+    NoSuchChoice(u64),
+    WrongCount { min: usize, max: usize, got: usize },
+    OutOfRange(usize),
+    Duplicate(usize),
+}
+
+impl std::fmt::Display for ChoiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChoiceError::NoSuchChoice(id) =>
+                write!(f, "no effect is waiting on choice #{}", id),
+            ChoiceError::WrongCount { min, max, got } if min == max =>
+                write!(f, "choose exactly {}, not {}", min, got),
+            ChoiceError::WrongCount { min, max, got } =>
+                write!(f, "choose between {} and {}, not {}", min, max, got),
+            ChoiceError::OutOfRange(i) =>
+                write!(f, "there is no option {}", i + 1),
+            ChoiceError::Duplicate(i) =>
+                write!(f, "option {} was chosen more than once", i + 1),
+        }
+    }
+}
+
+
+struct Suspended {
+    id: u64,
+    thread: rlua::RegistryKey,
+    // The option values themselves, which the answer is made from.
+    options: rlua::RegistryKey,
+    request: ChoiceRequest,
+}
+
+#[derive(Default)]
+struct EffectState {
+    next_id: u64,
+    // Effects started from lua, which haven't had their first run yet.
+    starting: Vec<rlua::RegistryKey>,
+    suspended: Vec<Suspended>,
+}
+
+
+// Checks the request is well formed before it yields,
+// so that mistakes are reported where they were made.
+const CHOOSE: &str = r#"
+    local yield, isyieldable, type, error = coroutine.yield, coroutine.isyieldable, type, error
+    return function(request)
+        if not isyieldable() then
+            error("effects.choose can only be called from inside an effect", 2)
+        end
+        if type(request) ~= "table" or type(request.options) ~= "table" then
+            error("effects.choose expects a table with a list of options", 2)
+        end
+        if #request.options == 0 then
+            error("effects.choose needs at least one option", 2)
+        end
+        local min = request.min or 1
+        local max = request.max or min
+        if min < 0 or max < min or max > #request.options then
+            error("effects.choose needs 0 <= min <= max <= #options", 2)
+        end
+        if request.reaction and min ~= 0 then
+            error("effects.choose needs min = 0 for a reaction, so that it can pass", 2)
+        end
+        return yield(request)
+    end
+"#;
+
+
+/// Card effects which can pause partway through to wait on a player's decision.
+///
+/// An effect is an ordinary function, run as a coroutine. Calling
+/// `effects.choose` suspends it until an answer arrives through `answer`,
+/// which resumes it with the list of chosen options:
+///
+/// ```lua
+/// effects.start(function()
+///     local picked = effects.choose {
+///         prompt = "Discard two cards",
+///         options = hand,
+///         min = 2, max = 2,
+///     }
+///     for _, card in ipairs(picked) do discard(card) end
+/// end)
+/// ```
+///
+/// An option which is a table with a `label` field is shown by that label.
+/// A request may also name the `player` (numbered from 1) who answers it,
+/// and set `reaction = true` to answer against a reaction timer.
+#[derive(Clone)]
+pub struct Effects {
+    state: Arc<Mutex<EffectState>>,
+    /// How many players choices can be asked of.
+    seats: usize,
+}
+
+
+impl Effects {
+
+    /// Installs the global `effects` table, for a game of `seats` players.
+    pub fn install(lua: &rlua::Lua, seats: usize) -> rlua::Result<Self> {
+        let effects = Self {
+            state: Arc::new(Mutex::new(EffectState::default())),
+            seats,
+        };
+
+        lua.context(|ctx| -> rlua::Result<()> {
+            let table = ctx.create_table()?;
+
+            let state = effects.state.clone();
+            table.set("start", ctx.create_function(move |ctx, f: rlua::Function| {
+                let thread = sandbox::create_thread(ctx, f)?;
+                state.lock().starting.push(ctx.create_registry_value(thread)?);
+                Ok(())
+            })?)?;
+
+            let choose: rlua::Function = ctx.load(CHOOSE)
+                .set_name("=effects")?
+                .eval()?;
+            table.set("choose", choose)?;
+
+            ctx.globals().set("effects", table)
+        })?;
+
+        Ok(effects)
+    }
+
+    /// Gives any effects started since the last call their first run.
+    pub fn update(&self, lua: &rlua::Lua) {
+        let starting = std::mem::take(&mut self.state.lock().starting);
+        for thread in starting {
+            self.resume(lua, thread, None);
+        }
+    }
+
+    /// Every choice effects are currently waiting on, oldest first.
+    pub fn pending(&self) -> Vec<(u64, ChoiceRequest)> {
+        self.state.lock().suspended.iter()
+            .map(|suspended| (suspended.id, suspended.request.clone()))
+            .collect()
+    }
+
+    /// Answers choice `id` with the (zero-based) indices of the options
+    /// picked, and resumes the effect waiting on it.
+    ///
+    /// A rejected answer leaves the effect waiting for a better one.
+    pub fn answer(&self, lua: &rlua::Lua, id: u64, picks: &[usize]) -> Result<(), ChoiceError> {
+        let suspended = self.take(id, picks)?;
+        self.resume(lua, suspended.thread, Some((suspended.options, picks)));
+        Ok(())
+    }
+
+    /// Starts `f` as an effect and runs it straight away, rather than at the
+    /// next `update`, for callers already inside lua. Returns the id of the
+    /// choice it's waiting on, if any. If the effect fails, the error is
+    /// returned rather than reported.
+    pub fn start_in<'lua>(&self, ctx: rlua::Context<'lua>, f: rlua::Function<'lua>) -> rlua::Result<Option<u64>> {
+        let thread = ctx.create_registry_value(sandbox::create_thread(ctx, f)?)?;
+        sandbox::limit_instructions(ctx, sandbox::Limits::default(), |ctx| self.step(ctx, thread, None))
+    }
+
+    /// `answer`, for callers already inside lua. Returns the id of the next
+    /// choice the effect is waiting on, if any. A rejected answer, or the
+    /// effect failing, is returned as an error.
+    pub fn answer_in(&self, ctx: rlua::Context, id: u64, picks: &[usize]) -> rlua::Result<Option<u64>> {
+        let suspended = self.take(id, picks)
+            .map_err(|e| rlua::Error::RuntimeError(e.to_string()))?;
+        sandbox::limit_instructions(ctx, sandbox::Limits::default(), |ctx| {
+            self.step(ctx, suspended.thread, Some((suspended.options, picks)))
+        })
+    }
+
+    /// Stops waiting on choice `id`, if `picks` would answer it.
+    fn take(&self, id: u64, picks: &[usize]) -> Result<Suspended, ChoiceError> {
+        let mut state = self.state.lock();
+        let at = state.suspended.iter()
+            .position(|suspended| suspended.id == id)
+            .ok_or(ChoiceError::NoSuchChoice(id))?;
+
+        validate(&state.suspended[at].request, picks)?;
+        Ok(state.suspended.remove(at))
+    }
+
+    fn resume(&self, lua: &rlua::Lua, thread: rlua::RegistryKey, answer: Option<(rlua::RegistryKey, &[usize])>) {
+        let result = sandbox::run_limited(lua, sandbox::Limits::default(), |ctx| self.step(ctx, thread, answer));
+
+        if let Err(e) = result {
+            script::report_error(lua, &format!("effect failed: {}", script::describe_error(&e)));
+        }
+    }
+
+    /// Resumes `thread`, with the options picked from `answer` if it's
+    /// waiting on a choice, until it finishes or asks for another. Returns
+    /// the new choice's id; `thread` is dropped if it finished or failed.
+    fn step<'lua>(
+        &self,
+        ctx: rlua::Context<'lua>,
+        thread: rlua::RegistryKey,
+        answer: Option<(rlua::RegistryKey, &[usize])>,
+    ) -> rlua::Result<Option<u64>> {
+        match advance(ctx, &thread, answer, self.seats) {
+            Ok(Some((request, options))) => {
+                let mut state = self.state.lock();
+                let id = state.next_id;
+                state.next_id += 1;
+                state.suspended.push(Suspended { id, thread, options, request });
+                Ok(Some(id))
+            },
+            Ok(None) => {
+                ctx.remove_registry_value(thread)?;
+                Ok(None)
+            },
+            Err(e) => {
+                let _ = ctx.remove_registry_value(thread);
+                Err(e)
+            },
+        }
+    }
+}
+
+
+/// Resumes `thread` once, returning the choice it yielded if it's still going.
+fn advance<'lua>(
+    ctx: rlua::Context<'lua>,
+    thread: &rlua::RegistryKey,
+    answer: Option<(rlua::RegistryKey, &[usize])>,
+    seats: usize,
+) -> rlua::Result<Option<(ChoiceRequest, rlua::RegistryKey)>> {
+    let coroutine: rlua::Thread = ctx.registry_value(thread)?;
+
+    let args = match answer {
+        None => rlua::Nil,
+        Some((options_key, picks)) => {
+            let options: rlua::Table = ctx.registry_value(&options_key)?;
+            ctx.remove_registry_value(options_key)?;
+            let chosen = ctx.create_table()?;
+            for (n, &i) in picks.iter().enumerate() {
+                chosen.set(n + 1, options.get::<_, rlua::Value>(i + 1)?)?;
+            }
+            rlua::Value::Table(chosen)
+        },
+    };
+
+    let yielded: rlua::Value = coroutine.resume(args)?;
+
+    match coroutine.status() {
+        rlua::ThreadStatus::Resumable => parse_request(ctx, yielded, seats).map(Some),
+        _ => Ok(None),
+    }
+}
+
+
+fn validate(request: &ChoiceRequest, picks: &[usize]) -> Result<(), ChoiceError> {
+    if picks.len() < request.min || picks.len() > request.max {
+        return Err(ChoiceError::WrongCount {
+            min: request.min,
+            max: request.max,
+            got: picks.len(),
+        });
+    }
+
+    for (n, &i) in picks.iter().enumerate() {
+        if i >= request.options.len() {
+            return Err(ChoiceError::OutOfRange(i));
+        }
+        if picks[..n].contains(&i) {
+            return Err(ChoiceError::Duplicate(i));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Whether some answer to `request` would be accepted, and the effect could go on.
+fn check_answerable(request: &ChoiceRequest) -> Result<(), String> {
+    if request.options.is_empty() {
+        return Err("there are no options to choose from".to_owned());
+    }
+    if request.min > request.max {
+        return Err(format!("min ({}) is more than max ({})", request.min, request.max));
+    }
+    if request.max > request.options.len() {
+        return Err(format!("max ({}) is more than the {} options", request.max, request.options.len()));
+    }
+    if request.reaction && request.min != 0 {
+        return Err("a reaction needs min = 0, so that it can pass".to_owned());
+    }
+    Ok(())
+}
+
+
+fn parse_request<'lua>(ctx: rlua::Context<'lua>, yielded: rlua::Value<'lua>, seats: usize)
+    -> rlua::Result<(ChoiceRequest, rlua::RegistryKey)>
+{
+    let request = match yielded {
+        rlua::Value::Table(request) => request,
+        _ => return Err(rlua::Error::RuntimeError(
+            "effects may only yield through effects.choose".to_owned()
+        )),
+    };
+
+    let options: rlua::Table = request.get("options")?;
+    let min = request.get::<_, Option<usize>>("min")?.unwrap_or(1);
+    let max = request.get::<_, Option<usize>>("max")?.unwrap_or(min);
+
+    let mut labels = Vec::new();
+    for option in options.clone().sequence_values::<rlua::Value>() {
+        let label = match option? {
+            rlua::Value::Table(option) => match option.get::<_, Option<String>>("label")? {
+                Some(label) => label,
+                None => script::display_values(ctx, rlua::MultiValue::from_vec(vec![rlua::Value::Table(option)]))?,
+            },
+            option => script::display_values(ctx, rlua::MultiValue::from_vec(vec![option]))?,
+        };
+        labels.push(label);
+    }
+
+    let player = match request.get::<_, Option<usize>>("player")? {
+        Some(player) if player < 1 || player > seats => return Err(rlua::Error::RuntimeError(format!(
+            "effects.choose: there's no player {}; players are numbered from 1 to {}", player, seats
+        ))),
+        player => player.map(|player| player - 1),
+    };
+
+    let request = ChoiceRequest {
+        prompt: request.get::<_, Option<String>>("prompt")?.unwrap_or_else(|| "Choose".to_owned()),
+        options: labels,
+        min,
+        max,
+        player,
+        reaction: request.get::<_, Option<bool>>("reaction")?.unwrap_or(false),
+    };
+
+    // `effects.choose` checks all this too, but an effect which yields
+    // by hand would otherwise leave a choice nobody can answer.
+    check_answerable(&request).map_err(|e| rlua::Error::RuntimeError(format!("effects.choose: {}", e)))?;
+
+    Ok((request, ctx.create_registry_value(options)?))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(options: usize, min: usize, max: usize) -> ChoiceRequest {
+        ChoiceRequest {
+            prompt: "Choose".to_owned(),
+            options: (0 .. options).map(|i| i.to_string()).collect(),
+            min,
+            max,
+            player: None,
+            reaction: false,
+        }
+    }
+
+    #[test]
+    fn unanswerable_requests_are_refused() {
+        assert!(check_answerable(&request(2, 1, 2)).is_ok());
+        assert!(check_answerable(&request(2, 0, 0)).is_ok());
+
+        assert!(check_answerable(&request(0, 0, 0)).is_err(), "no options");
+        assert!(check_answerable(&request(3, 2, 1)).is_err(), "min > max");
+        assert!(check_answerable(&request(1, 2, 2)).is_err(), "min > options");
+        assert!(check_answerable(&request(1, 1, 2)).is_err(), "max > options");
+        assert!(check_answerable(&ChoiceRequest { reaction: true, ..request(2, 1, 1) }).is_err(), "reaction without a pass");
+    }
+
+    #[test]
+    fn answers_are_validated() {
+        let request = request(3, 1, 2);
+        assert!(validate(&request, &[0]).is_ok());
+        assert!(validate(&request, &[2, 0]).is_ok());
+        assert!(matches!(validate(&request, &[]), Err(ChoiceError::WrongCount { .. })));
+        assert!(matches!(validate(&request, &[0, 1, 2]), Err(ChoiceError::WrongCount { .. })));
+        assert!(matches!(validate(&request, &[3]), Err(ChoiceError::OutOfRange(3))));
+        assert!(matches!(validate(&request, &[1, 1]), Err(ChoiceError::Duplicate(1))));
+    }
+
+    #[test]
+    fn hand_rolled_yields_are_checked() {
+        let lua = sandbox::new_state().unwrap();
+        let effects = Effects::install(&lua, 2).unwrap();
+
+        lua.context(|ctx| {
+            ctx.load(r#"
+                effects.start(function()
+                    coroutine.yield { options = {}, min = 1 }
+                end)
+            "#).exec()
+        }).unwrap();
+        effects.update(&lua);
+
+        assert!(effects.pending().is_empty());
+    }
+
+    #[test]
+    fn choices_need_a_player_and_an_option() {
+        let lua = sandbox::new_state().unwrap();
+        let effects = Effects::install(&lua, 2).unwrap();
+
+        lua.context(|ctx| {
+            ctx.load(r#"
+                for _, player in ipairs { 1, 2, 3 } do
+                    effects.start(function()
+                        effects.choose { options = { "a" }, player = player }
+                    end)
+                end
+                effects.start(function()
+                    effects.choose { options = {}, min = 0, max = 0 }
+                end)
+            "#).exec()
+        }).unwrap();
+        effects.update(&lua);
+
+        let players = effects.pending().into_iter()
+            .map(|(_, request)| request.player)
+            .collect::<Vec<_>>();
+        assert_eq!(players, [Some(0), Some(1)]);
+    }
+}
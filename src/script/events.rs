@@ -0,0 +1,281 @@
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::script::{self, sandbox};
+
+
+struct Subscription {
+    id: u64,
+    event: String,
+    priority: i64,
+    filter: Option<rlua::RegistryKey>,
+    handler: rlua::RegistryKey,
+}
+
+#[derive(Default)]
+struct Subscriptions {
+    next_id: u64,
+    // Kept sorted by descending priority, then by order of subscription.
+    list: Vec<Subscription>,
+}
+
+
+/// A handler which failed while an event was being dispatched.
+#[derive(Debug)]
+pub struct HandlerError {
+    pub subscription: u64,
+    pub event: String,
+    pub error: rlua::Error,
+}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f, "handler #{} for '{}' failed: {}",
+            self.subscription, self.event, script::describe_error(&self.error),
+        )
+    }
+}
+
+
+/// Named game events which lua scripts can subscribe to.
+///
+/// ```lua
+/// local id = events.subscribe("turn_start", function(e)
+///     print("turn " .. e.turn .. " for player " .. e.player)
+/// end, { priority = 10, filter = { player = 1 } })
+///
+/// events.unsubscribe(id)
+/// ```
+///
+/// Handlers run highest `priority` first (default 0), ties in the order
+/// they subscribed. A `filter` is either a table of fields the event must
+/// have exactly, or a function of the event returning whether to run.
+///
+/// The game emits `turn_start` (`turn`, `player`), `phase_change` (`phase`,
+/// `player`), `timeout` (`player`, `kind`), `card_played` (`player`, `card`)
+/// and `unit_died` (`player`, `card`).
+#[derive(Clone)]
+pub struct EventBus {
+    subs: Arc<Mutex<Subscriptions>>,
+}
+
+
+impl EventBus {
+
+    /// Installs the global `events` table.
+    pub fn install(lua: &rlua::Lua) -> rlua::Result<Self> {
+        let bus = Self {
+            subs: Arc::new(Mutex::new(Subscriptions::default())),
+        };
+
+        lua.context(|ctx| -> rlua::Result<()> {
+            let events = ctx.create_table()?;
+
+            let subs = bus.subs.clone();
+            events.set("subscribe", ctx.create_function(
+                move |ctx, (event, handler, options): (String, rlua::Function, Option<rlua::Table>)| {
+                    let (priority, filter) = match options {
+                        None => (0, rlua::Nil),
+                        Some(options) => (
+                            options.get::<_, Option<i64>>("priority")?.unwrap_or(0),
+                            options.get::<_, rlua::Value>("filter")?,
+                        ),
+                    };
+
+                    let filter = match filter {
+                        rlua::Value::Nil => None,
+                        filter @ rlua::Value::Table(_) | filter @ rlua::Value::Function(_) =>
+                            Some(ctx.create_registry_value(filter)?),
+                        _ => return Err(rlua::Error::RuntimeError(
+                            "event filter must be a table or a function".to_owned()
+                        )),
+                    };
+
+                    let handler = ctx.create_registry_value(handler)?;
+
+                    let mut subs = subs.lock();
+                    let id = subs.next_id;
+                    subs.next_id += 1;
+
+                    let at = subs.list.iter()
+                        .position(|sub| sub.priority < priority)
+                        .unwrap_or_else(|| subs.list.len());
+
+                    subs.list.insert(at, Subscription {
+                        id, event, priority, filter, handler,
+                    });
+
+                    Ok(id)
+                }
+            )?)?;
+
+            let subs = bus.subs.clone();
+            events.set("unsubscribe", ctx.create_function(move |ctx, id: u64| {
+                let removed = {
+                    let mut subs = subs.lock();
+                    subs.list.iter()
+                        .position(|sub| sub.id == id)
+                        .map(|at| subs.list.remove(at))
+                };
+
+                match removed {
+                    None => Ok(false),
+                    Some(sub) => {
+                        ctx.remove_registry_value(sub.handler)?;
+                        if let Some(filter) = sub.filter {
+                            ctx.remove_registry_value(filter)?;
+                        }
+                        Ok(true)
+                    },
+                }
+            })?)?;
+
+            ctx.globals().set("events", events)
+        })?;
+
+        Ok(bus)
+    }
+
+    /// Calls every handler subscribed to `event`, each with its own table
+    /// built by `payload`, so that no handler sees what another did to it.
+    ///
+    /// Each handler runs under its own sandbox limits, and one failing
+    /// doesn't stop the rest; their errors are collected and returned.
+    /// Handlers subscribed during dispatch wait for the next event, while
+    /// handlers unsubscribed during dispatch are skipped.
+    pub fn emit<F>(&self, lua: &rlua::Lua, event: &str, payload: F) -> Vec<HandlerError>
+    where
+        F: for<'lua> Fn(rlua::Context<'lua>) -> rlua::Result<rlua::Table<'lua>>,
+    {
+        self.subscribers(event).into_iter()
+            .filter_map(|id| {
+                sandbox::run_limited(lua, sandbox::Limits::default(), |ctx| self.call(ctx, id, &payload))
+                    .err()
+                    .map(|error| HandlerError { subscription: id, event: event.to_owned(), error })
+            })
+            .collect()
+    }
+
+    /// A lua function `emit(event, fields)` for events which happen in lua,
+    /// like the test harness's `world`. It dispatches as `emit` does, giving
+    /// each handler its own copy of `fields`, and returns a list of the
+    /// handlers' errors.
+    ///
+    /// Handlers run under their own instruction budgets, within the memory
+    /// cap of whatever called `emit`.
+    pub fn lua_emitter<'lua>(&self, ctx: rlua::Context<'lua>) -> rlua::Result<rlua::Function<'lua>> {
+        let bus = self.clone();
+        ctx.create_function(move |ctx, (event, fields): (String, rlua::Table)| {
+            let errors = ctx.create_table()?;
+            for id in bus.subscribers(&event) {
+                let payload = |ctx| copy_table(ctx, fields.clone(), 0);
+                let result = sandbox::limit_instructions(ctx, sandbox::Limits::default(), |ctx| bus.call(ctx, id, &payload));
+                if let Err(error) = result {
+                    let error = HandlerError { subscription: id, event: event.clone(), error };
+                    errors.set(errors.raw_len() + 1, error.to_string())?;
+                }
+            }
+            Ok(errors)
+        })
+    }
+
+    /// Everything subscribed to `event` right now, in the order to call them.
+    fn subscribers(&self, event: &str) -> Vec<u64> {
+        self.subs.lock().list.iter()
+            .filter(|sub| sub.event == event)
+            .map(|sub| sub.id)
+            .collect()
+    }
+
+    /// Calls subscription `id`'s handler with a table from `payload`, if
+    /// it's still subscribed and its filter lets the table through.
+    fn call<'lua, F>(&self, ctx: rlua::Context<'lua>, id: u64, payload: &F) -> rlua::Result<()>
+    where
+        F: Fn(rlua::Context<'lua>) -> rlua::Result<rlua::Table<'lua>>,
+    {
+        let (handler, filter) = {
+            let subs = self.subs.lock();
+            let sub = match subs.list.iter().find(|sub| sub.id == id) {
+                Some(sub) => sub,
+                None => return Ok(()),
+            };
+            let handler: rlua::Function = ctx.registry_value(&sub.handler)?;
+            let filter = match &sub.filter {
+                Some(filter) => ctx.registry_value::<rlua::Value>(filter)?,
+                None => rlua::Nil,
+            };
+            (handler, filter)
+        };
+
+        let payload = payload(ctx)?;
+
+        if !matches_filter(filter, &payload)? {
+            return Ok(());
+        }
+
+        handler.call::<_, ()>(payload)
+    }
+}
+
+
+/// How deeply nested a table passed to a lua `emit` can be.
+const MAX_PAYLOAD_DEPTH: usize = 8;
+
+/// A copy of `table`, and of any tables in it, without their metatables.
+fn copy_table<'lua>(ctx: rlua::Context<'lua>, table: rlua::Table<'lua>, depth: usize) -> rlua::Result<rlua::Table<'lua>> {
+    if depth >= MAX_PAYLOAD_DEPTH {
+        return Err(rlua::Error::RuntimeError(format!(
+            "event fields nest more than {} tables deep", MAX_PAYLOAD_DEPTH
+        )));
+    }
+
+    let copy = ctx.create_table()?;
+    for pair in table.pairs::<rlua::Value, rlua::Value>() {
+        let (key, value) = pair?;
+        let value = match value {
+            rlua::Value::Table(table) => rlua::Value::Table(copy_table(ctx, table, depth + 1)?),
+            value => value,
+        };
+        copy.raw_set(key, value)?;
+    }
+    Ok(copy)
+}
+
+
+fn matches_filter<'lua>(filter: rlua::Value<'lua>, payload: &rlua::Table<'lua>) -> rlua::Result<bool> {
+    use rlua::Value::*;
+
+    let fields = match filter {
+        Nil => return Ok(true),
+        Function(filter) => return filter.call(payload.clone()),
+        Table(fields) => fields,
+        _ => return Ok(false),
+    };
+
+    for pair in fields.pairs::<rlua::Value, rlua::Value>() {
+        let (key, expected) = pair?;
+        let actual = payload.get::<_, rlua::Value>(key)?;
+
+        let equal = match (&expected, &actual) {
+            (Nil, Nil) => true,
+            (Boolean(a), Boolean(b)) => a == b,
+            (Integer(a), Integer(b)) => a == b,
+            (Number(a), Number(b)) => a == b,
+            (Integer(a), Number(b)) | (Number(b), Integer(a)) => *a as f64 == *b,
+            (String(a), String(b)) => a.as_bytes() == b.as_bytes(),
+            (Nil, _) | (Boolean(_), _) | (Integer(_), _) | (Number(_), _) | (String(_), _) => false,
+            _ => return Err(rlua::Error::RuntimeError(
+                "event filter fields must be nil, booleans, numbers or strings".to_owned()
+            )),
+        };
+
+        if !equal {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,477 @@
+
+use std::path::{Path, PathBuf};
+
+use crate::script::{self, sandbox, loader::ScriptLoader, events::EventBus, effects::Effects, profiler::Profiler};
+
+
+const TESTS_REGISTRY_KEY: &str = "tbs_harness_tests";
+
+/// How many players choices in tests can be asked of.
+const SEATS: usize = 4;
+
+const ASSERT_EQ: &str = r#"
+    local tostring, error = tostring, error
+    return function(actual, expected, message)
+        if actual ~= expected then
+            local prefix = message and (tostring(message) .. ": ") or ""
+            error(prefix .. "expected " .. tostring(expected) .. ", got " .. tostring(actual), 2)
+        end
+    end
+"#;
+
+// A stand-in for the rules engine, just big enough to set up a position
+// and check what card abilities do to it. See `run_file` for how it's used.
+const WORLD: &str = r#"
+    local emit, driver = ...
+    local error, ipairs, pairs, pcall, require, select, tostring = error, ipairs, pairs, pcall, require, select, tostring
+    local setmetatable = setmetatable
+    local remove, insert, concat, unpack = table.remove, table.insert, table.concat, table.unpack
+
+    local World = {}
+    World.__index = World
+
+    local function module_name(name)
+        return "cards." .. name:lower():gsub("[^%w]+", "_")
+    end
+
+    -- Turns a list of card names into a zone of cards.
+    local function zone(names, owner)
+        local cards = {}
+        for i, name in ipairs(names or {}) do
+            cards[i] = { name = name, owner = owner }
+        end
+        return cards
+    end
+
+    -- Tells subscribers about `event`, failing if any of them did.
+    local function announce(event, fields)
+        local errors = emit(event, fields)
+        if #errors > 0 then
+            error(concat(errors, "\n"), 0)
+        end
+    end
+
+    local function setup(fixture)
+        local world = setmetatable({
+            cards = fixture.cards or {},
+            active = fixture.active or 1,
+            players = {},
+            pending = nil,
+        }, World)
+
+        for i, player in ipairs(fixture.players or {}) do
+            local resources = {}
+            for k, v in pairs(player.resources or {}) do resources[k] = v end
+            world.players[i] = {
+                deck = zone(player.deck, i),
+                hand = zone(player.hand, i),
+                board = zone(player.board, i),
+                graveyard = zone(player.graveyard, i),
+                resources = resources,
+            }
+        end
+
+        return world
+    end
+
+    function World:player(i)
+        return self.players[i] or error("there's no player " .. tostring(i), 2)
+    end
+
+    -- A card's definition, from the fixture or else from `assets/scripts/cards/`.
+    function World:card(name)
+        return self.cards[name] or require(module_name(name))
+    end
+
+    function World:find(player, zone_name, name)
+        local cards = self:player(player)[zone_name] or error("there's no zone " .. tostring(zone_name), 2)
+        for i, card in ipairs(cards) do
+            if card.name == name then return card, i end
+        end
+        return nil
+    end
+
+    function World:count(player, zone_name, name)
+        local n = 0
+        for _, card in ipairs(self:player(player)[zone_name]) do
+            if name == nil or card.name == name then n = n + 1 end
+        end
+        return n
+    end
+
+    -- Moves `card` from wherever it is to the end of `player`'s `zone_name`.
+    -- A card going from a board to a graveyard is a unit dying.
+    function World:move(card, player, zone_name)
+        local to = self:player(player)[zone_name] or error("there's no zone " .. tostring(zone_name), 2)
+        local died = false
+        for _, p in ipairs(self.players) do
+            for _, from in pairs({ p.deck, p.hand, p.board, p.graveyard }) do
+                for i, c in ipairs(from) do
+                    if c == card then
+                        remove(from, i)
+                        died = from == p.board and zone_name == "graveyard"
+                    end
+                end
+            end
+        end
+        insert(to, card)
+        if died then
+            announce("unit_died", { player = card.owner, card = card.name })
+        end
+    end
+
+    function World:draw(player, n)
+        local p = self:player(player)
+        for _ = 1, n or 1 do
+            local card = remove(p.deck, 1) or error("player " .. player .. " has no cards left to draw", 2)
+            insert(p.hand, card)
+        end
+    end
+
+    function World:pay(player, resource, n)
+        local p = self:player(player)
+        local have = p.resources[resource] or 0
+        if have < n then
+            error("player " .. player .. " has " .. have .. " " .. resource .. ", not " .. n, 2)
+        end
+        p.resources[resource] = have - n
+    end
+
+    -- Runs an ability as an effect, which suspends at any choice it asks for.
+    local function run(world, ability, ...)
+        if world.pending then
+            error("an ability is still waiting on a choice", 3)
+        end
+        local n, args = select("#", ...), { ... }
+        world.pending = driver.start(function() return ability(unpack(args, 1, n)) end)
+    end
+
+    -- Moves `name` from `player`'s hand to the board, and runs its `on_play`.
+    function World:play(player, name, ...)
+        local card = self:find(player, "hand", name) or error(name .. " isn't in player " .. player .. "'s hand", 2)
+        self:move(card, player, "board")
+        announce("card_played", { player = player, card = name })
+        local on_play = self:card(name).on_play
+        if on_play then
+            run(self, on_play, self, player, card, ...)
+        end
+        return card
+    end
+
+    -- Runs ability `ability` of `name`, which is on `player`'s board.
+    function World:activate(player, name, ability, ...)
+        local card = self:find(player, "board", name) or error(name .. " isn't on player " .. player .. "'s board", 2)
+        local f = self:card(name)[ability] or error(name .. " has no ability " .. tostring(ability), 2)
+        run(self, f, self, player, card, ...)
+        return card
+    end
+
+    -- The choice the running ability is waiting on, if any, as players see it.
+    function World:choice()
+        return self.pending and driver.request(self.pending)
+    end
+
+    -- Answers the waiting choice with the (1-based) positions of the options picked.
+    function World:answer(picks)
+        local id = self.pending or error("no ability is waiting on a choice", 2)
+        local ok, next_id = pcall(driver.answer, id, picks)
+        if not ok then
+            -- The ability failed, rather than the answer being refused.
+            if not driver.request(id) then
+                self.pending = nil
+            end
+            error(next_id, 0)
+        end
+        self.pending = next_id
+    end
+
+    return { setup = setup }
+"#;
+
+
+/// What the world runs abilities with: `start(f)` runs `f` as an effect,
+/// `answer(id, picks)` answers choice `id` with 1-based positions, and both
+/// return the id of the choice the effect waits on next, if any.
+/// `request(id)` describes choice `id`, if anything is waiting on it.
+fn world_driver<'lua>(ctx: rlua::Context<'lua>, effects: &Effects) -> rlua::Result<rlua::Table<'lua>> {
+    let driver = ctx.create_table()?;
+
+    let start = effects.clone();
+    driver.set("start", ctx.create_function(move |ctx, f: rlua::Function| start.start_in(ctx, f))?)?;
+
+    let answer = effects.clone();
+    driver.set("answer", ctx.create_function(move |ctx, (id, picks): (u64, Vec<usize>)| {
+        let picks = picks.into_iter()
+            .map(|pick| pick.checked_sub(1))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| rlua::Error::RuntimeError("options are numbered from 1".to_owned()))?;
+        answer.answer_in(ctx, id, &picks)
+    })?)?;
+
+    let request = effects.clone();
+    driver.set("request", ctx.create_function(move |ctx, id: u64| {
+        let request = match request.pending().into_iter().find(|&(pending, _)| pending == id) {
+            Some((_, request)) => request,
+            None => return Ok(None),
+        };
+
+        let table = ctx.create_table()?;
+        table.set("prompt", request.prompt)?;
+        table.set("options", request.options)?;
+        table.set("min", request.min)?;
+        table.set("max", request.max)?;
+        table.set("player", request.player.map(|player| player + 1))?;
+        table.set("reaction", request.reaction)?;
+        Ok(Some(table))
+    })?)?;
+
+    Ok(driver)
+}
+
+
+/// The outcome of one `test` in a test file.
+pub struct TestResult {
+    pub file: PathBuf,
+    pub name: String,
+    pub error: Option<rlua::Error>,
+}
+
+
+/// Every `.lua` file directly under `dir`, in name order.
+fn test_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    files.retain(|path| path.extension().map_or(false, |ext| ext == "lua"));
+    files.sort();
+    Ok(files)
+}
+
+
+/// Runs one lua test file in a fresh sandboxed state, with no window or GPU.
+///
+/// The file registers its tests with `test(name, fn)`, and they run one at a
+/// time once the whole file has loaded, each under its own sandbox limits:
+///
+/// ```lua
+/// test("unsubscribing twice is harmless", function()
+///     local id = events.subscribe("turn_start", function() end)
+///     assert_eq(events.unsubscribe(id), true)
+///     assert_eq(events.unsubscribe(id), false, "second unsubscribe")
+/// end)
+/// ```
+///
+/// `require`, `events` and `effects.choose` work as they do in game. The
+/// world runs abilities as effects, through `script::effects::Effects`, and
+/// emits `card_played` and `unit_died` events as the game would.
+///
+/// `world.setup` builds a position to test card abilities against, with
+/// each player's `deck`, `hand`, `board` and `graveyard` given as lists of
+/// card names. Cards' abilities come from the fixture's `cards`, or else
+/// from `assets/scripts/cards/`:
+///
+/// ```lua
+/// test("raiders make you discard", function()
+///     local w = world.setup {
+///         cards = { Raider = { on_play = function(w, player, card)
+///             w:pay(player, "mana", 2)
+///             local picked = effects.choose { options = w:player(2).hand }
+///             w:move(picked[1], 2, "graveyard")
+///         end } },
+///         players = {
+///             { hand = { "Raider" }, resources = { mana = 2 } },
+///             { hand = { "Forest", "Wall" } },
+///         },
+///     }
+///     w:play(1, "Raider")
+///     w:answer { 2 }
+///     assert_eq(w:count(2, "graveyard", "Wall"), 1)
+/// end)
+/// ```
+pub fn run_file(path: &Path, profiler: Option<&mut Profiler>) -> anyhow::Result<Vec<TestResult>> {
+    let source = std::fs::read_to_string(path)?;
+
+    let lua = sandbox::new_state()?;
+    ScriptLoader::install(&lua)?;
+    let events = EventBus::install(&lua)?;
+    let effects = Effects::install(&lua, SEATS)?;
+
+    if let Some(profiler) = profiler {
+        profiler.start(&lua)?;
+    }
+
+    lua.context(|ctx| -> rlua::Result<()> {
+        ctx.set_named_registry_value(TESTS_REGISTRY_KEY, ctx.create_table()?)?;
+
+        ctx.globals().set("test", ctx.create_function(|ctx, (name, f): (String, rlua::Function)| {
+            let tests: rlua::Table = ctx.named_registry_value(TESTS_REGISTRY_KEY)?;
+            let test = ctx.create_table()?;
+            test.set("name", name)?;
+            test.set("run", f)?;
+            tests.set(tests.raw_len() + 1, test)
+        })?)?;
+
+        let assert_eq: rlua::Function = ctx.load(ASSERT_EQ)
+            .set_name("=harness")?
+            .eval()?;
+        ctx.globals().set("assert_eq", assert_eq)?;
+
+        let world: rlua::Table = ctx.load(WORLD)
+            .set_name("=world")?
+            .call((events.lua_emitter(ctx)?, world_driver(ctx, &effects)?))?;
+        ctx.globals().set("world", world)
+    })?;
+
+    let chunk_name = format!("@{}", path.display());
+    sandbox::run_limited(&lua, sandbox::Limits::default(), |ctx| {
+        ctx.load(&source).set_name(&chunk_name)?.exec()
+    })?;
+
+    let test_ct = lua.context(|ctx| -> rlua::Result<i64> {
+        let tests: rlua::Table = ctx.named_registry_value(TESTS_REGISTRY_KEY)?;
+        Ok(tests.raw_len())
+    })?;
+
+    let mut results = Vec::new();
+
+    for i in 1..=test_ct {
+        let mut name = format!("#{}", i);
+
+        let result = sandbox::run_limited(&lua, sandbox::Limits::default(), |ctx| {
+            let tests: rlua::Table = ctx.named_registry_value(TESTS_REGISTRY_KEY)?;
+            let test: rlua::Table = tests.get(i)?;
+            name = test.get("name")?;
+            test.get::<_, rlua::Function>("run")?.call::<_, ()>(())
+        });
+
+        results.push(TestResult {
+            file: path.to_owned(),
+            name,
+            error: result.err(),
+        });
+    }
+
+    Ok(results)
+}
+
+
+/// How many of the most expensive functions `--profile` lists.
+const PROFILE_ROWS: usize = 20;
+
+
+/// Entry point for `tbs-tcg test-scripts [--profile] [paths...]`.
+///
+/// Runs every test file given, or every file in any directory given,
+/// defaulting to `assets/tests/`. Returns the process exit code.
+///
+/// With `--profile`, the costliest lua functions across the whole run are listed afterwards.
+pub fn run_cli(args: impl Iterator<Item = String>) -> i32 {
+    let mut profiler = None;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--profile" => profiler = Some(Profiler::new()),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        paths.push(PathBuf::from("assets/tests"));
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            match test_files(&path) {
+                Ok(found) => files.extend(found),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    return 2;
+                },
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    let mut passed = 0;
+    let mut failed = 0;
+
+    for file in files {
+        let results = match run_file(&file, profiler.as_mut()) {
+            Ok(results) => results,
+            Err(e) => {
+                let e = match e.downcast_ref::<rlua::Error>() {
+                    Some(e) => script::describe_error(e),
+                    None => e.to_string(),
+                };
+                println!("{}: failed to load\n{}\n", file.display(), e);
+                failed += 1;
+                continue;
+            },
+        };
+
+        for result in results {
+            match result.error {
+                None => {
+                    println!("{}: {} ... ok", file.display(), result.name);
+                    passed += 1;
+                },
+                Some(e) => {
+                    println!(
+                        "{}: {} ... FAILED\n{}\n",
+                        file.display(), result.name, script::describe_error(&e),
+                    );
+                    failed += 1;
+                },
+            }
+        }
+    }
+
+    if let Some(profiler) = profiler {
+        let profile = profiler.profile();
+        let mut functions = profile.functions().collect::<Vec<_>>();
+        functions.sort_by_key(|(_, cost)| std::cmp::Reverse(cost.self_time));
+
+        println!("\n{:>10} {:>10} {:>12} {:>12}  function", "calls", "self ms", "total ms", "instructions");
+        for (function, cost) in functions.into_iter().take(PROFILE_ROWS) {
+            println!(
+                "{:>10} {:>10.3} {:>12.3} {:>12}  {}",
+                cost.calls,
+                cost.self_time.as_secs_f64() * 1000.0,
+                cost.total_time.as_secs_f64() * 1000.0,
+                cost.instructions,
+                function,
+            );
+        }
+    }
+
+    println!("\n{} passed, {} failed", passed, failed);
+
+    if failed == 0 { 0 } else { 1 }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lua_tests_pass() {
+        let mut failures = Vec::new();
+
+        for file in test_files(Path::new("assets/tests")).unwrap() {
+            let results = run_file(&file, None)
+                .unwrap_or_else(|e| panic!("{} failed to load: {}", file.display(), e));
+
+            for result in results {
+                if let Some(e) = result.error {
+                    failures.push(format!("{}: {}\n{}", file.display(), result.name, script::describe_error(&e)));
+                }
+            }
+        }
+
+        assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+    }
+}
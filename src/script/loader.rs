@@ -0,0 +1,338 @@
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use parking_lot::Mutex;
+
+use crate::script::{self, sandbox};
+
+
+const LOADED_REGISTRY_KEY: &str = "tbs_loaded_modules";
+
+/// How often the loader checks loaded modules for changes on disk.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/// Whether `name` is `.`-separated segments of `[A-Za-z0-9_]`, which keeps
+/// `require` inside `assets/scripts` (no `..`, no absolute paths).
+fn is_module_name(name: &str) -> bool {
+    name.split('.').all(|segment| {
+        !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+fn load_script_file(name: &str) -> anyhow::Result<(PathBuf, String)> {
+    anyhow::ensure!(is_module_name(name), "invalid module name");
+
+    let mut path = PathBuf::from("assets/scripts");
+    path.push(name.replace('.', "/"));
+    path.set_extension("lua");
+
+    let path = path.canonicalize()?;
+    let source = std::fs::read_to_string(&path)?;
+
+    Ok((path, source))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+
+struct ModuleFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Default)]
+struct LoaderState {
+    files: HashMap<String, ModuleFile>,
+    // module name -> names of the modules which `require`d it.
+    dependents: HashMap<String, HashSet<String>>,
+    // modules currently partway through loading, innermost last.
+    loading: Vec<String>,
+}
+
+impl LoaderState {
+    /// Modules whose files have changed, or gone, since last checked.
+    /// Each change is only reported once, so a deleted file isn't
+    /// reloaded (and failed) over and over until it's back.
+    fn changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (name, file) in &mut self.files {
+            let modified = modified_time(&file.path);
+            if modified != file.modified {
+                file.modified = modified;
+                changed.push(name.clone());
+            }
+        }
+        changed
+    }
+
+    /// Forgets what `name` requires, before it's run again to find out anew.
+    fn forget_requires(&mut self, name: &str) {
+        for dependents in self.dependents.values_mut() {
+            dependents.remove(name);
+        }
+        self.dependents.retain(|_, dependents| !dependents.is_empty());
+    }
+
+    /// `names`, plus everything which (transitively) requires any of them.
+    fn with_dependents(&self, names: Vec<String>) -> HashSet<String> {
+        let mut stale = HashSet::new();
+        let mut queue = names;
+        while let Some(name) = queue.pop() {
+            if let Some(dependents) = self.dependents.get(&name) {
+                queue.extend(dependents.iter().cloned());
+            }
+            stale.insert(name);
+        }
+        stale
+    }
+}
+
+
+/// A `require` for modules under `assets/scripts/`, which reloads
+/// modules (and everything depending on them) when their files change.
+///
+/// `require "cards.common"` resolves to `assets/scripts/cards/common.lua`.
+#[derive(Clone)]
+pub struct ScriptLoader {
+    state: Arc<Mutex<LoaderState>>,
+    last_poll: Arc<Mutex<Instant>>,
+}
+
+
+impl ScriptLoader {
+
+    /// Installs `require` as a global of `lua`.
+    pub fn install(lua: &rlua::Lua) -> rlua::Result<Self> {
+        let loader = Self {
+            state: Arc::new(Mutex::new(LoaderState::default())),
+            last_poll: Arc::new(Mutex::new(Instant::now())),
+        };
+
+        lua.context(|ctx| -> rlua::Result<()> {
+            ctx.set_named_registry_value(LOADED_REGISTRY_KEY, ctx.create_table()?)?;
+
+            let state = loader.state.clone();
+            let require = ctx.create_function(move |ctx, name: String| {
+                require_module(ctx, &state, name, None)
+            })?;
+
+            ctx.globals().set("require", require)
+        })?;
+
+        Ok(loader)
+    }
+
+    /// Reloads any module whose file has changed since it was loaded,
+    /// re-running every module which depends on it as well.
+    ///
+    /// Cheap to call every frame; the files are only checked once per `POLL_INTERVAL`.
+    pub fn reload_changed(&self, lua: &rlua::Lua) {
+        {
+            let mut last_poll = self.last_poll.lock();
+            if last_poll.elapsed() < POLL_INTERVAL {
+                return;
+            }
+            *last_poll = Instant::now();
+        }
+
+        let stale = {
+            let mut state = self.state.lock();
+            let changed = state.changed();
+
+            if changed.is_empty() {
+                return;
+            }
+
+            state.with_dependents(changed)
+        };
+
+        self.reload(lua, stale);
+    }
+
+    fn reload(&self, lua: &rlua::Lua, modules: HashSet<String>) {
+        let unloaded = lua.context(|ctx| -> rlua::Result<()> {
+            let loaded: rlua::Table = ctx.named_registry_value(LOADED_REGISTRY_KEY)?;
+            for name in &modules {
+                loaded.set(name.as_str(), rlua::Nil)?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = unloaded {
+            script::report_error(lua, &script::describe_error(&e));
+            return;
+        }
+
+        for name in modules {
+            eprintln!("Reloading lua module: {}", name);
+            // Requiring a dependent pulls in the fresh copy of whatever it
+            // depends on, so the order these run in doesn't matter.
+            let reloaded = sandbox::run_limited(lua, sandbox::Limits::default(), |ctx| {
+                require_module(ctx, &self.state, name.clone(), None).map(|_| ())
+            });
+
+            if let Err(e) = reloaded {
+                script::report_error(lua, &format!(
+                    "failed to reload module '{}': {}", name, script::describe_error(&e)
+                ));
+            }
+        }
+    }
+}
+
+
+/// Each module sees the globals through an environment of its own, whose
+/// `require` records the module as depending on whatever it requires, even
+/// from functions run long after it was loaded, e.g. a card's abilities.
+fn module_env<'lua>(ctx: rlua::Context<'lua>, state: &Arc<Mutex<LoaderState>>, name: &str)
+    -> rlua::Result<rlua::Table<'lua>>
+{
+    let globals = ctx.globals();
+    let meta = ctx.create_table()?;
+    meta.set("__index", globals.clone())?;
+    meta.set("__newindex", globals)?;
+
+    let env = ctx.create_table()?;
+    env.set_metatable(Some(meta));
+
+    let state = state.clone();
+    let requirer = name.to_owned();
+    env.set("require", ctx.create_function(move |ctx, name: String| {
+        require_module(ctx, &state, name, Some(&requirer))
+    })?)?;
+
+    Ok(env)
+}
+
+/// Loads `name`, or returns it as already loaded, noting that `requirer`
+/// (a module name, if it's a module asking) depends on it.
+fn require_module<'lua>(
+    ctx: rlua::Context<'lua>,
+    state: &Arc<Mutex<LoaderState>>,
+    name: String,
+    requirer: Option<&str>,
+) -> rlua::Result<rlua::Value<'lua>> {
+    if !is_module_name(&name) {
+        return Err(rlua::Error::RuntimeError(format!(
+            "invalid module name '{}': expected names like 'cards.goblin'", name
+        )));
+    }
+
+    // WTF: Never hold the state lock while running lua,
+    // since the module being loaded will likely `require` more.
+    {
+        let mut state = state.lock();
+
+        if state.loading.contains(&name) {
+            return Err(rlua::Error::RuntimeError(format!(
+                "circular require of module '{}' (via {})", name, state.loading.join(" -> ")
+            )));
+        }
+
+        if let Some(requirer) = requirer {
+            state.dependents.entry(name.clone()).or_default().insert(requirer.to_owned());
+        }
+    }
+
+    let loaded: rlua::Table = ctx.named_registry_value(LOADED_REGISTRY_KEY)?;
+
+    match loaded.get::<_, rlua::Value>(name.as_str())? {
+        rlua::Value::Nil => (),
+        module => return Ok(module),
+    }
+
+    let (path, source) = load_script_file(&name).map_err(|e| {
+        rlua::Error::RuntimeError(format!("module '{}' not found: {}", name, e))
+    })?;
+
+    let chunk_name = format!("@{}", path.display());
+
+    {
+        let mut state = state.lock();
+        state.files.insert(name.clone(), ModuleFile {
+            modified: modified_time(&path),
+            path,
+        });
+        state.forget_requires(&name);
+        state.loading.push(name.clone());
+    }
+
+    let result = module_env(ctx, state, &name)
+        .and_then(|env| ctx.load(&source).set_name(&chunk_name)?.set_environment(env))
+        .and_then(|chunk| chunk.call::<_, rlua::Value>(name.as_str()));
+
+    state.lock().loading.pop();
+
+    let module = match result? {
+        rlua::Value::Nil => rlua::Value::Boolean(true),
+        module => module,
+    };
+
+    loaded.set(name.as_str(), module.clone())?;
+    Ok(module)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_names_stay_inside_the_scripts_directory() {
+        for name in &["cards", "cards.goblin", "lib.util_2"] {
+            assert!(is_module_name(name), "{}", name);
+        }
+        for name in &["", ".", "..", "cards..goblin", ".cards", "cards.", "../main", "/etc/passwd", "cards/goblin", "cards.gob lin"] {
+            assert!(!is_module_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn reloads_follow_current_requires() {
+        let mut state = LoaderState::default();
+        let requires = |state: &mut LoaderState, module: &str, deps: &[&str]| {
+            state.forget_requires(module);
+            for dep in deps {
+                state.dependents.entry(dep.to_string()).or_default().insert(module.to_owned());
+            }
+        };
+
+        requires(&mut state, "cards.goblin", &["lib.util", "lib.combat"]);
+        requires(&mut state, "lib.combat", &["lib.util"]);
+
+        let stale = state.with_dependents(vec!["lib.util".to_owned()]);
+        assert_eq!(stale, ["lib.util", "lib.combat", "cards.goblin"].iter().map(|s| s.to_string()).collect::<HashSet<_>>());
+
+        // The goblin stops requiring anything, so changes no longer reach it.
+        requires(&mut state, "cards.goblin", &[]);
+        let stale = state.with_dependents(vec!["lib.util".to_owned()]);
+        assert_eq!(stale, ["lib.util", "lib.combat"].iter().map(|s| s.to_string()).collect::<HashSet<_>>());
+    }
+
+    #[test]
+    fn deleted_files_are_reported_once() {
+        let dir = std::env::temp_dir().join(format!("loader-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("util.lua");
+        std::fs::write(&path, "return {}").unwrap();
+
+        let mut state = LoaderState::default();
+        state.files.insert("lib.util".to_owned(), ModuleFile { modified: modified_time(&path), path: path.clone() });
+        assert!(state.changed().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(state.changed(), vec!["lib.util".to_owned()]);
+        assert!(state.changed().is_empty());
+
+        std::fs::write(&path, "return {}").unwrap();
+        assert_eq!(state.changed(), vec!["lib.util".to_owned()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
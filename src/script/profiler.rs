@@ -0,0 +1,475 @@
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::script::sandbox;
+
+
+/// What a function (or a whole script) has cost since recording began.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Cost {
+    pub calls: u64,
+    /// Time spent in the function and everything it called,
+    /// counting recursive calls only once.
+    pub total_time: Duration,
+    /// Time spent in the function itself, including in any
+    /// library functions it called.
+    pub self_time: Duration,
+    /// Instructions run in the function itself, to within `PROFILE_INTERVAL`.
+    pub instructions: u64,
+}
+
+/// A function's name, and where it was defined.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FunctionId {
+    pub name: String,
+    pub script: String,
+    pub line: i32,
+}
+
+impl std::fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.line > 0 {
+            write!(f, "{} ({}:{})", self.name, self.script, self.line)
+        } else {
+            write!(f, "{} ({})", self.name, self.script)
+        }
+    }
+}
+
+
+struct Frame {
+    function: FunctionId,
+    entered: Instant,
+    in_callees: Duration,
+}
+
+/// The call stack of the main thread, or of one coroutine.
+#[derive(Default)]
+struct Thread {
+    frames: Vec<Frame>,
+    // When the thread last started running, and when it last stopped.
+    resumed: Option<Instant>,
+    suspended: Option<Instant>,
+}
+
+/// The id `running` starts with.
+const MAIN_THREAD: u64 = 0;
+
+pub struct Profile {
+    functions: HashMap<FunctionId, Cost>,
+    // Time spent in each script and whatever it called, counting
+    // calls between functions of the same script only once.
+    script_time: HashMap<String, Duration>,
+    threads: HashMap<u64, Thread>,
+    // The threads currently running, each resumed by the one before it.
+    running: Vec<u64>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            functions: HashMap::new(),
+            script_time: HashMap::new(),
+            threads: HashMap::new(),
+            running: vec![MAIN_THREAD],
+        }
+    }
+}
+
+impl Profile {
+    /// Costs per function.
+    pub fn functions(&self) -> impl Iterator<Item = (&FunctionId, &Cost)> {
+        self.functions.iter()
+    }
+
+    /// Costs per script (which for cards means per card), summing the
+    /// self costs of every function defined in it.
+    pub fn scripts(&self) -> HashMap<&str, Cost> {
+        let mut scripts = HashMap::<&str, Cost>::new();
+        for (function, cost) in &self.functions {
+            let script = scripts.entry(function.script.as_str()).or_default();
+            script.calls += cost.calls;
+            script.self_time += cost.self_time;
+            script.instructions += cost.instructions;
+        }
+        for (script, cost) in &mut scripts {
+            cost.total_time = self.script_time.get(*script).copied().unwrap_or_default();
+        }
+        scripts
+    }
+
+    fn stack(&mut self) -> &mut Vec<Frame> {
+        let running = *self.running.last().unwrap();
+        &mut self.threads.entry(running).or_default().frames
+    }
+
+    /// Whether any frame still open in a running thread matches `f`.
+    fn any_open(&self, f: impl Fn(&Frame) -> bool) -> bool {
+        self.running.iter()
+            .filter_map(|id| self.threads.get(id))
+            .any(|thread| thread.frames.iter().any(&f))
+    }
+
+    fn enter(&mut self, function: FunctionId) {
+        self.functions.entry(function.clone()).or_default().calls += 1;
+        self.stack().push(Frame {
+            function,
+            entered: Instant::now(),
+            in_callees: Duration::default(),
+        });
+    }
+
+    fn exit(&mut self) {
+        let frame = match self.stack().pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let elapsed = frame.entered.elapsed();
+        let recursive = self.any_open(|outer| outer.function == frame.function);
+        let nested = self.any_open(|outer| outer.function.script == frame.function.script);
+
+        if !nested {
+            *self.script_time.entry(frame.function.script.clone()).or_default() += elapsed;
+        }
+
+        let cost = self.functions.entry(frame.function).or_default();
+        cost.self_time += elapsed.checked_sub(frame.in_callees).unwrap_or_default();
+        if !recursive {
+            cost.total_time += elapsed;
+        }
+
+        if let Some(caller) = self.stack().last_mut() {
+            caller.in_callees += elapsed;
+        }
+    }
+
+    fn count(&mut self, instructions: u64) {
+        let function = match self.stack().last() {
+            Some(frame) => frame.function.clone(),
+            None => return,
+        };
+        self.functions.entry(function).or_default().instructions += instructions;
+    }
+
+    /// Switches to coroutine `id`'s stack, as it starts or resumes.
+    fn resume_thread(&mut self, id: u64) {
+        let now = Instant::now();
+        let thread = self.threads.entry(id).or_default();
+
+        // Time spent suspended doesn't count towards the coroutine's frames.
+        if let Some(suspended) = thread.suspended.take() {
+            let paused = now - suspended;
+            for frame in &mut thread.frames {
+                frame.entered += paused;
+            }
+        }
+        thread.resumed = Some(now);
+
+        self.running.push(id);
+    }
+
+    /// Switches back to whichever thread resumed the running coroutine,
+    /// as it yields or finishes.
+    fn suspend_thread(&mut self) {
+        if self.running.len() <= 1 {
+            return;
+        }
+
+        let now = Instant::now();
+        let id = self.running.pop().unwrap();
+        let thread = match self.threads.get_mut(&id) {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        let ran = thread.resumed.take().map(|resumed| now - resumed).unwrap_or_default();
+        if thread.frames.is_empty() {
+            self.threads.remove(&id);
+        } else {
+            thread.suspended = Some(now);
+        }
+
+        // The coroutine's frames account for its own time, so it's
+        // taken out of the self time of whatever resumed it.
+        if let Some(caller) = self.stack().last_mut() {
+            caller.in_callees += ran;
+        }
+    }
+
+    /// Closes every frame in every running thread, leaving only the main thread running.
+    fn unwind(&mut self) {
+        loop {
+            if !self.stack().is_empty() {
+                self.exit();
+            } else if self.running.len() > 1 {
+                let id = self.running.pop().unwrap();
+                self.threads.remove(&id);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+
+/// Which function `debug` is about, unless it's one the profile leaves out:
+/// functions written in C (or Rust) count towards their lua callers, and the
+/// sandbox's coroutine bookkeeping isn't worth showing.
+fn profiled(debug: &rlua::Debug) -> Option<FunctionId> {
+    let source = debug.source();
+    if source.what == Some(b"C") || source.source == Some(sandbox::COROUTINES_CHUNK.as_bytes()) {
+        return None;
+    }
+    Some(function_id(debug))
+}
+
+fn function_id(debug: &rlua::Debug) -> FunctionId {
+    let names = debug.names();
+    let source = debug.source();
+
+    let text = |bytes: Option<&[u8]>, default: &str| match bytes {
+        Some(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        None => default.to_owned(),
+    };
+
+    FunctionId {
+        name: text(names.name, "?"),
+        script: text(source.short_src, "?"),
+        line: source.line_defined,
+    }
+}
+
+
+/// The receiving end of the sandbox hook while profiling.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<Profile>>);
+
+impl Recorder {
+    pub(crate) fn record(&self, debug: &rlua::Debug, instructions: u64) {
+        let mut profile = self.0.lock();
+        match debug.event() {
+            rlua::DebugEvent::Call => if let Some(function) = profiled(debug) {
+                profile.enter(function);
+            },
+            // The caller's frame is replaced, and will never return.
+            rlua::DebugEvent::TailCall => {
+                profile.exit();
+                if let Some(function) = profiled(debug) {
+                    profile.enter(function);
+                }
+            },
+            rlua::DebugEvent::Ret => if profiled(debug).is_some() {
+                profile.exit();
+            },
+            rlua::DebugEvent::Count => profile.count(instructions),
+            _ => (),
+        }
+    }
+
+    /// Called as coroutine `id` starts or resumes.
+    pub(crate) fn resume_thread(&self, id: u64) {
+        self.0.lock().resume_thread(id);
+    }
+
+    /// Called as the running coroutine yields or finishes.
+    pub(crate) fn suspend_thread(&self) {
+        self.0.lock().suspend_thread();
+    }
+
+    /// Closes every frame still open, as when lua has unwound past them.
+    /// Suspended coroutines keep theirs, for when they resume.
+    pub(crate) fn unwind(&self) {
+        self.0.lock().unwind();
+    }
+}
+
+
+/// Records the time and instructions every lua function takes,
+/// across as many runs (and lua states) as it's left recording for.
+///
+/// Each coroutine gets its own call stack, and the time it spends suspended
+/// isn't counted. That relies on coroutines being made with the sandbox's
+/// `coroutine` functions, or `sandbox::create_thread`.
+///
+/// Profiling slows scripts down considerably, so it's off until `start`ed.
+#[derive(Clone)]
+pub struct Profiler {
+    recorder: Recorder,
+    recording: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            recorder: Recorder(Arc::new(Mutex::new(Profile::default()))),
+            recording: false,
+        }
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn start(&mut self, lua: &rlua::Lua) -> rlua::Result<()> {
+        sandbox::set_profiler(lua, Some(self.recorder.clone()))?;
+        self.recording = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self, lua: &rlua::Lua) -> rlua::Result<()> {
+        sandbox::set_profiler(lua, None)?;
+        self.recording = false;
+        self.recorder.unwind();
+        Ok(())
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn reset(&self) {
+        let mut profile = self.recorder.0.lock();
+        profile.functions.clear();
+        profile.script_time.clear();
+    }
+
+    pub fn profile(&self) -> MutexGuard<Profile> {
+        self.recorder.0.lock()
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, script: &str) -> FunctionId {
+        FunctionId { name: name.to_owned(), script: script.to_owned(), line: 1 }
+    }
+
+    fn frames(profile: &Profile, thread: u64) -> Vec<&str> {
+        profile.threads.get(&thread)
+            .map(|thread| thread.frames.iter().map(|frame| frame.function.name.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn coroutines_keep_their_own_stacks() {
+        let mut profile = Profile::default();
+
+        profile.enter(function("update", "main"));
+        profile.resume_thread(1);
+        profile.enter(function("effect", "card"));
+        profile.suspend_thread();
+
+        // Returning from the main thread mustn't close the suspended effect.
+        profile.exit();
+        assert_eq!(frames(&profile, MAIN_THREAD), Vec::<&str>::new());
+        assert_eq!(frames(&profile, 1), vec!["effect"]);
+
+        profile.resume_thread(1);
+        profile.exit();
+        profile.suspend_thread();
+
+        assert!(!profile.threads.contains_key(&1), "finished coroutines are forgotten");
+        assert_eq!(profile.running, vec![MAIN_THREAD]);
+        assert_eq!(profile.functions[&function("effect", "card")].calls, 1);
+        assert_eq!(profile.functions[&function("update", "main")].calls, 1);
+    }
+
+    #[test]
+    fn unwinding_spares_suspended_coroutines() {
+        let mut profile = Profile::default();
+
+        profile.resume_thread(1);
+        profile.enter(function("waiting", "card"));
+        profile.suspend_thread();
+
+        profile.enter(function("update", "main"));
+        profile.resume_thread(2);
+        profile.enter(function("failing", "card"));
+        profile.unwind();
+
+        assert_eq!(profile.running, vec![MAIN_THREAD]);
+        assert_eq!(frames(&profile, MAIN_THREAD), Vec::<&str>::new());
+        assert_eq!(frames(&profile, 1), vec!["waiting"]);
+        assert!(!profile.threads.contains_key(&2));
+    }
+
+    #[test]
+    fn leaving_the_main_thread_is_ignored() {
+        let mut profile = Profile::default();
+        profile.suspend_thread();
+        assert_eq!(profile.running, vec![MAIN_THREAD]);
+    }
+
+    #[test]
+    fn script_totals_count_nested_calls_once() {
+        let mut profile = Profile::default();
+
+        profile.enter(function("a", "one"));
+        profile.enter(function("b", "one"));
+        profile.enter(function("c", "two"));
+        profile.exit();
+        profile.exit();
+        profile.exit();
+
+        let scripts = profile.scripts();
+        let total = |name| profile.functions[&function(name, if name == "c" { "two" } else { "one" })].total_time;
+
+        assert_eq!(scripts["one"].total_time, total("a"));
+        assert_eq!(scripts["two"].total_time, total("c"));
+        assert_eq!(scripts["one"].calls, 2);
+        assert!(scripts["one"].total_time >= scripts["one"].self_time);
+    }
+
+    #[test]
+    fn follows_lua_coroutines() {
+        let lua = sandbox::new_state().unwrap();
+        let mut profiler = Profiler::new();
+        profiler.start(&lua).unwrap();
+
+        sandbox::run_limited(&lua, sandbox::Limits::default(), |ctx| {
+            ctx.load(r#"
+                local function inner() return coroutine.yield(1) end
+                local co = coroutine.create(function() inner(); inner() end)
+                local function drive()
+                    while coroutine.status(co) ~= "dead" do
+                        assert(coroutine.resume(co))
+                    end
+                end
+                drive()
+
+                local f = coroutine.wrap(function(x) return x + coroutine.yield(x) end)
+                assert(f(1) == 1)
+                assert(f(2) == 3)
+
+                -- Errors inside coroutines still reach whoever resumed them.
+                local ok, err = coroutine.resume(coroutine.create(function() error("boom", 0) end))
+                assert(not ok and err == "boom")
+            "#).set_name("=test")?.exec()
+        }).unwrap();
+
+        profiler.stop(&lua).unwrap();
+
+        let profile = profiler.profile();
+        let calls = |name: &str| profile.functions()
+            .find(|(function, _)| function.name == name)
+            .map(|(_, cost)| cost.calls);
+
+        assert_eq!(calls("inner"), Some(2));
+        assert_eq!(calls("drive"), Some(1));
+        assert_eq!(profile.running, vec![MAIN_THREAD]);
+        assert!(profile.threads.values().all(|thread| thread.frames.is_empty()));
+        assert!(profile.functions().all(|(function, _)| function.script != "coroutines"));
+    }
+}
@@ -0,0 +1,408 @@
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use rlua::StdLib;
+
+use crate::script::profiler::Recorder;
+
+
+/// How often (in VM instructions) the instruction budget is checked.
+const CHECK_INTERVAL: u32 = 1000;
+
+/// How often the budget is checked while profiling, which is
+/// also how finely instructions are attributed to functions.
+const PROFILE_INTERVAL: u32 = 100;
+
+const BUDGET_REGISTRY_KEY: &str = "tbs_sandbox_budget";
+const COROUTINE_BODY_REGISTRY_KEY: &str = "tbs_sandbox_coroutine_body";
+
+/// Ids for coroutines, unique across every lua state, since one profiler can
+/// watch several. The main thread is 0.
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The only parts of the lua standard library scripts get to see.
+/// Notably absent are `io`, `os`, `package` and `debug`.
+fn sandbox_libs() -> StdLib {
+    StdLib::BASE
+        | StdLib::COROUTINE
+        | StdLib::TABLE
+        | StdLib::STRING
+        | StdLib::UTF8
+        | StdLib::MATH
+}
+
+// `load` would otherwise happily accept precompiled bytecode,
+// which can corrupt the VM. Force it to only ever accept text.
+// WTF: `load` treats an `env` of nil as an empty environment, rather than
+// the globals, so it's only passed along when the caller passed one.
+const TEXT_ONLY_LOAD: &str = r#"
+    local load, select = load, select
+    return function(chunk, chunkname, mode, ...)
+        if select('#', ...) > 0 then
+            return load(chunk, chunkname, "t", (...))
+        end
+        return load(chunk, chunkname, "t")
+    end
+"#;
+
+// WTF: Lua runs `__gc` finalizers with hooks disabled, so one that never
+// returns would hang the game with no budget to stop it. Finalizers are
+// only ever set up by `setmetatable`, so that's where they're turned away.
+const NO_FINALIZERS: &str = r#"
+    local setmetatable, rawget, type, error = setmetatable, rawget, type, error
+    return function(t, mt)
+        if type(mt) == "table" and rawget(mt, "__gc") ~= nil then
+            error("scripts can't set __gc metamethods", 2)
+        end
+        return setmetatable(t, mt)
+    end
+"#;
+
+
+// Tells the profiler (if there is one) whenever a coroutine starts, resumes,
+// yields or finishes, so that it can keep a call stack for each. Takes the
+// functions to tell it with, and returns `body`, which wraps a coroutine's
+// function so that it reports starting and finishing.
+// WTF: Nothing here may tail call another lua function, since the profiler
+// would take that for one of the script's own functions being replaced.
+pub(crate) const COROUTINES_CHUNK: &str = "=coroutines";
+const COROUTINES: &str = r#"
+    local enter, leave, new_id = ...
+    local create, wrap, yield = coroutine.create, coroutine.wrap, coroutine.yield
+    local running, isyieldable = coroutine.running, coroutine.isyieldable
+    local pcall, error, type, setmetatable = pcall, error, type, setmetatable
+    local pack, unpack = table.pack, table.unpack
+
+    local ids = setmetatable({}, { __mode = "k" })
+
+    local function id()
+        local co = running()
+        local id = ids[co]
+        if not id then
+            id = new_id()
+            ids[co] = id
+        end
+        return id
+    end
+
+    local function body(f)
+        return function(...)
+            enter(id())
+            local results = pack(pcall(f, ...))
+            leave()
+            if not results[1] then
+                error(results[2], 0)
+            end
+            return unpack(results, 2, results.n)
+        end
+    end
+
+    function coroutine.create(f)
+        if type(f) ~= "function" then
+            return create(f)
+        end
+        return create(body(f))
+    end
+
+    function coroutine.wrap(f)
+        if type(f) ~= "function" then
+            return wrap(f)
+        end
+        return wrap(body(f))
+    end
+
+    function coroutine.yield(...)
+        if not isyieldable() then
+            return yield(...)
+        end
+        leave()
+        local results = pack(yield(...))
+        enter(id())
+        return unpack(results, 1, results.n)
+    end
+
+    return body
+"#;
+
+
+/// Resource limits for one run of a script.
+#[derive(Copy, Clone, Debug)]
+pub struct Limits {
+    /// Roughly how many VM instructions may run, give or take `CHECK_INTERVAL`.
+    pub instructions: u64,
+    /// How many bytes the lua heap may grow by.
+    pub memory: usize,
+}
+
+impl Limits {
+    /// Generous limits for code typed into the console by hand.
+    pub const CONSOLE: Limits = Limits {
+        instructions: 500_000_000,
+        memory: 256 * 1024 * 1024,
+    };
+}
+
+impl Default for Limits {
+    /// Limits suitable for a single card ability or scenario callback.
+    fn default() -> Self {
+        Self {
+            instructions: 10_000_000,
+            memory: 16 * 1024 * 1024,
+        }
+    }
+}
+
+
+// Instructions left to the currently running script, and where to
+// send profiling data if anywhere.
+// Kept in the registry so that anything holding the `Lua` can find it.
+#[derive(Clone)]
+struct Budget {
+    remaining: Arc<AtomicU64>,
+    /// How many `run_limited` calls are in progress. Nothing is charged
+    /// to `remaining` while there are none.
+    depth: Arc<AtomicUsize>,
+    recorder: Option<Recorder>,
+}
+
+impl rlua::UserData for Budget {}
+
+
+/// Creates a lua state with only the sandboxed libraries loaded,
+/// and an instruction counting hook installed.
+///
+/// Outside of `run_limited`, the instruction budget is unlimited.
+pub fn new_state() -> rlua::Result<rlua::Lua> {
+    let lua = rlua::Lua::new_with(sandbox_libs());
+    let budget = Budget {
+        remaining: Arc::new(AtomicU64::new(u64::MAX)),
+        depth: Arc::new(AtomicUsize::new(0)),
+        recorder: None,
+    };
+
+    lua.context(|ctx| -> rlua::Result<()> {
+        let globals = ctx.globals();
+
+        globals.set("dofile", rlua::Nil)?;
+        globals.set("loadfile", rlua::Nil)?;
+
+        let text_only_load: rlua::Function = ctx.load(TEXT_ONLY_LOAD)
+            .set_name("=sandbox")?
+            .eval()?;
+        globals.set("load", text_only_load)?;
+
+        let no_finalizers: rlua::Function = ctx.load(NO_FINALIZERS)
+            .set_name("=sandbox")?
+            .eval()?;
+        globals.set("setmetatable", no_finalizers)?;
+
+        ctx.set_named_registry_value(BUDGET_REGISTRY_KEY, budget.clone())?;
+
+        let enter = ctx.create_function(|ctx, id: u64| {
+            if let Some(recorder) = recorder(ctx)? {
+                recorder.resume_thread(id);
+            }
+            Ok(())
+        })?;
+        let leave = ctx.create_function(|ctx, ()| {
+            if let Some(recorder) = recorder(ctx)? {
+                recorder.suspend_thread();
+            }
+            Ok(())
+        })?;
+        let new_id = ctx.create_function(|_, ()| {
+            Ok(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed))
+        })?;
+
+        let body: rlua::Function = ctx.load(COROUTINES)
+            .set_name(COROUTINES_CHUNK)?
+            .call((enter, leave, new_id))?;
+        ctx.set_named_registry_value(COROUTINE_BODY_REGISTRY_KEY, body)
+    })?;
+
+    install_hook(&lua, budget);
+
+    Ok(lua)
+}
+
+
+fn budget(ctx: rlua::Context) -> rlua::Result<Budget> {
+    let budget: rlua::AnyUserData = ctx.named_registry_value(BUDGET_REGISTRY_KEY)?;
+    let budget = budget.borrow::<Budget>()?;
+    Ok(budget.clone())
+}
+
+fn recorder(ctx: rlua::Context) -> rlua::Result<Option<Recorder>> {
+    Ok(budget(ctx)?.recorder)
+}
+
+
+/// Makes a coroutine running `f`, as `coroutine.create` would from lua.
+/// Use this rather than `Context::create_thread`, which the profiler can't follow.
+pub fn create_thread<'lua>(ctx: rlua::Context<'lua>, f: rlua::Function<'lua>) -> rlua::Result<rlua::Thread<'lua>> {
+    let body: rlua::Function = ctx.named_registry_value(COROUTINE_BODY_REGISTRY_KEY)?;
+    ctx.create_thread(body.call(f)?)
+}
+
+
+fn install_hook(lua: &rlua::Lua, budget: Budget) {
+    let recorder = budget.recorder;
+    let interval = match recorder {
+        Some(_) => PROFILE_INTERVAL,
+        None => CHECK_INTERVAL,
+    };
+
+    let remaining = budget.remaining;
+    let depth = budget.depth;
+    lua.set_hook(
+        rlua::HookTriggers {
+            every_nth_instruction: Some(interval),
+            on_calls: recorder.is_some(),
+            on_returns: recorder.is_some(),
+            ..Default::default()
+        },
+        move |_ctx, debug| {
+            let step = interval as u64;
+
+            if let Some(recorder) = &recorder {
+                recorder.record(&debug, step);
+                match debug.event() {
+                    rlua::DebugEvent::Count => (),
+                    _ => return Ok(()),
+                }
+            }
+
+            if depth.load(Ordering::Relaxed) == 0 {
+                return Ok(());
+            }
+
+            // WTF: Once exhausted, the budget stays exhausted, so a script
+            // which `pcall`s its way past the first error just fails again
+            // at the next check rather than looping forever.
+            match remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(step)) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(rlua::Error::RuntimeError(
+                    "script exceeded its instruction budget".to_owned()
+                )),
+            }
+        },
+    );
+}
+
+
+/// Starts (or with `None`, stops) passing every call, return and
+/// instruction count in `lua` on to `recorder`.
+pub fn set_profiler(lua: &rlua::Lua, recorder: Option<Recorder>) -> rlua::Result<()> {
+    let budget = lua.context(|ctx| -> rlua::Result<Budget> {
+        let budget: rlua::AnyUserData = ctx.named_registry_value(BUDGET_REGISTRY_KEY)?;
+        let budget = Budget {
+            recorder,
+            ..budget.borrow::<Budget>()?.clone()
+        };
+        ctx.set_named_registry_value(BUDGET_REGISTRY_KEY, budget.clone())?;
+        Ok(budget)
+    })?;
+
+    install_hook(lua, budget);
+    Ok(())
+}
+
+
+/// Runs `f` with the instruction budget and memory cap of `limits` in force,
+/// lifting them again afterwards whether or not `f` succeeded.
+///
+/// Nested calls can only ever tighten the instruction budget, and whatever
+/// they use is charged to the enclosing call. The memory cap of the
+/// outermost call stays in force throughout.
+pub fn run_limited<R, F>(lua: &rlua::Lua, limits: Limits, f: F) -> rlua::Result<R>
+where
+    F: FnOnce(rlua::Context) -> rlua::Result<R>,
+{
+    let budget = lua.context(budget)?;
+
+    let outermost = budget.depth.load(Ordering::Relaxed) == 0;
+    if outermost {
+        lua.set_memory_limit(Some(lua.used_memory() + limits.memory));
+    }
+
+    let result = lua.context(|ctx| limit_instructions(ctx, limits, f));
+
+    if outermost {
+        lua.set_memory_limit(None);
+        // Errors unwind lua's stack without any return hooks firing.
+        if let Some(recorder) = &budget.recorder {
+            recorder.unwind();
+        }
+    }
+
+    result
+}
+
+/// The instruction budget half of `run_limited`, for use from inside lua,
+/// e.g. in a callback, where there's no `Lua` to set a memory cap on.
+/// Whatever memory cap is in force stays as it is.
+pub fn limit_instructions<'lua, R, F>(ctx: rlua::Context<'lua>, limits: Limits, f: F) -> rlua::Result<R>
+where
+    F: FnOnce(rlua::Context<'lua>) -> rlua::Result<R>,
+{
+    let budget = budget(ctx)?;
+
+    let outermost = budget.depth.fetch_add(1, Ordering::Relaxed) == 0;
+    let outer = budget.remaining.load(Ordering::Relaxed);
+    let granted = if outermost { limits.instructions } else { limits.instructions.min(outer) };
+    budget.remaining.store(granted, Ordering::Relaxed);
+
+    let result = f(ctx);
+    budget.depth.fetch_sub(1, Ordering::Relaxed);
+
+    if outermost {
+        budget.remaining.store(u64::MAX, Ordering::Relaxed);
+    } else {
+        let used = granted - budget.remaining.load(Ordering::Relaxed);
+        budget.remaining.store(outer.saturating_sub(used), Ordering::Relaxed);
+    }
+
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runaway(lua: &rlua::Lua) -> rlua::Result<()> {
+        run_limited(lua, Limits { instructions: 100_000, memory: 1024 * 1024 }, |ctx| {
+            ctx.load("while true do end").exec()
+        })
+    }
+
+    #[test]
+    fn unlimited_code_leaves_the_budget_alone() {
+        let lua = new_state().unwrap();
+
+        for _ in 0 .. 3 {
+            lua.context(|ctx| ctx.load("for i = 1, 100000 do end").exec()).unwrap();
+            assert!(runaway(&lua).is_err());
+        }
+
+        // The memory cap is set by, and lifted after, the outermost call.
+        let grow = "local t = {} for i = 1, 1e6 do t[i] = i end";
+        let capped = run_limited(&lua, Limits { instructions: u64::MAX, memory: 64 * 1024 }, |ctx| {
+            ctx.load(grow).exec()
+        });
+        assert!(capped.is_err());
+        lua.context(|ctx| ctx.load(grow).exec()).unwrap();
+    }
+
+    #[test]
+    fn refuses_finalizers() {
+        let lua = new_state().unwrap();
+        let result = run_limited(&lua, Limits::default(), |ctx| {
+            ctx.load("setmetatable({}, { __gc = function() while true do end end })").exec()
+        });
+        assert!(result.is_err());
+
+        lua.context(|ctx| ctx.load("collectgarbage()").exec()).unwrap();
+    }
+}
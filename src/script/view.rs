@@ -0,0 +1,173 @@
+
+use std::sync::Arc;
+
+use nalgebra_glm as glm;
+use parking_lot::Mutex;
+
+use crate::render::camera::GimbalCamera;
+
+
+/// A change to the view requested from lua, for the main loop to apply.
+#[derive(Copy, Clone, Debug)]
+pub enum ViewCommand {
+    SetPosition(glm::Vec3),
+    SetTarget(glm::Vec3),
+    Orbit { lr: f32, ud: f32 },
+    Zoom(f32),
+    SetRenderScale(f64),
+}
+
+impl ViewCommand {
+    /// Applies the command to `camera`, or returns the requested
+    /// render scale if that's what the command was for.
+    pub fn apply(self, camera: &mut GimbalCamera) -> Option<f64> {
+        match self {
+            ViewCommand::SetPosition(pos) => if !camera.set_position(pos) {
+                eprintln!("view.set_position: {:?} is the camera's target, ignoring", pos);
+            },
+            ViewCommand::SetTarget(center) => if !camera.set_target(center) {
+                eprintln!("view.set_target: {:?} is the camera's position, ignoring", center);
+            },
+            ViewCommand::Orbit { lr, ud } => {
+                camera.gimbal_lr(lr);
+                camera.gimbal_ud(ud);
+            },
+            ViewCommand::Zoom(ratio) => camera.zoom(ratio),
+            ViewCommand::SetRenderScale(scale) => return Some(scale),
+        }
+        None
+    }
+}
+
+
+#[derive(Copy, Clone, Debug)]
+struct ViewSnapshot {
+    position: glm::Vec3,
+    target: glm::Vec3,
+    render_scale: f64,
+}
+
+/// The view as lua reads it back, as of the last call to `update`.
+#[derive(Clone)]
+pub struct ViewState(Arc<Mutex<ViewSnapshot>>);
+
+impl ViewState {
+    pub fn update(&self, camera: &GimbalCamera, render_scale: f64) {
+        *self.0.lock() = ViewSnapshot {
+            position: camera.position(),
+            target: camera.target(),
+            render_scale,
+        };
+    }
+}
+
+
+type Sender = Arc<Mutex<Box<dyn Fn(ViewCommand) + Send>>>;
+
+
+/// Installs the global `view` table, whose functions pass `ViewCommand`s to `send`.
+///
+/// Commands take effect whenever `send`'s receiver gets around to them, so
+/// reading the view back straight after changing it gives the old values.
+pub fn install<F>(lua: &rlua::Lua, camera: &GimbalCamera, render_scale: f64, send: F)
+    -> rlua::Result<ViewState>
+where
+    F: Fn(ViewCommand) + Send + 'static,
+{
+    let state = ViewState(Arc::new(Mutex::new(ViewSnapshot {
+        position: camera.position(),
+        target: camera.target(),
+        render_scale,
+    })));
+
+    // WTF: Event loop proxies aren't `Sync`, so they can't be shared
+    // between several lua functions without a lock around them.
+    let send: Sender = Arc::new(Mutex::new(Box::new(send)));
+
+    lua.context(|ctx| -> rlua::Result<()> {
+        let view = ctx.create_table()?;
+
+        // The camera ignores positions on top of its target anyway, but
+        // whatever's obviously so is reported back to the script here.
+        let tx = send.clone();
+        let snapshot = state.0.clone();
+        view.set("set_position", ctx.create_function(move |_, (x, y, z): (f32, f32, f32)| {
+            let pos = point("set_position", x, y, z)?;
+            apart("set_position", pos, snapshot.lock().target)?;
+            (tx.lock())(ViewCommand::SetPosition(pos));
+            Ok(())
+        })?)?;
+
+        let tx = send.clone();
+        let snapshot = state.0.clone();
+        view.set("set_target", ctx.create_function(move |_, (x, y, z): (f32, f32, f32)| {
+            let center = point("set_target", x, y, z)?;
+            apart("set_target", center, snapshot.lock().position)?;
+            (tx.lock())(ViewCommand::SetTarget(center));
+            Ok(())
+        })?)?;
+
+        let tx = send.clone();
+        view.set("orbit", ctx.create_function(move |_, (lr, ud): (f32, Option<f32>)| {
+            (tx.lock())(ViewCommand::Orbit { lr, ud: ud.unwrap_or(0.0) });
+            Ok(())
+        })?)?;
+
+        let tx = send.clone();
+        view.set("zoom", ctx.create_function(move |_, ratio: f32| {
+            (tx.lock())(ViewCommand::Zoom(ratio));
+            Ok(())
+        })?)?;
+
+        let tx = send.clone();
+        view.set("set_render_scale", ctx.create_function(move |_, scale: f64| {
+            if scale.is_nan() || scale <= 0.0 || scale > 4.0 {
+                return Err(rlua::Error::RuntimeError(format!(
+                    "render scale must be in (0, 4], got {}", scale
+                )));
+            }
+            (tx.lock())(ViewCommand::SetRenderScale(scale));
+            Ok(())
+        })?)?;
+
+        let snapshot = state.0.clone();
+        view.set("position", ctx.create_function(move |_, ()| {
+            let pos = snapshot.lock().position;
+            Ok((pos.x, pos.y, pos.z))
+        })?)?;
+
+        let snapshot = state.0.clone();
+        view.set("target", ctx.create_function(move |_, ()| {
+            let center = snapshot.lock().target;
+            Ok((center.x, center.y, center.z))
+        })?)?;
+
+        let snapshot = state.0.clone();
+        view.set("render_scale", ctx.create_function(move |_, ()| {
+            Ok(snapshot.lock().render_scale)
+        })?)?;
+
+        ctx.globals().set("view", view)
+    })?;
+
+    Ok(state)
+}
+
+fn point(function: &str, x: f32, y: f32, z: f32) -> rlua::Result<glm::Vec3> {
+    if !(x.is_finite() && y.is_finite() && z.is_finite()) {
+        return Err(rlua::Error::RuntimeError(format!(
+            "view.{}: ({}, {}, {}) isn't a point", function, x, y, z
+        )));
+    }
+    Ok(glm::vec3(x, y, z))
+}
+
+/// Fails unless `a` and `b` are far enough apart for the camera to look from one to the other.
+fn apart(function: &str, a: glm::Vec3, b: glm::Vec3) -> rlua::Result<()> {
+    if glm::distance(&a, &b) <= 1e-6 {
+        return Err(rlua::Error::RuntimeError(format!(
+            "view.{}: the camera's position and target can't be the same point", function
+        )));
+    }
+    Ok(())
+}